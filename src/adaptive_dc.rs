@@ -1,11 +1,23 @@
 use std;
 use na::*;
 use math::*;
-use renderer::*;
+use density::*;
+use mesh::*;
+use qef::{Qef, CollapseSettings};
+use marching_cubes;
+use marching_cubes::{edge_axes, TestShapes};
+use uniform_manifold_dc;
+use uniform_manifold_dc::{corner_points, vertex_num_table, sample_surface_intersection, sample_normal};
 use alga::general::*;
 use std::rc::Rc;
 use std::cell::RefCell;
- use num::PrimInt;
+use std::collections::HashMap;
+
+//octree dual contouring (Ju et al. 2002)
+//the leaves of the octree are the cells of the grid, a cell the surface passes through gets the qef of the hermite data on its edges
+//and one vertex at the minimizer, then 8 leaves collapse into their parent while the error of their summed qef stays below the tolerance
+//and the signs inside the parent say the surface in it keeps its shape
+//quads are made on the minimal edges with a sign change by the cellProc/faceProc/edgeProc recursion, so leaves of any size meet without cracks
 
 pub struct Octree<T> {
     parent : Option<Rc<RefCell<Octree<T>>>>,
@@ -19,21 +31,26 @@ pub struct Octree<T> {
 
 pub struct NodeId(usize);
 
+type Node = Rc<RefCell<Octree<VoxelData<f32>>>>;
 
 
 #[derive(Clone, Debug)]
 pub struct VoxelData<T : Copy + PartialEq + std::fmt::Debug> where T : 'static{
-    pub densities : [T;8],
-    //pub planes : Vec<Plane<T>>,
+    pub densities : [T;8], //at the corners of the node (see `corner_points`)
+    pub qef : Option<Qef>, //of the hermite data of the cells in the node, `None` if the surface does not pass through it
+    pub normal : Vector3<f32>, //sum of the hermite normals
+    pub vertex : Vector3<f32>, //minimizer of `qef`, kept inside the node
 }
 
 impl<T : Real + Identity<Additive>> VoxelData<T>{
+    //bit `i` set - corner `i` is inside
+    pub fn config(&self) -> usize{
+        (0..8).filter(|&i| self.densities[i] < T::zero()).fold(0, |config, i| config | (1 << i))
+    }
+
     pub fn is_heterogeneous(&self) -> bool{
-        let sign = self.densities[0];
-        for i in 1..8{
-            if self.densities[i] * sign <= T::zero() {return true}
-        }
-        false
+        let config = self.config();
+        config != 0 && config != 255
     }
 }
 
@@ -51,133 +68,417 @@ pub fn count_leading_non_zeros_oct(x : usize, acc : usize) -> usize {
     }
 }
 
-pub fn index_to_point3(offset : Vector3<f32>, index : usize, a : f32, level : usize, max_level : usize) -> Vector3<f32>{
-    use std::string::ToString;
-    if index == 0 {return offset}
-    else{
-        let cur = index & 0b1111;  //100010100010001000001
-        //println!("max {}, lev {}, index {:b}, cur {:b}", max_level, level, index, cur);
-        let t = 2.pow( (max_level - level) as u32 );
-        let new_i = index >> 4;
-         match cur{
-            1 => index_to_point3(offset + Vector3::new(0.0, 0.0, a * t as f32), new_i, a, level - 1, max_level),
-            2 => index_to_point3(offset + Vector3::new(a * t as f32, 0.0, a * t as f32), new_i, a, level - 1, max_level),
-            3 => index_to_point3(offset + Vector3::new(a * t as f32, 0.0, 0.0), new_i, a, level - 1, max_level),
-            4 => index_to_point3(offset, new_i, a, level - 1, max_level),
-
-            5 => index_to_point3(offset + Vector3::new(0.0, a * t as f32, a * t as f32), new_i, a, level - 1, max_level),
-            6 => index_to_point3(offset + Vector3::new(a * t as f32, a * t as f32, a * t as f32), new_i, a, level - 1, max_level),
-            7 => index_to_point3(offset + Vector3::new(a * t as f32, a * t as f32, 0.0), new_i, a, level - 1, max_level),
-            8 => index_to_point3(offset + Vector3::new(0.0, a * t as f32, 0.0) ,new_i, a, level - 1, max_level),
-            _ => panic!(String::from("impossible happend: ") + &cur.to_string())
+//offset of child `index` (1 - 8) in its parent, in sizes of the child
+const CHILD_OFFSETS : [[usize;3];8] = [[0, 0, 1], [1, 0, 1], [1, 0, 0], [0, 0, 0], [0, 1, 1], [1, 1, 1], [1, 1, 0], [0, 1, 0]];
+
+//min corner of the node in leaves, the lowest 4 bits of `index` pick the child at level 1, the next 4 at level 2 and so on
+pub fn index_to_cell(index : usize, level : usize, max_level : usize) -> [usize;3]{
+    let mut cell = [0;3];
+    for lev in 1..level + 1{
+        let child = (index >> (4 * (lev - 1))) & 0b1111;
+        let t = 1 << (max_level - lev);
+        for axis in 0..3{
+            cell[axis] += CHILD_OFFSETS[child - 1][axis] * t;
         }
     }
-    
+    cell
 }
 
-//index is in range: 1-8 including both
-pub fn fill_in_leaves(f : &DenFn3<f32>, offset : Vector3<f32>, a : f32, size : usize, lev : usize, index : usize, parent : Rc<RefCell<Octree<VoxelData<f32>>>>,
-    debug_render_lines : &mut RendererVertFragDef){
-    
-    let max_level = (size as f32).log2() as usize; //TODO test
+pub fn index_to_point3(offset : Vector3<f32>, index : usize, a : f32, level : usize, max_level : usize) -> Vector3<f32>{
+    let cell = index_to_cell(index, level, max_level);
+    offset + Vector3::new(cell[0] as f32, cell[1] as f32, cell[2] as f32) * a
+}
 
-    if lev < max_level{
+//number of levels encoded in `index`, 0 for the root
+fn level_of(index : usize) -> usize{
+    let (mut index, mut level) = (index, 0);
+    while index != 0{
+        index >>= 4;
+        level += 1;
+    }
+    level
+}
 
-        let parent_index = parent.borrow().index;
+//what the recursion of `fill_in_leaves` shares
+struct Builder<'f, D : Density3 + 'f>{
+    f : &'f D,
+    offset : Vector3<f32>,
+    a : f32,
+    size : usize,
+    max_level : usize,
+    accuracy : usize,
+    tolerance : f32,
+    corners : Vec<[usize;3]>,
+    edges : Vec<(usize, usize, usize)>, //see `edge_axes`
+    planes : HashMap<usize, Plane<f32>>, //hermite data of each grid edge, shared by the cells around it
+    debug : Option<DebugGeometry>,
+}
 
-        let this_index = {
-            let c = 4 * (lev - 1);
-                //println!("count leading non zeros {} {}, max level : {}", c, parent_index, max_level);
-            parent_index | (index << c)
-        };
+impl<'f, D : Density3> Builder<'f, D>{
+    fn sample_corners(&self, pos : Vector3<f32>, a : f32) -> VoxelData<f32>{
+        let corners : Vec<Vector3<f32>> = corner_points().iter().map(|c| pos + *c * a).collect();
+        let mut densities = [0.0;8];
+        self.f.density_batch(&corners, &mut densities);
+        VoxelData{densities, qef : None, normal : Vector3::new(0.0, 0.0, 0.0), vertex : pos + Vector3::new(a, a, a) / 2.0}
+    }
 
-        //println!("this index1 {:b} at level {}", this_index, lev);
+    //surface point and normal on the grid edge from `cell` + `lo` to `cell` + `hi`
+    fn plane(&mut self, cell : [usize;3], lo : usize, hi : usize, axis : usize) -> Plane<f32>{
+        let n = self.size + 1;
+        let (p, q) = (self.corners[lo], self.corners[hi]);
+        let key = 3 * (((cell[2] + p[2]) * n + cell[1] + p[1]) * n + cell[0] + p[0]) + axis;
+        if let Some(plane) = self.planes.get(&key){
+            return *plane;
+        }
 
-        let tree = Rc::new(RefCell::new(Octree{parent : Some(parent.clone()), children : None, status : 0, index : this_index, data : None}));
+        let point = |c : [usize;3]| self.offset + Vector3::new((cell[0] + c[0]) as f32, (cell[1] + c[1]) as f32, (cell[2] + c[2]) as f32) * self.a;
+        let point = sample_surface_intersection(&Line3{start : point(p), end : point(q)}, self.accuracy, self.f);
+        let plane = Plane{point, normal : sample_normal(&point, self.a / 100.0, self.f)};
+        self.planes.insert(key, plane);
+        plane
+    }
 
-        let mut all_homo = true;
-        for i in 0..8{
-            fill_in_leaves(f, offset, a, size, lev + 1, i + 1, tree.clone(), debug_render_lines);
-            if all_homo{
-                let maybe_children = &tree.borrow().children;
-                let children =  &maybe_children.as_ref().unwrap();
-                let child_i = children[i].as_ref().unwrap();
-                let data_for_child = &child_i.borrow().data; //child `i` is present at this moment
-                if data_for_child.is_some() && data_for_child.as_ref().unwrap().is_heterogeneous(){all_homo = false;}
+    //the data `node` has as one leaf, if its children can be replaced by it:
+    //they are leaves with one sheet of the surface each, the node has one sheet too,
+    //the sign in the middle of each edge and face and of the node itself is the sign of one of the node corners around it
+    //and the summed qef has a small error at its minimizer
+    fn collapse(&self, node : &Node, pos : Vector3<f32>, a : f32) -> Option<VoxelData<f32>>{
+        let node = node.borrow();
+        let children = node.children.as_ref().unwrap();
+        let vertex_num = vertex_num_table();
+
+        let mut densities = [[[0.0;3];3];3]; //at the corners of the children
+        let mut qef = Qef::new();
+        let mut normal = Vector3::new(0.0, 0.0, 0.0);
+        let mut crossed = false;
+        for k in 0..8{
+            let child = children[k].as_ref().unwrap().borrow();
+            if child.children.is_some() {return None}
+            let data = child.data.as_ref().unwrap();
+            if vertex_num[data.config()] > 1 {return None}
+
+            let o = CHILD_OFFSETS[k];
+            for (c, corner) in self.corners.iter().enumerate(){
+                densities[o[0] + corner[0]][o[1] + corner[1]][o[2] + corner[2]] = data.densities[c];
+            }
+            if let Some(ref child_qef) = data.qef{
+                qef.merge(child_qef);
+                normal += data.normal;
+                crossed = true;
             }
         }
 
-        if all_homo{
-            tree.borrow_mut().children = None;
-            tree.borrow_mut().status = 0;
-            tree.borrow_mut().data = None; //unite 8 children into one tree
-        }else{//this is he
-            let pos = index_to_point3(offset, this_index, a, lev, max_level);
-            let a_for_level = 2.pow( (max_level - lev) as u32 ) as f32 * a;
-            add_square3_bounds_color(debug_render_lines, Square3{center : pos + Vector3::new(a_for_level, a_for_level, a_for_level) / 2.0, extent : a_for_level / 2.0}, Vector3::new(1.0,1.0,1.0) );
+        let inside = |p : [usize;3]| densities[p[0]][p[1]][p[2]] < 0.0;
+        for x in 0..3{
+            for y in 0..3{
+                for z in 0..3{
+                    let p = [x, y, z];
+                    let middle : Vec<usize> = (0..3).filter(|&axis| p[axis] == 1).collect();
+                    if middle.is_empty() {continue;}
+                    //node corners around `p`: each middle coordinate goes to one side
+                    let kept = (0..1 << middle.len()).any(|sides : usize|{
+                        let mut q = p;
+                        for (k, &axis) in middle.iter().enumerate(){
+                            q[axis] = 2 * ((sides >> k) & 1);
+                        }
+                        inside(q) == inside(p)
+                    });
+                    if !kept {return None}
+                }
+            }
         }
 
+        let mut data = VoxelData{densities : [0.0;8], qef : None, normal, vertex : pos + Vector3::new(a, a, a) / 2.0};
+        for (c, corner) in self.corners.iter().enumerate(){
+            data.densities[c] = densities[2 * corner[0]][2 * corner[1]][2 * corner[2]];
+        }
+        if vertex_num[data.config()] > 1 {return None}
+
+        if crossed{
+            let vertex = clamp_to_node(qef.solve().0, pos, a);
+            if qef.error(&vertex) > self.tolerance {return None}
+            data.qef = Some(qef);
+            data.vertex = vertex;
+        }
+        Some(data)
+    }
+}
+
+fn clamp_to_node(p : Vector3<f32>, pos : Vector3<f32>, a : f32) -> Vector3<f32>{
+    let mut clamped = p;
+    for axis in 0..3{
+        clamped[axis] = clamped[axis].max(pos[axis]).min(pos[axis] + a);
+    }
+    clamped
+}
+
+//index is in range: 1-8 including both
+//nodes whose children can be one leaf are collapsed, their bounds go to `debug` when it is present and the surface passes through them
+fn fill_in_leaves<'f, D : Density3>(builder : &mut Builder<'f, D>, lev : usize, index : usize, parent : &Node){
+    let this_index = parent.borrow().index | (index << (4 * (lev - 1)));
+    let pos = index_to_point3(builder.offset, this_index, builder.a, lev, builder.max_level);
+    let a_for_level = (1 << (builder.max_level - lev)) as f32 * builder.a;
+
+    let tree = Rc::new(RefCell::new(Octree{parent : Some(parent.clone()), children : None, status : 0, index : this_index, data : None}));
+
+    if lev < builder.max_level{
+        //the whole node is proven to be inside or outside, no need to go down to the leaves
+        let culled = match builder.f.bounds(pos, pos + Vector3::new(a_for_level, a_for_level, a_for_level)){
+            Some((lo, hi)) => lo > 0.0 || hi < 0.0,
+            None => false,
+        };
 
-        if parent.borrow().children.is_none(){
-            let mut n = vec![None;8];
-            n[index-1] = Some(tree);
-            parent.borrow_mut().children = Some(n);
+        if culled{
+            tree.borrow_mut().data = Some(builder.sample_corners(pos, a_for_level));
         }else{
-            parent.borrow_mut().children.as_mut().unwrap()[index-1] = Some(tree)
+            for i in 0..8{
+                fill_in_leaves(builder, lev + 1, i + 1, &tree);
+            }
+            collapse_into(builder, &tree, pos, a_for_level);
         }
+    }else{
+        let mut data = builder.sample_corners(pos, builder.a);
+        if data.is_heterogeneous(){
+            let cell = index_to_cell(this_index, lev, builder.max_level);
+            let mut qef = Qef::new();
+            for e in 0..12{
+                let (lo, hi, axis) = builder.edges[e];
+                if (data.densities[lo] < 0.0) == (data.densities[hi] < 0.0) {continue;}
+                let plane = builder.plane(cell, lo, hi, axis);
+                qef.add(&plane);
+                data.normal += plane.normal;
+            }
+            data.vertex = clamp_to_node(qef.solve().0, pos, builder.a);
+            data.qef = Some(qef);
+        }
+        tree.borrow_mut().data = Some(data);
+    }
+
+    let mut parent = parent.borrow_mut();
+    if parent.children.is_none(){
+        parent.children = Some(vec![None;8]);
+    }
+    parent.children.as_mut().unwrap()[index - 1] = Some(tree);
+    parent.status |= 1 << (index - 1);
+}
 
-        
+fn collapse_into<'f, D : Density3>(builder : &mut Builder<'f, D>, tree : &Node, pos : Vector3<f32>, a : f32){
+    if let Some(data) = builder.collapse(tree, pos, a){
+        if let Some(ref mut debug) = builder.debug{
+            if data.qef.is_some(){
+                debug.add_square3_bounds(Square3{center : pos + Vector3::new(a, a, a) / 2.0, extent : a / 2.0}, Vector3::new(1.0, 1.0, 1.0));
+            }
+        }
+        let mut tree = tree.borrow_mut();
+        tree.children = None; //unite 8 children into one leaf
+        tree.status = 0;
+        tree.data = Some(data);
+    }
+}
 
-        let p_stat = parent.borrow().status;
-        parent.borrow_mut().status = p_stat | (1 << (index-1));
+//`size`^3 leaves with edge `a` from `offset`, `size` must be a power of two
+//with `debug` set, the bounds of every collapsed node the surface passes through are returned too
+pub fn make_tree<D : Density3>(f : &D, offset : Vector3<f32>, a : f32, size : usize, settings : &CollapseSettings, debug : bool) -> (Rc<RefCell<Octree<VoxelData<f32>>>>, Option<DebugGeometry>){
+    assert!(size.is_power_of_two() && size > 1, "the octree needs a power of two grid size, got {}", size);
+    let tree = Rc::new(RefCell::new( Octree{parent : None, children : None, status : 0, index : 0, data : None} ));
+    let mut builder = Builder{f, offset, a, size, max_level : size.trailing_zeros() as usize, accuracy : settings.accuracy, tolerance : settings.error * a * a,
+        corners : corner_points().iter().map(|c| [c.x as usize, c.y as usize, c.z as usize]).collect(), edges : edge_axes(),
+        planes : HashMap::new(), debug : if debug {Some(DebugGeometry::new())} else {None}};
 
+    for i in 0..8{
+        fill_in_leaves(&mut builder, 1, i + 1, &tree);
+    }
+    //the root is never collapsed: a single leaf has no edges inside the grid to put quads on
 
-    }else{
-        
-        let parent_index = parent.borrow().index;
-        let c = 4 * (lev - 1);
-        let this_index = parent_index | (index << c);
-        //println!("this index2 {:b} at level {}", this_index, lev);
-        let pos = index_to_point3(offset, this_index, a, lev, max_level);
+    (tree, builder.debug)
+}
 
-        let d00 = f(Vector3::new(0.0, 0.0, a) + pos);
-        let d01 = f(Vector3::new(a,0.0,a) + pos);
-        let d02 = f(Vector3::new(a,0.0,0.0) + pos);
-        let d03 = f(Vector3::new(0.0,0.0,0.0) + pos);
+//child of `node` at offset `o` (0 or 1 along each axis), a leaf stands for its own children
+fn child(node : &Node, o : [usize;3]) -> Node{
+    match node.borrow().children{
+        Some(ref children) => children[CHILD_OFFSETS.iter().position(|c| *c == o).unwrap()].as_ref().unwrap().clone(),
+        None => node.clone(),
+    }
+}
 
-        let d10 = f(Vector3::new(0.0, a, a) + pos);
-        let d11 = f(Vector3::new(a,a,a) + pos);
-        let d12 = f(Vector3::new(a,a,0.0) + pos);
-        let d13 = f(Vector3::new(0.0,a,0.0) + pos);
+fn is_leaf(node : &Node) -> bool{
+    node.borrow().children.is_none()
+}
 
-        let voxel_data = VoxelData{densities : [d00,d01,d02,d03,  d10,d11,d12,d13]};
+//the 4 nodes around an edge along `axis`, node `du` + 2 * `dv` lies on the `du` side of the edge along `u` = `axis` + 1
+//and on the `dv` side along `v` = `axis` + 2 (0 - the low side)
+fn around<F : Fn(usize, usize) -> Node>(node_at : F) -> [Node;4]{
+    [node_at(0, 0), node_at(1, 0), node_at(0, 1), node_at(1, 1)]
+}
 
-        let this = Octree{parent : Some(parent.clone()), children : None, status : 0, index : this_index, data : Some(voxel_data)};
+struct Contour{
+    mesh : Mesh,
+    vertices : HashMap<usize, u32>, //mesh vertex of each leaf by its index
+    corners : Vec<[usize;3]>,
+}
 
+impl Contour{
+    fn cell_proc(&mut self, node : &Node){
+        if is_leaf(node) {return}
+        for o in CHILD_OFFSETS.iter(){
+            self.cell_proc(&child(node, *o));
+        }
 
-        if parent.borrow().children.is_none(){
-            let mut n = vec![None;8];
-            n[index-1] = Some(Rc::new(RefCell::new(this)));
-            parent.borrow_mut().children = Some(n);
-        }else{
-            parent.borrow_mut().children.as_mut().unwrap()[index-1] = Some(Rc::new(RefCell::new(this)))
+        for axis in 0..3{
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+            //faces between the children on both sides of the middle plane across `axis`
+            for i in 0..2{
+                for j in 0..2{
+                    let side = |s : usize| {let mut o = [0;3]; o[axis] = s; o[u] = i; o[v] = j; o};
+                    self.face_proc(&[child(node, side(0)), child(node, side(1))], axis);
+                }
+            }
+            //edges on the middle line along `axis`
+            for h in 0..2{
+                let nodes = around(|du, dv| {let mut o = [0;3]; o[axis] = h; o[u] = du; o[v] = dv; child(node, o)});
+                self.edge_proc(&nodes, axis);
+            }
         }
+    }
 
+    //`nodes[0]` is below the face across `axis`, `nodes[1]` above it
+    fn face_proc(&mut self, nodes : &[Node;2], axis : usize){
+        if is_leaf(&nodes[0]) && is_leaf(&nodes[1]) {return}
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        for i in 0..2{
+            for j in 0..2{
+                let side = |s : usize| {let mut o = [0;3]; o[axis] = s; o[u] = i; o[v] = j; o};
+                self.face_proc(&[child(&nodes[0], side(1)), child(&nodes[1], side(0))], axis);
+            }
+        }
 
-        let p_stat = parent.borrow().status;
-        parent.borrow_mut().status = p_stat | (1 << (index-1));
-        
+        //edges on the middle lines of the face
+        for &(dir, across) in [(u, v), (v, u)].iter(){
+            let (eu, ev) = ((dir + 1) % 3, (dir + 2) % 3);
+            for h in 0..2{
+                let edge_nodes = around(|du, dv|{
+                    let mut s = [0;3];
+                    s[eu] = du;
+                    s[ev] = dv;
+                    let mut o = [0;3];
+                    o[dir] = h;
+                    o[axis] = 1 - s[axis];
+                    o[across] = s[across];
+                    child(&nodes[s[axis]], o)
+                });
+                self.edge_proc(&edge_nodes, dir);
+            }
+        }
     }
 
-}
+    fn edge_proc(&mut self, nodes : &[Node;4], axis : usize){
+        if nodes.iter().all(is_leaf){
+            return self.process_edge(nodes, axis);
+        }
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        for h in 0..2{
+            let children = around(|du, dv| {let mut o = [0;3]; o[axis] = h; o[u] = 1 - du; o[v] = 1 - dv; child(&nodes[du + 2 * dv], o)});
+            self.edge_proc(&children, axis);
+        }
+    }
 
-pub fn make_tree(f : &DenFn3<f32>, offset : Vector3<f32>, a : f32, size : usize, debug_render_lines : &mut RendererVertFragDef) -> Rc<RefCell<Octree<VoxelData<f32>>>>{
-    let mut tree = Rc::new(RefCell::new( Octree{parent : None, children : None, status : 0, index : 0, data : None} ));
+    //a quad around the edge if it has a sign change, the edge of the smallest node is taken: the edges of the others contain it
+    fn process_edge(&mut self, nodes : &[Node;4], axis : usize){
+        let min = (0..4).max_by_key(|&i| level_of(nodes[i].borrow().index)).unwrap();
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        let mut lo = [0;3];
+        lo[u] = 1 - min % 2;
+        lo[v] = 1 - min / 2;
+        let mut hi = lo;
+        hi[axis] = 1;
+
+        let lo_inside = {
+            let node = nodes[min].borrow();
+            let data = node.data.as_ref().unwrap();
+            let corner = |c : [usize;3]| data.densities[self.corners.iter().position(|x| *x == c).unwrap()];
+            let (d0, d1) = (corner(lo), corner(hi));
+            if (d0 < 0.0) == (d1 < 0.0) {return}
+            d0 < 0.0
+        };
 
-    for i in 0..8{
-        fill_in_leaves(f, offset, a, size, 1, i + 1, tree.clone(), debug_render_lines);
+        let mut quad = [0;4];
+        for (k, &i) in [0, 1, 3, 2].iter().enumerate(){
+            let node = nodes[i].borrow();
+            let data = node.data.as_ref().unwrap();
+            let mesh = &mut self.mesh;
+            quad[k] = *self.vertices.entry(node.index).or_insert_with(|| mesh.push_vertex(data.vertex, data.normal.normalize(), 0));
+        }
+        //counter clockwise around `axis`, facing from the inside corner to the outside one
+        if !lo_inside{
+            quad.swap(1, 3);
+        }
+        //a leaf on both sides of an edge in the middle of its face makes the quad a triangle
+        let distinct = (0..4).filter(|&i| quad[i] != quad[(i + 1) % 4]).count();
+        if distinct == 4{
+            self.mesh.push_bent_quad(quad[0], quad[1], quad[2], quad[3]);
+        }else if distinct == 3{
+            let i = (0..4).find(|&i| quad[i] == quad[(i + 1) % 4]).unwrap();
+            self.mesh.push_triangle(quad[(i + 1) % 4], quad[(i + 2) % 4], quad[(i + 3) % 4]);
+        }
     }
+}
 
-    tree
+//octree dual contouring of `size`^3 cells with edge `a` from `offset`, `size` must be a power of two
+pub fn make_mesh<D : Density3>(f : &D, offset : Vector3<f32>, a : f32, size : usize, settings : &CollapseSettings, debug : bool) -> (Mesh, Option<DebugGeometry>){
+    let (tree, debug) = make_tree(f, offset, a, size, settings, debug);
+    (contour(&tree).mesh, debug)
 }
 
+fn contour(tree : &Node) -> Contour{
+    let mut contour = Contour{mesh : Mesh::new(), vertices : HashMap::new(), corners : corner_points().iter().map(|c| [c.x as usize, c.y as usize, c.z as usize]).collect()};
+    contour.cell_proc(tree);
+    contour
+}
+
+//a sphere, a box and a torus, all closed
+//the flat faces of the box collapse to far fewer triangles than the uniform grid gives,
+//its leaves are of several levels, so `face_proc` and `edge_proc` make polygons between leaves of different levels
+//a node with bounds skips the leaves of the nodes it proves empty or full and must give the same mesh as its plain density
+pub fn test_adc(){
+    let settings = CollapseSettings::new(8);
+    let shapes = TestShapes::new();
+    let (a, size, offset) = (shapes.a, shapes.size, shapes.offset);
+
+    let (mesh, _) = make_mesh(&shapes.sphere(), offset, a, size, &settings, false);
+    println!("adc, sphere: {} vertices, {} triangles", mesh.vertex_count(), mesh.triangle_count());
+    marching_cubes::assert_sphere_mesh(&mesh, shapes.center, shapes.rad, a / 4.0);
+
+    let aabb = shapes.aabb();
+    let (mesh, _) = make_mesh(&aabb, offset, a, size, &settings, false);
+    let (_, uniform, _) = uniform_manifold_dc::construct_grid(&aabb, offset, a, size, settings.accuracy, false);
+    println!("adc, box: {} vertices, {} triangles, umdc: {} triangles", mesh.vertex_count(), mesh.triangle_count(), uniform.triangle_count());
+    assert!(mesh.triangle_count() * 4 < uniform.triangle_count());
+    marching_cubes::assert_box_corners(&mesh, shapes.box_center, shapes.box_extent, a / 4.0);
+
+    let (tree, _) = make_tree(&aabb, offset, a, size, &settings, false);
+    let contour = contour(&tree);
+    let level : HashMap<u32, usize> = contour.vertices.iter().map(|(&index, &vertex)| (vertex, level_of(index))).collect();
+    let mixed = (0..contour.mesh.triangle_count()).filter(|&i|{
+        let levels : Vec<usize> = contour.mesh.indices[3 * i..3 * i + 3].iter().map(|v| level[v]).collect();
+        levels.iter().any(|&l| l != levels[0])
+    }).count();
+    println!("adc, box: {} of {} triangles join leaves of different levels", mixed, contour.mesh.triangle_count());
+    assert!(mixed > 0, "no polygon joins leaves of different levels");
+
+    let (mesh, _) = make_mesh(&shapes.torus(), offset, a, size, &settings, false);
+    println!("adc, torus: {} vertices, {} triangles", mesh.vertex_count(), mesh.triangle_count());
+    marching_cubes::assert_closed(&mesh);
+
+    let node = DenNode::sdf_sphere(Sphere{center : shapes.center, rad : shapes.rad}).union(DenNode::sdf_aabb(shapes.box_center + Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.6, 0.5, 0.6)));
+    let (culled, _) = make_mesh(&node, offset, a, size, &settings, false);
+    //the same node without bounds, so only the culling differs
+    struct Unbounded(DenNode);
+    impl Density3 for Unbounded{
+        fn density(&self, p : Vector3<f32>) -> f32 {self.0.eval(p)}
+        fn gradient(&self, p : Vector3<f32>) -> Option<Vector3<f32>> {self.0.gradient(p)}
+    }
+    let (sampled, _) = make_mesh(&Unbounded(node.clone()), offset, a, size, &settings, false);
+    println!("adc, node with bounds: {} vertices, {} triangles", culled.vertex_count(), culled.triangle_count());
+    marching_cubes::assert_closed(&culled);
+    assert!(culled.vertices == sampled.vertices && culled.indices == sampled.indices, "culling changed the mesh");
+}
@@ -1,74 +1,459 @@
 use std;
 use na::*;
 use math::*;
-use renderer::*;
-use alga::general::*;
-use num::PrimInt;
+use mesh::*;
+use density::*;
+use cms;
+use cms::Samples;
+use qef::{Qef, CollapseSettings};
+use marching_cubes;
+use marching_cubes::{faces, edge_axes, TestShapes};
+use uniform_manifold_dc;
+use uniform_manifold_dc::{edge_table, vertex_num_table, which_edges_are_signed, corner_points, edge_pairs};
+use std::collections::{HashMap, HashSet};
 
+//adaptive manifold dual contouring (Lin et al. 2015) built on manifold dual contouring (Schaefer, Ju, Warren 2007)
+//every cell of the sampled grid gets one vertex per surface component in it (dual marching cubes table), then the octree is walked bottom up
+//and the vertices of a node connected by the surface inside the node are clustered, their qefs summed
+//a cluster stands in for its members if they do too, its qef error is below the tolerance and the surface it covers is a disk
+//that meets each face of the node in one curve and each edge of the node at most once, which keeps the mesh manifold
+//polygons are made on the grid edges from the topmost standing in vertices and the degenerate ones are dropped, so there are no cracks
 
-#[derive(Clone, Debug)]
-pub struct VoxelData<T : Copy + PartialEq + std::fmt::Debug> where T : 'static{
-    pub densities : [T;8], //do we need it ?
-    pub config : usize,
-    pub planes : Vec<Plane<T>>,
+//vertex of a grid cell or cluster of vertices of an octree node, in coordinates relative to the grid
+struct Vertex{
+    qef : Qef,
+    normal : Vector3<f32>, //sum of the normals
+    position : Vector3<f32>,
+    bounds : Square3<f32>, //cell or node
+    parent : Option<u32>,
+    leaves : Vec<u32>, //cell vertices it covers, only kept while it may still be collapsed into its parent
+    collapsible : bool,
 }
 
-impl<T : Real + Identity<Additive>> VoxelData<T>{
-    pub fn is_heterogeneous(&self) -> bool{
-        let sign = self.densities[0];
-        for i in 1..8{
-            if self.densities[i] * sign <= T::zero() {return true}
-        }
-        false
-    }
+struct Amdc<'s>{
+    samples : &'s Samples,
+    size : usize,
+    tolerance : f32,
+    vertices : Vec<Vertex>, //cell vertices first
+    leaf_edges : Vec<([usize;3], Vec<usize>)>, //cell and cell edges of each cell vertex
+    cell_vertices : HashMap<usize, [u32;12]>, //vertex bound to each edge of the cell, `std::u32::MAX` for edges without a sign change
+    local_edge : [[[usize;2];2];3], //cell edge along `axis` at offsets `du`, `dv` (see `cells_around`)
+    face_edges : Vec<(usize, usize, [usize;4])>, //axis, side and cell edges of each cube face
 }
 
-pub struct Octree<T>{
-    pub parent : *mut Octree<T>,
-    pub children : *mut (*mut Octree<T>),
-    pub data : VoxelData<T>,
+fn unit_corners() -> Vec<[usize;3]>{
+    corner_points().iter().map(|c| [c.x as usize, c.y as usize, c.z as usize]).collect()
 }
 
-pub fn corner_points() -> Vec< Vector3<f32> >{
-    vec![
-        Vector3::new(0.0,0.0,0.0),
-        Vector3::new(1.0,0.0,0.0),
-        Vector3::new(1.0,0.0,1.0),  //clockwise starting from zero y min
-        Vector3::new(0.0,0.0,1.0),
+fn edge_id(a : usize, b : usize) -> usize{
+    edge_pairs().iter().position(|e| (e.x == a && e.y == b) || (e.x == b && e.y == a)).unwrap()
+}
 
-        Vector3::new(0.0,1.0,0.0),
-        Vector3::new(1.0,1.0,0.0), //y max
-        Vector3::new(1.0,1.0,1.0),
-        Vector3::new(0.0,1.0,1.0)
+impl<'s> Amdc<'s>{
+    fn new(samples : &'s Samples, size : usize, tolerance : f32) -> Amdc<'s>{
+        let corners = unit_corners();
+        let mut local_edge = [[[0;2];2];3];
+        for (id, &(lo, _, axis)) in edge_axes().iter().enumerate(){
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+            local_edge[axis][corners[lo][u]][corners[lo][v]] = id;
+        }
+        let face_edges = faces().iter().map(|face|{
+            let c = corners[face[0]];
+            let axis = (0..3).find(|&axis| (0..4).all(|k| corners[face[k]][axis] == c[axis])).unwrap();
+            let mut edges = [0;4];
+            for k in 0..4{
+                edges[k] = edge_id(face[k], face[(k + 1) % 4]);
+            }
+            (axis, c[axis], edges)
+        }).collect();
 
-    ]
-}
+        Amdc{samples, size, tolerance, vertices : Vec::new(), leaf_edges : Vec::new(), cell_vertices : HashMap::new(), local_edge, face_edges}
+    }
+
+    fn cell_index(&self, c : [usize;3]) -> usize{
+        (c[2] * self.size + c[1]) * self.size + c[0]
+    }
+
+    //the four cells around the grid edge from `q` along `axis` counter clockwise seen from its end, `None` outside the grid
+    //with the offsets of the edge in each of them
+    fn cells_around(&self, q : [usize;3], axis : usize) -> [(Option<[usize;3]>, usize, usize);4]{
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        let mut cells = [(None, 0, 0);4];
+        for (i, &(du, dv)) in [(1, 1), (0, 1), (0, 0), (1, 0)].iter().enumerate(){
+            let mut c = q;
+            let inside = c[u] >= du && c[v] >= dv && c[u] - du < self.size && c[v] - dv < self.size && c[axis] < self.size;
+            c[u] = c[u].wrapping_sub(du);
+            c[v] = c[v].wrapping_sub(dv);
+            cells[i] = (if inside {Some(c)} else {None}, du, dv);
+        }
+        cells
+    }
+
+    fn vertex_at(&self, c : [usize;3], local : usize) -> u32{
+        self.cell_vertices.get(&self.cell_index(c)).map(|vertices| vertices[local]).unwrap_or(std::u32::MAX)
+    }
+
+    //vertices of the cells around the grid edge, if the edge is inside the grid
+    fn edge_vertices(&self, q : [usize;3], axis : usize) -> Option<[u32;4]>{
+        let mut vertices = [0;4];
+        for (i, &(cell, du, dv)) in self.cells_around(q, axis).iter().enumerate(){
+            vertices[i] = self.vertex_at(cell?, self.local_edge[axis][du][dv]);
+        }
+        Some(vertices)
+    }
+
+    //one vertex per surface component of each cell (dual marching cubes), placed at its qef minimizer
+    fn make_leaves(&mut self){
+        let table = edge_table();
+        let vertex_num = vertex_num_table();
+        let corners = unit_corners();
+        let edges = edge_axes();
+        let a = self.samples.vg.a;
+        let size = self.size;
+
+        for z in 0..size{
+            for y in 0..size{
+                for x in 0..size{
+                    let mut config = 0;
+                    for i in 0..8{
+                        if self.samples.inside([x + corners[i][0], y + corners[i][1], z + corners[i][2]]) {config |= 1 << i;}
+                    }
+                    if vertex_num[config] == 0 {continue;}
+
+                    let mut bound = [std::u32::MAX;12];
+                    let bounds = Square3{center : self.samples.vg.get_point(x, y, z) + Vector3::new(a, a, a) * 0.5, extent : a / 2.0};
+                    for group in which_edges_are_signed(&table, config){
+                        let mut qef = Qef::new();
+                        let mut normal = Vector3::new(0.0, 0.0, 0.0);
+                        for &local in &group{
+                            let (lo, _, axis) = edges[local];
+                            let key = self.samples.edge_key([x + corners[lo][0], y + corners[lo][1], z + corners[lo][2]], axis);
+                            let plane = self.samples.planes[&key];
+                            qef.add(&Plane{point : plane.point - self.samples.offset, normal : plane.normal});
+                            normal += plane.normal;
+                        }
+                        let id = self.vertices.len() as u32;
+                        for &local in &group{
+                            bound[local] = id;
+                        }
+                        let position = clamp_to(qef.solve().0, &bounds);
+                        self.vertices.push(Vertex{qef, normal, position, bounds, parent : None, leaves : vec![id], collapsible : true});
+                        self.leaf_edges.push(([x, y, z], group));
+                    }
+                    let index = self.cell_index([x, y, z]);
+                    self.cell_vertices.insert(index, bound);
+                }
+            }
+        }
+    }
+
+    fn root(&self, vertex : u32) -> u32{
+        let mut v = vertex;
+        while let Some(parent) = self.vertices[v as usize].parent {v = parent;}
+        v
+    }
+
+    //the vertex standing in for a cell vertex: its topmost ancestor with all clusters on the way collapsed
+    fn representative(&self, vertex : u32) -> u32{
+        let mut v = vertex;
+        while let Some(parent) = self.vertices[v as usize].parent{
+            if !self.vertices[parent as usize].collapsible {break;}
+            v = parent;
+        }
+        v
+    }
+
+    //clusters the vertices of the node at `pos` (grid units) bottom up, returns the topmost vertices in it
+    fn cluster(&mut self, pos : [usize;3], size : usize) -> Vec<u32>{
+        if size == 1{
+            return self.cell_vertices.get(&self.cell_index(pos)).map(|bound|{
+                let mut vertices : Vec<u32> = bound.iter().cloned().filter(|&v| v != std::u32::MAX).collect();
+                vertices.sort();
+                vertices.dedup();
+                vertices
+            }).unwrap_or(Vec::new());
+        }
+
+        let half = size / 2;
+        let mut roots = Vec::new();
+        for c in unit_corners(){
+            roots.extend(self.cluster([pos[0] + c[0] * half, pos[1] + c[1] * half, pos[2] + c[2] * half], half));
+        }
+        if roots.len() < 2 {return roots;}
+
+        //vertices of the children are connected by the grid edges on the planes between the children
+        let mut union = (0..roots.len()).collect::<Vec<usize>>();
+        fn find(union : &mut Vec<usize>, i : usize) -> usize{
+            let mut i = i;
+            while union[i] != i {union[i] = union[union[i]]; i = union[i];}
+            i
+        }
+        let local : HashMap<u32, usize> = roots.iter().enumerate().map(|(i, &v)| (v, i)).collect();
+        let mid = [pos[0] + half, pos[1] + half, pos[2] + half];
+        for k in 0..3{
+            for axis in (0..3).filter(|&axis| axis != k){
+                let w = 3 - k - axis;
+                for t in pos[axis]..pos[axis] + size{
+                    for s in pos[w] + 1..pos[w] + size{
+                        let mut q = [0;3];
+                        q[k] = mid[k];
+                        q[axis] = t;
+                        q[w] = s;
+                        let mut end = q;
+                        end[axis] += 1;
+                        if self.samples.inside(q) == self.samples.inside(end) {continue;}
+
+                        let vertices = self.edge_vertices(q, axis).unwrap();
+                        let first = find(&mut union, local[&self.root(vertices[0])]);
+                        for &vertex in vertices[1..].iter(){
+                            let other = find(&mut union, local[&self.root(vertex)]);
+                            union[other] = first;
+                        }
+                    }
+                }
+            }
+        }
 
-pub fn sample_voxeldata(bounds : Square3<f32>, f : DenFn3<f32>, corner_points : Vec<Vector3<f32>>) -> VoxelData<Tf32>{
-    
-    let mut corner_samples = [f32;8];
-    let mut config = 0;
-    
-    for i in 0..8{
-        corner_samples[i] = f(bounds.center + bounds.extent * corner_points[i]);
-        if densities[i] < 0.0{
-            config |= 1 << i;
+        let mut components : HashMap<usize, Vec<u32>> = HashMap::new();
+        for (i, &root) in roots.iter().enumerate(){
+            components.entry(find(&mut union, i)).or_insert(Vec::new()).push(root);
         }
+        let mut components : Vec<Vec<u32>> = components.into_iter().map(|(_, members)| members).collect();
+        components.sort();
+
+        let a = self.samples.vg.a;
+        let bounds = Square3{center : self.samples.vg.get_point(pos[0], pos[1], pos[2]) + Vector3::new(1.0, 1.0, 1.0) * (a * half as f32), extent : a * half as f32};
+        let mut result = Vec::with_capacity(components.len());
+        for members in components{
+            if members.len() == 1{
+                result.push(members[0]);
+                continue;
+            }
+
+            let id = self.vertices.len() as u32;
+            let mut qef = Qef::new();
+            let mut normal = Vector3::new(0.0, 0.0, 0.0);
+            let mut leaves = Vec::new();
+            let mut collapsible = true;
+            for &m in &members{
+                let member = &mut self.vertices[m as usize];
+                member.parent = Some(id);
+                qef.merge(&member.qef);
+                normal += member.normal;
+                collapsible &= member.collapsible;
+                leaves.extend(std::mem::replace(&mut member.leaves, Vec::new()));
+            }
+            let (position, _) = qef.solve();
+            let position = clamp_to(position, &bounds);
+            collapsible = collapsible && qef.error(&position) <= self.tolerance && self.is_manifold(&leaves, pos, size);
+            if !collapsible {leaves = Vec::new();}
+            self.vertices.push(Vertex{qef, normal, position, bounds, parent : None, leaves, collapsible});
+            result.push(id);
+        }
+        result
     }
 
+    //the surface covered by the cell vertices `leaves` of the node is a disk (euler characteristic of its dual polygons is 1),
+    //it crosses every edge of the node at most once and meets every face of the node in a single open curve
+    fn is_manifold(&self, leaves : &Vec<u32>, pos : [usize;3], size : usize) -> bool{
+        let in_node = |c : [usize;3]| (0..3).all(|i| c[i] >= pos[i] && c[i] < pos[i] + size);
+        let members : HashSet<u32> = leaves.iter().cloned().collect();
+        let edges = edge_axes();
+        let corners = unit_corners();
+
+        let mut polygons = 0;
+        let mut dual_edges = HashSet::new();
+        let mut node_edges : HashMap<(usize, usize, usize), usize> = HashMap::new();
+        //grid edges on each face of the node and the links between them through the cells
+        let mut face_points : Vec<HashSet<usize>> = vec![HashSet::new();6];
+        let mut face_links : Vec<Vec<(usize, usize)>> = vec![Vec::new();6];
+
+        for &leaf in leaves{
+            let (cell, ref group) = self.leaf_edges[leaf as usize];
+            for &local in group{
+                let (lo, _, axis) = edges[local];
+                let q = [cell[0] + corners[lo][0], cell[1] + corners[lo][1], cell[2] + corners[lo][2]];
+                let around = self.cells_around(q, axis);
+                let inside : Vec<bool> = around.iter().map(|&(c, _, _)| c.map_or(false, |c| in_node(c))).collect();
+                match inside.iter().filter(|&&i| i).count(){
+                    4 => {
+                        //each polygon is counted from its first cell
+                        if around[0].0 != Some(cell) {continue;}
+                        let vertices = self.edge_vertices(q, axis).unwrap();
+                        if vertices.iter().any(|v| !members.contains(v)) {return false;}
+                        polygons += 1;
+                        for i in 0..4{
+                            let (p, r) = (vertices[i], vertices[(i + 1) % 4]);
+                            dual_edges.insert((std::cmp::min(p, r), std::cmp::max(p, r)));
+                        }
+                    },
+                    1 => {
+                        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+                        let count = node_edges.entry((axis, q[u], q[v])).or_insert(0);
+                        *count += 1;
+                        if *count > 1 {return false;}
+                    },
+                    _ => (),
+                }
+            }
+
+            for (face, &(axis, side, ref face_edges)) in self.face_edges.iter().enumerate(){
+                if cell[axis] != if side == 0 {pos[axis]} else {pos[axis] + size - 1} {continue;}
+                let on_face : Vec<usize> = face_edges.iter().cloned().filter(|e| group.contains(e)).map(|local|{
+                    let (lo, _, axis) = edges[local];
+                    self.samples.edge_key([cell[0] + corners[lo][0], cell[1] + corners[lo][1], cell[2] + corners[lo][2]], axis)
+                }).collect();
+                match on_face.len(){
+                    0 => (),
+                    1 => {face_points[face].insert(on_face[0]);},
+                    2 => {
+                        face_points[face].insert(on_face[0]);
+                        face_points[face].insert(on_face[1]);
+                        face_links[face].push((on_face[0], on_face[1]));
+                    },
+                    _ => return false,
+                }
+            }
+        }
+
+        if leaves.len() as isize - dual_edges.len() as isize + polygons != 1 {return false;}
 
+        //an open curve is a connected graph with one link less than points
+        for face in 0..6{
+            let points : Vec<usize> = face_points[face].iter().cloned().collect();
+            if points.is_empty() {continue;}
+            if face_links[face].len() + 1 != points.len() {return false;}
+            let mut union : HashMap<usize, usize> = points.iter().map(|&p| (p, p)).collect();
+            fn find(union : &mut HashMap<usize, usize>, p : usize) -> usize{
+                let mut p = p;
+                while union[&p] != p {p = union[&p];}
+                p
+            }
+            let mut components = points.len();
+            for &(p, r) in &face_links[face]{
+                let (p, r) = (find(&mut union, p), find(&mut union, r));
+                if p != r {union.insert(p, r); components -= 1;}
+            }
+            if components != 1 {return false;}
+        }
+        true
+    }
 }
 
-fn is_const_sign(a : f32, b : f32) -> bool {
-    if a > 0.0 { b > 0.0} else {b <= 0.0}
+fn clamp_to(p : Vector3<f32>, cube : &Square3<f32>) -> Vector3<f32>{
+    let (min, max) = (cube.center - Vector3::new(cube.extent, cube.extent, cube.extent), cube.center + Vector3::new(cube.extent, cube.extent, cube.extent));
+    Vector3::new(p.x.max(min.x).min(max.x), p.y.max(min.y).min(max.y), p.z.max(min.z).min(max.z))
 }
 
-pub fn construct_octree(f : DenFn3<f32>, offset : Vector3<f32>, a : f32, size : usize) -> *mut Octree<f32>{
-    for i in 0..(size/2){
-        for j in 0..(size/2){
-            for k in 0..(size/2){
+//`size` must be a power of two, debug geometry - bounds of the nodes of the collapsed clusters
+pub fn make_mesh<D : Density3>(f : &D, offset : Vector3<f32>, a : f32, size : usize, settings : &CollapseSettings, debug : bool) -> (Mesh, Option<DebugGeometry>){
+    assert!(size.is_power_of_two(), "amdc needs a power of two grid size, got {}", size);
+    let samples = cms::sample(f, offset, a, size, settings.accuracy);
+    let mut amdc = Amdc::new(&samples, size, settings.error * a * a);
+    amdc.make_leaves();
+    amdc.cluster([0, 0, 0], size);
 
+    //a quad per grid edge with a sign change, facing from the inside corner to the outside one
+    let mut keys : Vec<usize> = samples.planes.keys().cloned().collect();
+    keys.sort();
+    let n = size + 1;
+    let mut mesh = Mesh::new();
+    let mut mesh_vertex : HashMap<u32, u32> = HashMap::new();
+    for key in keys{
+        let (axis, vertex) = (key % 3, key / 3);
+        let q = [vertex % n, vertex / n % n, vertex / (n * n)];
+        let vertices = match amdc.edge_vertices(q, axis){
+            Some(vertices) => vertices,
+            None => continue,
+        };
+
+        let mut quad = [0;4];
+        for i in 0..4{
+            let v = amdc.representative(vertices[i]);
+            let vertex = &amdc.vertices[v as usize];
+            quad[i] = *mesh_vertex.entry(v).or_insert_with(|| mesh.push_vertex(vertex.position + offset, vertex.normal.normalize(), 0));
+        }
+        if !samples.inside(q){
+            quad.swap(1, 3);
+        }
+
+        //clustered vertices make some of the quads triangles or nothing
+        let distinct = (0..4).filter(|&i| quad[i] != quad[(i + 1) % 4]).count();
+        if distinct == 4 && quad[0] != quad[2] && quad[1] != quad[3]{
+            //collapsed vertices bend the quads
+            mesh.push_bent_quad(quad[0], quad[1], quad[2], quad[3]);
+        }else if distinct == 3{
+            let i = (0..4).find(|&i| quad[i] == quad[(i + 1) % 4]).unwrap();
+            mesh.push_triangle(quad[(i + 1) % 4], quad[(i + 2) % 4], quad[(i + 3) % 4]);
+        }
+    }
+
+    let debug = if debug{
+        let mut geometry = DebugGeometry::new();
+        for (i, vertex) in amdc.vertices.iter().enumerate(){
+            if i >= amdc.leaf_edges.len() && vertex.collapsible && vertex.parent.map_or(true, |p| !amdc.vertices[p as usize].collapsible){
+                geometry.add_square3_bounds(Square3{center : vertex.bounds.center + offset, extent : vertex.bounds.extent}, Vector3::new(0.0, 0.0, 1.0));
             }
         }
+        Some(geometry)
+    }else {None};
+
+    (mesh, debug)
+}
+
+//a sphere, a box, a torus and a field full of saddles cut by a sphere, all closed
+//the flat faces of the box collapse to far fewer triangles than the uniform grid gives, collapsing keeps the genus of the sphere and the torus
+//even with the qef error ignored, the clusters `is_manifold` rejects keep the torus a torus: a tube through a node is not a disk
+pub fn test_amdc(){
+    let settings = CollapseSettings::new(8);
+    let shapes = TestShapes::new();
+    let (a, size, offset) = (shapes.a, shapes.size, shapes.offset);
+    //v - e + f of a closed mesh (each edge is shared by two triangles), 2 for a sphere, 0 for a torus
+    let euler = |mesh : &Mesh| {
+        let used : HashSet<u32> = mesh.indices.iter().cloned().collect();
+        used.len() as isize - (3 * mesh.triangle_count() / 2) as isize + mesh.triangle_count() as isize
+    };
+
+    let (mesh, _) = make_mesh(&shapes.sphere(), offset, a, size, &settings, false);
+    println!("amdc, sphere: {} vertices, {} triangles", mesh.vertex_count(), mesh.triangle_count());
+    marching_cubes::assert_sphere_mesh(&mesh, shapes.center, shapes.rad, a / 4.0);
+    assert_eq!(euler(&mesh), 2);
+
+    let aabb = shapes.aabb();
+    let (mesh, _) = make_mesh(&aabb, offset, a, size, &settings, false);
+    let (_, uniform, _) = uniform_manifold_dc::construct_grid(&aabb, offset, a, size, settings.accuracy, false);
+    println!("amdc, box: {} vertices, {} triangles, umdc: {} triangles", mesh.vertex_count(), mesh.triangle_count(), uniform.triangle_count());
+    assert!(mesh.triangle_count() * 4 < uniform.triangle_count());
+    marching_cubes::assert_box_corners(&mesh, shapes.box_center, shapes.box_extent, a / 4.0);
+
+    let torus = shapes.torus();
+    let (mesh, _) = make_mesh(&torus, offset, a, size, &settings, false);
+    println!("amdc, torus: {} vertices, {} triangles", mesh.vertex_count(), mesh.triangle_count());
+    marching_cubes::assert_closed(&mesh);
+    assert_eq!(euler(&mesh), 0);
+
+    let (coarse, _) = make_mesh(&torus, offset, a, size, &CollapseSettings{error : std::f32::MAX, ..settings}, false);
+    println!("amdc, torus with any qef error: {} vertices, {} triangles", coarse.vertex_count(), coarse.triangle_count());
+    marching_cubes::assert_closed(&coarse);
+    assert_eq!(euler(&coarse), 0);
+    assert!(coarse.triangle_count() < mesh.triangle_count());
+
+    let (mesh, _) = make_mesh(&shapes.saddles(), offset, a, size, &settings, false);
+    println!("amdc, saddles: {} vertices, {} triangles", mesh.vertex_count(), mesh.triangle_count());
+    marching_cubes::assert_closed(&mesh);
+
+    //all cell vertices of a grid of one node: a sheet across it is a disk, a tube through it is not
+    let (a, size) = (0.125, 8);
+    let sheet : DenFn3<f32> = box |p| {p.z - 0.47};
+    let tube : DenFn3<f32> = box |p| {((p.x - 0.52).powi(2) + (p.y - 0.47).powi(2)).sqrt() - 0.25};
+    for &(f, manifold) in [(&sheet, true), (&tube, false)].iter(){
+        let samples = cms::sample(f, offset, a, size, settings.accuracy);
+        let mut amdc = Amdc::new(&samples, size, std::f32::MAX);
+        amdc.make_leaves();
+        let leaves = (0..amdc.vertices.len() as u32).collect();
+        assert_eq!(amdc.is_manifold(&leaves, [0, 0, 0], size), manifold);
+        let roots = amdc.cluster([0, 0, 0], size);
+        assert_eq!(roots.len(), 1);
+        assert_eq!(amdc.vertices[roots[0] as usize].collapsible, manifold);
     }
-}
\ No newline at end of file
+}
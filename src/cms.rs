@@ -1,188 +1,459 @@
 use std;
 use na::*;
 use math::*;
-use renderer::*;
-use alga::general::*;
-use std::rc::Rc;
-use std::cell::RefCell;
-use num::PrimInt;
+use mesh::*;
+use density::*;
+use dc;
+use dc::VoxelGrid3;
+use uniform_manifold_dc::{corner_points, sample_surface_intersection, sample_normal, solve_qef_analically_qr};
+use marching_cubes::{faces, edge_axes, polygon_table};
+use marching_cubes;
+use marching_cubes::TestShapes;
 use std::collections::HashMap;
-use qef_bindings::*;
-
-
-
-// static corner_points : Vec<Vector3<f32>> = {
-//     vec![
-//         Vector3::new(0.0,0.0,0.0),
-//         Vector3::new(1.0,0.0,0.0),
-//         Vector3::new(1.0,0.0,1.0),  //clockwise starting from zero y min
-//         Vector3::new(0.0,0.0,1.0),
-
-//         Vector3::new(0.0,1.0,0.0),
-//         Vector3::new(1.0,1.0,0.0), //y max
-//         Vector3::new(1.0,1.0,1.0),
-//         Vector3::new(0.0,1.0,1.0)
-
-//     ]
-// };
-
-
-// static edge_pairs :  Vec<Vector2<usize>> = {
-//     vec![
-//         Vector2::new(0,1),
-//         Vector2::new(1,2),
-//         Vector2::new(3,2),
-//         Vector2::new(0,3),
-
-//         Vector2::new(4,5), 
-//         Vector2::new(5,6), //5
-//         Vector2::new(7,6), //6
-//         Vector2::new(4,7), 
-
-//         Vector2::new(4,0),
-//         Vector2::new(1,5), 
-//         Vector2::new(2,6), //10
-//         Vector2::new(3,7)  
-//     ]
-// };
-
-// #[derive(Clone, Debug)]
-// pub struct HermiteData<T : Real>{
-//     pub a : T,//length of one edge of a cubic cell
-//     pub cell_count : usize, //number of cells along each axis, must be a power of 2
-//     pub pos : Vector3<T>, //min corner of the AABB
-//     pub grid : Vec<T>,
-//     pub hermite : Vec<Vec<Plane<T>>>, //for now(not efficient) this Vec contains hermite data for each cell(duplications)
-//     //after filling the grid each cell must be initialized (set to Some)
-// }
-
-// pub struct Octree<T : Real>{
-//     pub level : usize,//starting from 0 for roots
-//     pub children : Option<Vec<*mut Octree<T>>>,
-//     pub pos : Vec<u8>,
-
-//     //oct indexing of this node relative to the root
-//     //to be replaced by bitfield:
-//     //root node has pos equal to 0
-//     //next node can have position: (0-7) + 1
-//     //next: (0-7 0-7) + 1
-//     //...
-//     //(<>) is a number in base 8 each digit is separated by space
-// }
-
-// impl<T : Real> Octree<T>{
-    
-// }
-
-// //in local coordinates [0,0,0] - [1,1,1]
-// unsafe fn get_bounding_cube(node : &(*mut Octree<f32>)) -> Square3<f32>{
-//     let mut res = Square3{center : Vector3::new(0.5, 0.5, 0.5), extent : 0.5};
-
-//     for i in &(**node).pos{
-//         let ext = res.extent / 2.0;
-//         match i.clone(){
-//             0 => {
-//                 res = Square3{center : res.center * 0.5, extent : ext};
-//             },
-//             1 => {
-//                 res = Square3{center : Vector3::new(res.center.x * 1.5, res.center.y * 0.5, res.center.z * 0.5), extent : ext};
-//             },
-//             2 => {
-//                 res = Square3{center : Vector3::new(res.center.x * 1.5, res.center.y * 0.5, res.center.z * 1.5), extent : ext};
-//             },
-//             3 => {
-//                 res = Square3{center : Vector3::new(res.center.x * 0.5, res.center.y * 0.5, res.center.z * 1.5), extent : ext};
-//             },
-
-
-
-//             4 => {
-//                 res = Square3{center : Vector3::new(res.center.x * 0.5, res.center.y * 1.5, res.center.z * 0.5), extent : ext};
-//             },
-//             5 => {
-//                 res = Square3{center : Vector3::new(res.center.x * 1.5, res.center.y * 1.5, res.center.z * 0.5), extent : ext};
-//             },
-//             6 => {
-//                 res = Square3{center : Vector3::new(res.center.x * 1.5, res.center.y * 1.5, res.center.z * 1.5), extent : ext};
-//             },
-//             7 => {
-//                 res = Square3{center : Vector3::new(res.center.x * 0.5, res.center.y * 1.5, res.center.z * 1.5), extent : ext};
-//             },
-//             _ => (),
-//         }
-//     }
-
-//     res
-// }
-
-// fn scala_local_cube_bounds(bounds : &Square3<f32>, data : &HermiteData<f32>) -> Square3<f32>{
-//     let e = data.a * (data.cell_count/2) as f32;
-//     Square3{center : bounds.center * e, extent : bounds.extent * e}
-// }
-
-
-// struct SampleRange{ //corners are included
-//     pub xmin : usize,
-//     pub ymin : usize,
-//     pub zmin : usize,
-//     pub xmax : usize,
-//     pub ymax : usize,
-//     pub zmax : usize,
-// }
-
-// fn sample_range_for_scaled_local_bounds(bounds : &Square3<f32>, data : &HermiteData<f32>) -> SampleRange{
-//     let xmin = bounds.center.x - bounds.extent;
-//     let ymin = bounds.center.y - bounds.extent;
-//     let zmin = bounds.center.z - bounds.extent;
-
-//     let xmax = bounds.center.x + bounds.extent;
-//     let ymax = bounds.center.y + bounds.extent;
-//     let zmax = bounds.center.z + bounds.extent;
-
-//     SampleRange{
-//         xmin : (xmin / data.a) as usize,
-//         ymin : (ymin / data.a) as usize,
-//         zmin : (zmin / data.a) as usize,
-//         xmax : (xmax / data.a) as usize,
-//         ymax : (ymax / data.a) as usize,
-//         zmax : (zmax / data.a) as usize,
-//     }
-
-
-// }
-
-// // unsafe fn get_corner_samples(node : &mut Octree<f32>, data : &HermiteData<f32>) -> [f32;8]{
-
-// // }
-
-
-// //cur starts from 0
-// //pos starts from empty Vec
-// pub unsafe fn initialize_base_grid(res : *mut *mut Octree<f32>, n0 : usize, cur : usize, pos : Vec<u8>){
-
-
-//     if n0 != cur{
-//         let mut children = Vec::with_capacity(8);
-//         for i in 0..8{
-//             let mut new_pos = pos.clone();
-//             new_pos.push(i as u8);
-//             initialize_base_grid(&mut children[i], n0, cur + 1, new_pos);
-//         }
-//         *res = &mut Octree{level : cur, children : Some(children), pos}
-//     }else{
-//         *res = &mut Octree{level : cur, children : None, pos}
-//     }
-// }
-
-// pub unsafe fn subdivide_cell(hermite_data : &HermiteData<f32>, cell : *mut *mut Octree<f32>){
-
-// }
-
-// //hermite data should be generated alongside isosurface extraction (on GPU) to increase performance as hermite data is almost always generated real-time from noise function
-// //input: hermite_data : pregenerated hermite data, n0 : size (spanning one dimension) of the coarse uniform grid
-// pub fn cubical_marching_squares(hermite_data : &HermiteData<f32>, n0 : usize){
-//     unsafe{
-//         let mut base_grid = std::ptr::null_mut();
-//         initialize_base_grid(&mut base_grid, n0, 0, Vec::with_capacity(0));
-//     }
-// }
\ No newline at end of file
+use rayon::prelude::*;
+
+//cubical marching squares (Ho et al. 2005) over an adaptive octree of the sampled grid
+//cells are merged while the surface in them stays one flat sheet, every face of a leaf is split along the faces of its smaller neighbours
+//and marching squares runs on the pieces, so neighbouring leaves share each point on their faces and the mesh has no cracks or t-junctions
+//face segments that bend sharply get a point where their tangents meet, each loop of segments around a leaf is fanned from its own vertex
+//(the qef minimizer when the loop goes around a sharp feature), so leaves crossed by several sheets stay manifold
+
+#[derive(Clone, Copy, Debug)]
+pub struct CmsSettings{
+    pub accuracy : usize, //iterations spent on each edge intersection
+    pub flatness : f32, //cells are merged while the cosine between each surface normal in them and their average stays above this
+    pub error : f32, //or while the surface planes in them pass on average within this many grid cells of their qef minimizer (sharp features)
+    pub sharpness : f32, //loops and face segments get a feature point when two of their normals have a smaller cosine between them
+}
+
+impl CmsSettings{
+    pub fn new(accuracy : usize) -> CmsSettings{
+        CmsSettings{accuracy, flatness : 0.95, error : 0.05, sharpness : 0.75}
+    }
+}
+
+//cube of the octree, in grid units
+#[derive(Clone, Copy, Debug)]
+pub struct Leaf{
+    pub pos : [usize;3],
+    pub size : usize,
+}
+
+//signs at the grid vertices and the surface point + normal on each grid edge with a sign change
+//`vg` is filled by `dc::fill_in_grid`: away from the surface it holds bounds, not densities
+pub struct Samples{
+    pub vg : VoxelGrid3<f32>,
+    pub offset : Vector3<f32>,
+    pub planes : HashMap<usize, Plane<f32>>, //by `edge_key`
+}
+
+impl Samples{
+    pub fn inside(&self, p : [usize;3]) -> bool{
+        self.vg.get(p[0], p[1], p[2]) < 0.0
+    }
+
+    pub fn point(&self, p : [usize;3]) -> Vector3<f32>{
+        self.offset + self.vg.get_point(p[0], p[1], p[2])
+    }
+
+    //grid edge from `p` along `axis`
+    pub fn edge_key(&self, p : [usize;3], axis : usize) -> usize{
+        3 * ((p[2] * self.vg.vertices_y() + p[1]) * self.vg.vertices_x() + p[0]) + axis
+    }
+
+    //the last grid edge with a sign change on the `len` edges from `p` along `axis` and the number of sign changes there
+    pub fn crossing(&self, p : [usize;3], axis : usize, len : usize) -> (Option<usize>, usize){
+        let (mut key, mut changes) = (None, 0);
+        let mut cur = p;
+        for _ in 0..len{
+            let mut next = cur;
+            next[axis] += 1;
+            if self.inside(cur) != self.inside(next){
+                key = Some(self.edge_key(cur, axis));
+                changes += 1;
+            }
+            cur = next;
+        }
+        (key, changes)
+    }
+}
+
+//`size`^3 cells with edge `a` from `offset`
+pub fn sample<D : Density3>(f : &D, offset : Vector3<f32>, a : f32, size : usize, accuracy : usize) -> Samples{
+    let mut vg = VoxelGrid3::new(a, size, size, size);
+    dc::fill_in_grid(&mut vg, f, offset);
+    let mut samples = Samples{vg, offset, planes : HashMap::new()};
+
+    //one z layer of edges per task
+    let n = size + 1;
+    let layers : Vec<Vec<(usize, Plane<f32>)>> = {
+        let samples = &samples;
+        (0..n).into_par_iter().map(|z|{
+            let mut layer = Vec::new();
+            for y in 0..n{
+                for x in 0..n{
+                    for axis in 0..3{
+                        let (p, mut q) = ([x, y, z], [x, y, z]);
+                        q[axis] += 1;
+                        if q[axis] >= n || samples.inside(p) == samples.inside(q) {continue;}
+                        let point = sample_surface_intersection(&Line3{start : samples.point(p), end : samples.point(q)}, accuracy, f);
+                        layer.push((samples.edge_key(p, axis), Plane{point, normal : sample_normal(&point, a / 100.0, f)}));
+                    }
+                }
+            }
+            layer
+        }).collect()
+    };
+    for layer in layers{
+        samples.planes.extend(layer);
+    }
+
+    samples
+}
+
+fn unit_corners() -> Vec<[usize;3]>{
+    corner_points().iter().map(|c| [c.x as usize, c.y as usize, c.z as usize]).collect()
+}
+
+fn corner_of(pos : [usize;3], size : usize, c : [usize;3]) -> [usize;3]{
+    [pos[0] + c[0] * size, pos[1] + c[1] * size, pos[2] + c[2] * size]
+}
+
+//bit `i` set - corner `i` is inside
+fn config_of(samples : &Samples, corners : &[[usize;3]], pos : [usize;3], size : usize) -> usize{
+    let mut config = 0;
+    for i in 0..8{
+        if samples.inside(corner_of(pos, size, corners[i])) {config |= 1 << i;}
+    }
+    config
+}
+
+//a cube can be one leaf if the surface in it is a single sheet the marching squares on its faces recover:
+//each cube edge crosses it at most once, no face is ambiguous and the sheet is either flat or bent only along sharp features
+fn is_simple(samples : &Samples, settings : &CmsSettings, table : &Vec<Vec<Vec<usize>>>, pos : [usize;3], size : usize, planes : &Vec<Plane<f32>>) -> bool{
+    let corners = unit_corners();
+    let config = config_of(samples, &corners, pos, size);
+    if planes.is_empty() {return true;} //no grid edge in the cube changes its sign
+    if config == 0 || config == 255 || table[config].len() != 1 {return false;}
+
+    for &(lo, _, axis) in edge_axes().iter(){
+        if samples.crossing(corner_of(pos, size, corners[lo]), axis, size).1 > 1 {return false;}
+    }
+    for face in faces().iter(){
+        let changes = (0..4).filter(|&k| (config >> face[k]) & 1 != (config >> face[(k + 1) % 4]) & 1).count();
+        if changes > 2 {return false;}
+    }
+
+    let sum = planes.iter().fold(Vector3::new(0.0, 0.0, 0.0), |acc, p| acc + p.normal);
+    let mean = sum / sum.norm();
+    if planes.iter().all(|p| p.normal.dot(&mean) >= settings.flatness) {return true;}
+
+    let a = samples.vg.a;
+    let cube = Square3{center : samples.point(pos) + Vector3::new(1.0, 1.0, 1.0) * (a * size as f32 / 2.0), extent : a * size as f32 / 2.0};
+    let point = feature_point(planes, &cube);
+    let error = planes.iter().map(|p| p.normal.dot(&(point - p.point)).powi(2)).sum::<f32>() / planes.len() as f32;
+    error <= (settings.error * a).powi(2)
+}
+
+//splits the cube while it is not simple, pushing the leaves it ends up with
+//returns the surface planes in the cube if it may still be merged with its siblings
+fn subdivide(samples : &Samples, settings : &CmsSettings, table : &Vec<Vec<Vec<usize>>>, pos : [usize;3], size : usize, leaves : &mut Vec<Leaf>) -> Option<Vec<Plane<f32>>>{
+    let corners = unit_corners();
+    if size == 1{
+        let planes = edge_axes().iter().filter_map(|&(lo, _, axis)| samples.planes.get(&samples.edge_key(corner_of(pos, 1, corners[lo]), axis)).cloned()).collect();
+        return Some(planes);
+    }
+
+    let half = size / 2;
+    let children : Vec<([usize;3], Option<Vec<Plane<f32>>>)> = corners.iter().map(|&c|{
+        let child = corner_of(pos, half, c);
+        (child, subdivide(samples, settings, table, child, half, leaves))
+    }).collect();
+
+    if children.iter().all(|&(_, ref planes)| planes.is_some()){
+        let planes : Vec<Plane<f32>> = children.iter().flat_map(|&(_, ref planes)| planes.as_ref().unwrap().iter().cloned()).collect();
+        if is_simple(samples, settings, table, pos, size, &planes){
+            return Some(planes);
+        }
+    }
+
+    for &(child, ref planes) in children.iter(){
+        if planes.is_some(){
+            leaves.push(Leaf{pos : child, size : half});
+        }
+    }
+    None
+}
+
+//where the tangents of the surface at the ends of a face segment meet if the segment bends sharply
+//`axis` - normal of the face, only the other coordinates of `min` and `max` bound the point
+fn face_feature(h0 : &Plane<f32>, h1 : &Plane<f32>, axis : usize, min : Vector3<f32>, max : Vector3<f32>, sharpness : f32) -> Option<Vector3<f32>>{
+    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+    let (n0, n1) = (Vector2::new(h0.normal[u], h0.normal[v]), Vector2::new(h1.normal[u], h1.normal[v]));
+    if n0.norm() < 0.01 || n1.norm() < 0.01 {return None;}
+    let (n0, n1) = (n0.normalize(), n1.normalize());
+    let det = n0.x * n1.y - n0.y * n1.x;
+    if n0.dot(&n1) >= sharpness || det.abs() < 0.01 {return None;}
+
+    let (d0, d1) = (n0.x * h0.point[u] + n0.y * h0.point[v], n1.x * h1.point[u] + n1.y * h1.point[v]);
+    let mut point = h0.point;
+    point[u] = (d0 * n1.y - n0.y * d1) / det;
+    point[v] = (n0.x * d1 - d0 * n1.x) / det;
+    let eps = (max - min).norm() * 0.001;
+    if point[u] < min[u] - eps || point[u] > max[u] + eps || point[v] < min[v] - eps || point[v] > max[v] + eps {return None;}
+    Some(point)
+}
+
+//qef minimizer kept near the mass point by weak planes through it, so rank deficient systems (sharp edges, flat sheets) stay solvable
+fn feature_point(planes : &Vec<Plane<f32>>, cube : &Square3<f32>) -> Vector3<f32>{
+    let mass = planes.iter().fold(Vector3::new(0.0, 0.0, 0.0), |acc, p| acc + p.point) / planes.len() as f32;
+    let mut biased = planes.clone();
+    for axis in 0..3{
+        let mut normal = Vector3::new(0.0, 0.0, 0.0);
+        normal[axis] = 0.05;
+        biased.push(Plane{point : mass, normal});
+    }
+    let point = solve_qef_analically_qr(&biased, cube);
+    let (min, max) = (cube.center - Vector3::new(cube.extent, cube.extent, cube.extent), cube.center + Vector3::new(cube.extent, cube.extent, cube.extent));
+    Vector3::new(point.x.max(min.x).min(max.x), point.y.max(min.y).min(max.y), point.z.max(min.z).min(max.z))
+}
+
+//moves `p` towards the surface along the normal by one newton step
+fn project_to_surface<D : Density3>(p : Vector3<f32>, eps : f32, f : &D) -> Vector3<f32>{
+    let normal = sample_normal(&p, eps, f);
+    let slope = (f.density(p + normal * eps) - f.density(p - normal * eps)) / (2.0 * eps);
+    if slope.abs() < 0.001 {return p;}
+    p - normal * (f.density(p) / slope)
+}
+
+//the pieces of each leaf face marching squares runs on: the faces of the smaller neighbours, the whole face otherwise
+//(min corner, size, face id in `faces()` order, normal axis of the face)
+fn face_pieces(leaves : &Vec<Leaf>, owner : &Vec<u32>, n : usize, leaf : &Leaf) -> Vec<([usize;3], usize, usize, usize)>{
+    let corners = unit_corners();
+    let mut pieces = Vec::new();
+    for (face_id, face) in faces().iter().enumerate(){
+        let c = corners[face[0]];
+        let axis = if (0..4).all(|k| corners[face[k]][0] == c[0]) {0} else if (0..4).all(|k| corners[face[k]][1] == c[1]) {1} else {2};
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        let mut plane = leaf.pos;
+        plane[axis] += c[axis] * leaf.size;
+
+        let layer = if c[axis] == 0 {leaf.pos[axis].checked_sub(1)} else {Some(leaf.pos[axis] + leaf.size)};
+        let layer = match layer{
+            Some(l) if l < n => l,
+            _ => {pieces.push((plane, leaf.size, face_id, axis)); continue;},
+        };
+
+        let mut seen = Vec::new();
+        for i in 0..leaf.size{
+            for j in 0..leaf.size{
+                let mut cell = leaf.pos;
+                cell[axis] = layer;
+                cell[u] += i;
+                cell[v] += j;
+                let id = owner[(cell[2] * n + cell[1]) * n + cell[0]];
+                if !seen.contains(&id) {seen.push(id);}
+            }
+        }
+
+        for &id in seen.iter(){
+            let neighbour = &leaves[id as usize];
+            if neighbour.size >= leaf.size{
+                pieces.push((plane, leaf.size, face_id, axis));
+                break;
+            }
+            let mut min = neighbour.pos;
+            min[axis] = plane[axis];
+            pieces.push((min, neighbour.size, face_id, axis));
+        }
+    }
+    pieces
+}
+
+//leaves of the octree over the whole grid and the leaf owning each grid cell
+fn make_leaves(samples : &Samples, settings : &CmsSettings, table : &Vec<Vec<Vec<usize>>>, size : usize) -> (Vec<Leaf>, Vec<u32>){
+    let mut leaves = Vec::new();
+    if subdivide(samples, settings, table, [0, 0, 0], size, &mut leaves).is_some(){
+        leaves.push(Leaf{pos : [0, 0, 0], size});
+    }
+    let mut owner = vec![0u32;size * size * size];
+    for (id, leaf) in leaves.iter().enumerate(){
+        for z in leaf.pos[2]..leaf.pos[2] + leaf.size{
+            for y in leaf.pos[1]..leaf.pos[1] + leaf.size{
+                for x in leaf.pos[0]..leaf.pos[0] + leaf.size{
+                    owner[(z * size + y) * size + x] = id as u32;
+                }
+            }
+        }
+    }
+    (leaves, owner)
+}
+
+//`size` must be a power of two, debug geometry - bounds of the leaves the surface passes through
+pub fn make_mesh<D : Density3>(f : &D, offset : Vector3<f32>, a : f32, size : usize, settings : &CmsSettings, debug : bool) -> (Mesh, Option<DebugGeometry>){
+    assert!(size.is_power_of_two(), "cms needs a power of two grid size, got {}", size);
+    let samples = sample(f, offset, a, size, settings.accuracy);
+    let table = polygon_table();
+    let corners = unit_corners();
+    let cube_faces = faces();
+
+    let (leaves, owner) = make_leaves(&samples, settings, &table, size);
+
+    let mut mesh = Mesh::new();
+    let mut debug_geometry = if debug {Some(DebugGeometry::new())} else {None};
+    let mut vertex_of_edge : HashMap<usize, u32> = HashMap::new();
+    let mut face_features : HashMap<(usize, usize, usize), Option<u32>> = HashMap::new();
+
+    for leaf in leaves.iter(){
+        let config = config_of(&samples, &corners, leaf.pos, leaf.size);
+        if config == 0 || config == 255 {continue;}
+        let cube = Square3{center : samples.point(leaf.pos) + Vector3::new(1.0, 1.0, 1.0) * (a * leaf.size as f32 / 2.0), extent : a * leaf.size as f32 / 2.0};
+        if let Some(ref mut geometry) = debug_geometry{
+            geometry.add_square3_bounds(cube, Vector3::new(0.0, 1.0, 0.0));
+        }
+
+        //marching squares on every piece of the leaf faces, segments go from the start of a run of inside corners to its end
+        //(crossing edge, feature point, crossing edge)
+        let mut next : HashMap<usize, (Option<u32>, usize)> = HashMap::new();
+        for (min, len, face_id, normal_axis) in face_pieces(&leaves, &owner, size, leaf){
+            let face = cube_faces[face_id];
+            let mut p = [min;4];
+            for k in 0..4{
+                for axis in (0..3).filter(|&axis| axis != normal_axis){
+                    p[k][axis] += corners[face[k]][axis] * len;
+                }
+            }
+            let inside : Vec<bool> = p.iter().map(|&q| samples.inside(q)).collect();
+            //the grid edge with the sign change on the side from corner `k` to `k + 1`
+            let crossing = |k : usize|{
+                let (q0, q1) = (p[k], p[(k + 1) % 4]);
+                let axis = (0..3).find(|&axis| q0[axis] != q1[axis]).unwrap();
+                let start = if q0[axis] < q1[axis] {q0} else {q1};
+                samples.crossing(start, axis, len).0.unwrap()
+            };
+
+            for k in 0..4{
+                if inside[k] || !inside[(k + 1) % 4] {continue;}
+                let mut j = (k + 1) % 4;
+                while inside[(j + 1) % 4] {j = (j + 1) % 4;}
+                let (from, to) = (crossing(k), crossing(j));
+
+                let key = (std::cmp::min(from, to), std::cmp::max(from, to), normal_axis);
+                let feature = if face_features.contains_key(&key) {face_features[&key]} else{
+                    let (h0, h1) = (samples.planes[&key.0], samples.planes[&key.1]);
+                    let point = face_feature(&h0, &h1, normal_axis, samples.point(min), samples.point(min) + Vector3::new(1.0, 1.0, 1.0) * (a * len as f32), settings.sharpness);
+                    let vertex = point.map(|point| mesh.push_vertex(point, (h0.normal + h1.normal).normalize(), 0));
+                    face_features.insert(key, vertex);
+                    vertex
+                };
+                next.insert(from, (feature, to));
+            }
+        }
+
+        //each loop of segments is one sheet of the surface in the leaf
+        loop{
+            let start = match next.keys().min() {Some(&start) => start, None => break};
+            let mut ring = Vec::new();
+            let mut planes = Vec::new();
+            let mut edge = start;
+            let mut closed = false;
+            while let Some((feature, to)) = next.remove(&edge){
+                let plane = samples.planes[&edge];
+                let vertex = *vertex_of_edge.entry(edge).or_insert_with(|| mesh.push_vertex(plane.point, plane.normal, 0));
+                ring.push(vertex);
+                planes.push(plane);
+                if let Some(feature) = feature {ring.push(feature);}
+                edge = to;
+                if edge == start {closed = true; break;}
+            }
+            if !closed || ring.len() < 3 {continue;}
+
+            let sharp = planes.iter().enumerate().any(|(i, p)| planes[i + 1..].iter().any(|q| p.normal.dot(&q.normal) < settings.sharpness));
+            if !sharp && ring.len() <= 4{
+                if ring.len() == 3{
+                    mesh.push_triangle(ring[0], ring[1], ring[2]);
+                }else{
+                    //split along the shorter diagonal
+                    let v = |i : usize| mesh.vertices[ring[i] as usize];
+                    if (v(0) - v(2)).norm() <= (v(1) - v(3)).norm(){
+                        mesh.push_quad(ring[0], ring[1], ring[2], ring[3]);
+                    }else{
+                        mesh.push_quad(ring[1], ring[2], ring[3], ring[0]);
+                    }
+                }
+                continue;
+            }
+
+            let point = if sharp {feature_point(&planes, &cube)} else{
+                let mass = ring.iter().fold(Vector3::new(0.0, 0.0, 0.0), |acc, &i| acc + mesh.vertices[i as usize]) / ring.len() as f32;
+                project_to_surface(mass, a / 100.0, f)
+            };
+            let normal = planes.iter().fold(Vector3::new(0.0, 0.0, 0.0), |acc, p| acc + p.normal);
+            let center = mesh.push_vertex(point, normal.normalize(), 0);
+            for i in 0..ring.len(){
+                mesh.push_triangle(center, ring[i], ring[(i + 1) % ring.len()]);
+            }
+        }
+    }
+
+    (mesh, debug_geometry)
+}
+
+//a sphere, a torus, a box with sharp edges and corners, then a field full of saddles cut by a sphere
+//all must be closed, the box needs far fewer triangles than marching cubes and keeps its corners
+//leaves merged over the outer side of the torus border the smaller ones around its inner side, their faces are split into the faces of the smaller ones
+pub fn test_cms(){
+    let settings = CmsSettings::new(8);
+    let shapes = TestShapes::new();
+    let (a, size, offset) = (shapes.a, shapes.size, shapes.offset);
+
+    let sphere = shapes.sphere();
+    let (mesh, _) = make_mesh(&sphere, offset, a, size, &settings, false);
+    println!("cms, sphere: {} vertices, {} triangles", mesh.vertex_count(), mesh.triangle_count());
+    marching_cubes::assert_sphere_mesh(&mesh, shapes.center, shapes.rad, a / 4.0);
+
+    //a stricter flatness merges fewer cells of the sphere
+    let (fine, _) = make_mesh(&sphere, offset, a, size, &CmsSettings{flatness : 0.99, ..settings}, false);
+    println!("cms, sphere with flatness 0.99: {} vertices, {} triangles", fine.vertex_count(), fine.triangle_count());
+    marching_cubes::assert_sphere_mesh(&fine, shapes.center, shapes.rad, a / 4.0);
+    assert!(fine.triangle_count() > mesh.triangle_count());
+
+    let torus = shapes.torus();
+    let (mesh, _) = make_mesh(&torus, offset, a, size, &settings, false);
+    println!("cms, torus: {} vertices, {} triangles", mesh.vertex_count(), mesh.triangle_count());
+    marching_cubes::assert_closed(&mesh);
+
+    //the pieces of each face tile it, on a split face each piece is the face of the smaller neighbour touching it
+    let samples = sample(&torus, offset, a, size, settings.accuracy);
+    let (leaves, owner) = make_leaves(&samples, &settings, &polygon_table(), size);
+    let mut split = 0;
+    for leaf in leaves.iter().filter(|leaf| {let config = config_of(&samples, &unit_corners(), leaf.pos, leaf.size); config != 0 && config != 255}){
+        let pieces = face_pieces(&leaves, &owner, size, leaf);
+        for face_id in 0..6{
+            let on_face : Vec<&([usize;3], usize, usize, usize)> = pieces.iter().filter(|piece| piece.2 == face_id).collect();
+            assert_eq!(on_face.iter().map(|piece| piece.1 * piece.1).sum::<usize>(), leaf.size * leaf.size, "face {} of {:?} is not tiled", face_id, leaf);
+            for &&(min, len, _, axis) in on_face.iter().filter(|piece| piece.1 < leaf.size){
+                let mut cell = min;
+                if min[axis] == leaf.pos[axis] {cell[axis] -= 1;}
+                let neighbour = &leaves[owner[(cell[2] * size + cell[1]) * size + cell[0]] as usize];
+                assert!(neighbour.size == len && (0..3).all(|i| i == axis || neighbour.pos[i] == min[i]), "piece {:?} of {:?} is not the face of {:?}", min, leaf, neighbour);
+                split += 1;
+            }
+        }
+    }
+    println!("cms, torus: {} leaves, {} pieces of split faces", leaves.len(), split);
+    assert!(split > 0, "no leaf borders smaller leaves");
+
+    let aabb = shapes.aabb();
+    let (mesh, _) = make_mesh(&aabb, offset, a, size, &settings, false);
+    let mut vg = VoxelGrid3::new(a, size, size, size);
+    dc::fill_in_grid(&mut vg, &aabb, offset);
+    let mc = marching_cubes::make_mesh(&vg);
+    println!("cms, box: {} vertices, {} triangles, marching cubes: {} triangles", mesh.vertex_count(), mesh.triangle_count(), mc.triangle_count());
+    assert!(mesh.triangle_count() * 4 < mc.triangle_count());
+    marching_cubes::assert_box_corners(&mesh, shapes.box_center, shapes.box_extent, a / 10.0);
+
+    let (mesh, _) = make_mesh(&shapes.saddles(), offset, a, size, &settings, false);
+    println!("cms, saddles: {} vertices, {} triangles", mesh.vertex_count(), mesh.triangle_count());
+    marching_cubes::assert_closed(&mesh);
+}
@@ -4,9 +4,10 @@ use std::ffi::CString;
 use std::ffi::CStr;
 use na::{Vector3,Real};
 use ptr;
-use renderer::*;
+use mesh::*;
+use dcm::*;
 use math::*;
-use noise::{NoiseModule, Perlin};
+use terrain::*;
 
 #[derive(Debug)]
 #[repr(C)]
@@ -20,9 +21,9 @@ pub struct Node{
 extern{
     pub fn alloc_grid(size : usize, grid : *mut *mut *mut Node);
     pub fn gen_dense_grid(size : usize, center : Vector3<f32>, extent : f32, dense_grid : *mut *mut Node);
-    pub fn gen_dense_grid_custom(size : usize, center : Vector3<f32>, extent : f32, dense_grid : *mut *mut Node, f : extern fn(&(&Perlin,Square3<f32>), Vector3<f32>) -> f32, perlin : &(&Perlin,Square3<f32>));
+    pub fn gen_dense_grid_custom(size : usize, center : Vector3<f32>, extent : f32, dense_grid : *mut *mut Node, f : extern fn(*const c_void, Vector3<f32>) -> f32, arg : *const c_void);
     pub fn simplify_grid_recursively(size : usize, center : Vector3<f32>, extent : f32, dense_grid : *mut *mut Node) -> *mut *mut Node;
-    pub fn init_noise();
+    pub fn init_noise(seed : i32);
     pub fn print_octree(node : *mut Node, lev : usize);
 }
 
@@ -62,62 +63,54 @@ pub unsafe fn for_each_leaf<A>(root : *mut Node, f : &mut Box<A>, center : Vecto
 }
 
 
-//0 to 1.0
-fn octave_perlin3(perlin : &Perlin, x : f32, y : f32, z : f32, octaves : usize, persistence : f32) -> f32{
-    let mut total = 0.0;
-    let mut frequency = 1.0;
-    let mut amplitude = 1.0;
-    let mut max_value = 0.0;
+//density for the C side, `arg` points to a `&DensityMat3`
+extern fn density_of(arg : *const c_void, x : Vector3<f32>) -> f32{
+    let f = unsafe{&*(arg as *const &DensityMat3)};
+    f.material_point(x).density
+}
 
-    let k = 2.0.powi((octaves - 1) as i32);
+//samples `f` at the centers of `size`^3 cells (`size` - power of two) of the cube `center` +- `extent`, merges equal
+//cells into an octree and returns the leaf cubes whose density at the center is within `threshold` of the surface
+pub unsafe fn mesh_cubic_octree(f : &DensityMat3, center : Vector3<f32>, extent : f32, size : usize, threshold : f32) -> Mesh{
+    let mut dense_grid : *mut *mut Node = ptr::null_mut();
+    alloc_grid(size, &mut dense_grid);
+    gen_dense_grid_custom(size, center, extent, dense_grid, density_of, &f as *const &DensityMat3 as *const c_void);
+    let simplified = simplify_grid_recursively(size, center, extent, dense_grid);
+    //print_octree(*simplified, 0);
 
-    for i in 0..octaves{
-        total += (perlin.get([x * frequency / k, y * frequency / k, z * frequency / k]) + 1.0)/2.0 * amplitude;
-        max_value += amplitude;
-        amplitude *= persistence;
-        frequency *= 2.0;
-    }
+    let mut mesh = Mesh::new();
+    for_each_leaf(*simplified, &mut box |node, center, ext, lev|{
+            if (*node).density.abs() <= threshold{
+                //debug.add_square3_bounds(Square3{center, extent : ext}, Vector3::new(1.0,1.0,1.0));
+                mesh.push_cube(Square3{center, extent : ext}, f.material_point(center).material);
+            }
 
-    total / max_value
-}
+        }, center, extent, 0);
 
+    mesh
+}
 
-pub unsafe fn test_cubic_octree(render : &mut RendererVertFragDef){
-    init_noise();
-    let mut dense_grid : *mut *mut Node = ptr::null_mut();
+pub unsafe fn test_cubic_octree(seed : WorldSeed) -> Mesh{
+    init_noise(seed.derive(NoiseSource::FastNoise) as i32);
     let size = 128;
     let center = Vector3::new(0.0, 0.0, 0.0);
     let extent = 8.0;
 
-    let perlin = Perlin::new();
-
+    let perlin = seed.perlin(NoiseSource::CubicOctree);
+    let cube = Square3{center, extent};
 
-    extern fn f(d : &(&Perlin,Square3<f32>), x : Vector3<f32>) -> f32{
-        let perlin = d.0;
-        let cube = d.1;
+    let f : DenMatFn3<f32> = box move |x|{
         //octave_perlin3(&perlin, v.x, v.y, v.z, 8, 0.7)
-        if point3_inside_square3_inclusive(&x, &cube){
-            let den = -octave_perlin3(perlin, x.x - (cube.center.x - cube.extent), x.y - (cube.center.y - cube.extent), x.z - (cube.center.z - cube.extent), 4, 0.56) * 2.0 * cube.extent;
+        let density = if point3_inside_square3_inclusive(&x, &cube){
+            let den = -octave_perlin3(&perlin, x.x - (cube.center.x - cube.extent), x.y - (cube.center.y - cube.extent), x.z - (cube.center.z - cube.extent), 4, 0.56) * 2.0 * cube.extent;
             let dy = (x.y - (cube.center.y - cube.extent) ); //cube.extent / 2.0 ; // 0 - 1
             //println!("{} {} {}", den, dy, x.y);
             den + dy
         }else{
             0.01
-        }
-    }
-
-    alloc_grid(size, &mut dense_grid);
-    gen_dense_grid_custom(size, center, extent, dense_grid, f, &(&perlin,Square3{center,extent}));
-    let simplified = simplify_grid_recursively(size, center, extent, dense_grid);
-    //print_octree(*simplified, 0);
+        };
+        MaterialPoint{density, material : 0}
+    };
 
-
-    for_each_leaf(*simplified, &mut box |node, center, ext, lev|{
-            if (*node).density.abs() <= 0.5{
-                //add_square3_bounds_color(render, Square3{center, extent : ext}, Vector3::new(1.0,1.0,1.0));
-                add_cube_color_normal(render, Square3{center, extent : ext}, Vector3::new(0.6, 0.4, 0.4));
-            }
-
-        }, center, extent, 0);
+    mesh_cubic_octree(&f, center, extent, size, 0.5)
 }
-
@@ -1,8 +1,11 @@
 use std;
 use na::*;
 use math::*;
-use renderer::*;
+use density::*;
+use mesh::*;
 use alga::general::SupersetOf;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
 
 pub struct VoxelGrid3<T : Real + Copy>{
     pub a : T,
@@ -92,7 +95,7 @@ fn sample_qef_brute(square : Square3<f32>, n : usize, planes : &Vec<Plane<f32>>)
 //so the algorithm should use some interpolation methods assuming the surface is smooth(does not change too much within one cube of the grid)
 //interpolation can operate on 8 corner vertices of the cube
 //TODO or maybe save generator to disk ??, in case of random(presudo-random) generator - its seed can be saved
-fn sample_intersection_brute(line : Line3<f32>, n_ : usize, f : &DenFn3<f32>) -> Vector3<f32>{
+fn sample_intersection_brute<D : Density3>(line : Line3<f32>, n_ : usize, f : &D) -> Vector3<f32>{
     let ext = line.end - line.start;
     let norm = ext.norm();
     let dir = ext / norm;
@@ -108,8 +111,8 @@ fn sample_intersection_brute(line : Line3<f32>, n_ : usize, f : &DenFn3<f32>) ->
     for i in 0..n {
         let point1 = center - dir * cur_ext;
         let point2 = center + dir * cur_ext;
-        let den1 = f(point1).abs();
-        let den2 = f(point2).abs();
+        let den1 = f.density(point1).abs();
+        let den2 = f.density(point2).abs();
 
         if den1 <= den2 {
             center = point1;
@@ -124,16 +127,14 @@ fn sample_intersection_brute(line : Line3<f32>, n_ : usize, f : &DenFn3<f32>) ->
 
 
 //why haven't I come up with this one at the start ? :)
-pub fn sample_normal(point : &Vector3<f32>, eps : f32, f : &DenFn3<f32>) -> Vector3<f32>{
-    Vector3::new( f(Vector3::new(point.x + eps, point.y, point.z)) - f(Vector3::new(point.x - eps, point.y, point.z)),
-                  f(Vector3::new(point.x, point.y + eps, point.z)) - f(Vector3::new(point.x, point.y - eps, point.z)),
-                  f(Vector3::new(point.x, point.y, point.z + eps)) - f(Vector3::new(point.x, point.y, point.z - eps)) ).normalize()
+pub fn sample_normal<D : Density3>(point : &Vector3<f32>, eps : f32, f : &D) -> Vector3<f32>{
+    surface_normal(f, point, eps) //exact gradient if `f` provides one, central differences otherwise
 }
 
 //works not so well
-pub fn sample_normal1(sphere : &Sphere<f32>, n : usize, f : &DenFn3<f32>) -> Vector3<f32>{
+pub fn sample_normal1<D : Density3>(sphere : &Sphere<f32>, n : usize, f : &D) -> Vector3<f32>{
 
-    let den_at_center = f(sphere.center);
+    let den_at_center = f.density(sphere.center);
 
     let mut best = 0.0;
     let mut normal_point = sphere.center;
@@ -153,7 +154,7 @@ pub fn sample_normal1(sphere : &Sphere<f32>, n : usize, f : &DenFn3<f32>) -> Vec
             let z = -slice1.sin() * slice2.sin().abs() * sphere.rad;
 
             let point = sphere.center + Vector3::new(x,y,z);
-            let den = f(point);
+            let den = f.density(point);
             let attempt = den - den_at_center;
             if attempt > best{
                 best = attempt;
@@ -176,12 +177,72 @@ pub fn test_sample_normal(){
     println!("{}", res); //result should approach {0.0,0.0,1.0} increasing accuracy
 }
 
+//the grid and the contour must not depend on how many threads sample them
+pub fn test_thread_count_determinism(){
+    let f = DenNode::sdf_sphere(Sphere{center : Vector3::new(2.0, 2.0, 2.0), rad : 1.3}).smooth_union(DenNode::sdf_aabb(Vector3::new(2.5, 1.5, 2.0), Vector3::new(1.0, 0.4, 1.2)), 0.3, BlendKind::Polynomial);
+
+    let mut results = Vec::new();
+    for &threads in [1, 4].iter(){
+        let pool = ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+        let (grid, mesh) = pool.install(|| {
+            let mut vg = VoxelGrid3::new(0.125, 32, 32, 32);
+            fill_in_grid(&mut vg, &f, Vector3::new(0.0, 0.0, 0.0));
+            let contour = make_contour(&vg, &f, 8);
+            (vg.grid, contour.mesh)
+        });
+        println!("{} threads: {} vertices, {} triangles", threads, mesh.vertex_count(), mesh.triangle_count());
+        results.push((grid, mesh));
+    }
+
+    assert!(results[0].0 == results[1].0);
+    assert!(results[0].1.vertices == results[1].1.vertices && results[0].1.indices == results[1].1.indices);
+}
+
+//culling keeps the signs of the whole grid, and next to the surface (the vertices of cells with a sign change and their neighbours)
+//the same densities as sampling every vertex of a density without bounds, the exact fill gives them everywhere
+pub fn test_culled_grid(){
+    let node = DenNode::sdf_sphere(Sphere{center : Vector3::new(2.0, 2.0, 2.0), rad : 1.3}).union(DenNode::aabb(Vector3::new(1.0, 2.5, 2.0), Vector3::new(0.8, 0.3, 0.6)));
+    let unbounded = node.clone().to_den_fn();
+    let (mut culled, mut sampled) = (VoxelGrid3::new(0.125, 32, 32, 32), VoxelGrid3::new(0.125, 32, 32, 32));
+    fill_in_grid(&mut culled, &node, Vector3::new(0.0, 0.0, 0.0));
+    fill_in_grid(&mut sampled, &unbounded, Vector3::new(0.0, 0.0, 0.0));
+
+    let n = culled.vertices_x();
+    let mut near = vec![false;n * n * n];
+    for z in 0..32{
+        for y in 0..32{
+            for x in 0..32{
+                let inside = |dx : usize, dy : usize, dz : usize| sampled.get(x + dx, y + dy, z + dz) < 0.0;
+                if (0..8).all(|i| inside(i & 1, (i >> 1) & 1, i >> 2) == inside(0, 0, 0)) {continue;}
+                //the cell, its vertices and their neighbours
+                for vz in z.saturating_sub(1)..std::cmp::min(z + 3, n){
+                    for vy in y.saturating_sub(1)..std::cmp::min(y + 3, n){
+                        for vx in x.saturating_sub(1)..std::cmp::min(x + 3, n){
+                            near[(vz * n + vy) * n + vx] = true;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let exact = culled.grid.iter().zip(sampled.grid.iter()).enumerate().filter(|&(i, _)| near[i]).all(|(_, (c, s))| c == s);
+    let signs = culled.grid.iter().zip(sampled.grid.iter()).all(|(c, s)| (*c < 0.0) == (*s < 0.0));
+    println!("culled grid: {} vertices next to the surface", near.iter().filter(|n| **n).count());
+    assert!(exact && signs);
+
+    let mut all = VoxelGrid3::new(0.125, 32, 32, 32);
+    fill_in_grid_exact(&mut all, &node, Vector3::new(0.0, 0.0, 0.0));
+    assert!(all.grid == sampled.grid, "exact fill skipped vertices");
+}
+
 //voxel grid is an array like structure (in the feature it should be upgraded to an octree) that contains density information at each vertex of each cube of the grid
 
 //feature is a vertex that may or may not be calculated for each cube of the grid. It is calculated for each cube that exhibits a sign change(this means that the cube
 // intersects the surface) and not calculated otherwise
-fn calc_feature(vg : &VoxelGrid3<f32>, x : usize, y : usize, z : usize,
-               f : &DenFn3<f32>, accuracy : usize, contour_data : &mut ContourData, debug_render : &mut RendererVertFragDef) -> Option<Vector3<f32>>{
+//feature vertex of the cell and the surface normal at it
+fn calc_feature<D : Density3>(vg : &VoxelGrid3<f32>, x : usize, y : usize, z : usize,
+               f : &D, accuracy : usize) -> Option<(Vector3<f32>, Vector3<f32>)>{
     //let epsilon = vg.a / accuracy as f32;
 
     let p00 = vg.get(x, y, z);
@@ -279,202 +340,154 @@ fn calc_feature(vg : &VoxelGrid3<f32>, x : usize, y : usize, z : usize,
         //println!("{:?} {}", normals.as_slice(), A);
 
         let feature_vertex = sample_qef_brute(vg.square3(x, y, z), accuracy, &planes);
-                
-
-        let t = z * vg.size_y * vg.size_x + y * vg.size_x + x;
-
-        contour_data.features[t] = Some(feature_vertex);
-        //contour_data.normals[t] = Some(sample_normal(&Sphere{center : feature_vertex, rad : rad_for_normal}, accuracy, f));
-        contour_data.normals[t] = Some(sample_normal(&feature_vertex, vg.a / 100.0, f));
-
 
-        Some(feature_vertex)
+        //let normal = sample_normal(&Sphere{center : feature_vertex, rad : rad_for_normal}, accuracy, f);
+        Some((feature_vertex, sample_normal(&feature_vertex, vg.a / 100.0, f)))
         
     }else{
         None
     }
 }
 
-//TODO debug_renderer is for debug only
-pub fn make_contour(vg : &VoxelGrid3<f32>, f : &DenFn3<f32>, accuracy : usize, debug_renderer : &mut RendererVertFragDef) -> ContourData{
-
-    //TODO inefficient Vec::new() creation vvv
-    let mut contour_data = ContourData{lines : Vec::new(), triangles : Vec::new(), triangle_normals : Vec::new(), features : vec![None;vg.size_x * vg.size_y * vg.size_z], normals : vec![None;vg.size_x * vg.size_y * vg.size_z]};
-    let mut cache_already_calculated = vec![false;vg.size_x * vg.size_y * vg.size_z]; //this cache is used to mark cubes that have already been calculated for feature vertex
-
-    {
-        //&mut contour_data, cache_already_calculated
-        let mut cached_make = |x: usize, y: usize, z : usize, contour_data : &mut ContourData| -> Option<Vector3<f32>>{
-            let t = z * vg.size_y * vg.size_x + y * vg.size_x + x;
+//two triangles of the quad between the feature vertices `i0`..`i3`, facing along `normal` (normal at `i0`)
+pub fn push_quad(vertices : &[Vector3<f32>], i0 : u32, i1 : u32, i2 : u32, i3 : u32, normal : &Vector3<f32>, indices : &mut Vec<u32>){
+    let (f0, f2, f3) = (vertices[i0 as usize], vertices[i2 as usize], vertices[i3 as usize]);
+    //this is needed to calculate the direction of the resulting quad correctly
+    let dir = (f2 - f0).cross(&(f3 - f0));
+    let quad = if dir.dot(normal) > 0.0 {[i0, i2, i3, i0, i1, i2]} else {[i0, i3, i2, i0, i2, i1]}; //should not be zero at any time
+    indices.extend_from_slice(&quad);
+}
 
-            if cache_already_calculated[t] {
-                contour_data.features[t]
-            }else{
-                cache_already_calculated[t] = true;
-                calc_feature(&vg, x, y, z, f, accuracy, contour_data, debug_renderer)
+//feature vertices are computed for all cells first, then quads are built, both in parallel z-slabs
+//slabs are concatenated in z order so the output does not depend on the number of threads
+//each feature point becomes one mesh vertex shared by all quads around it
+pub fn make_contour<D : Density3>(vg : &VoxelGrid3<f32>, f : &D, accuracy : usize) -> ContourData{
+    let slab = vg.size_x * vg.size_y;
+    let mut features = vec![None;slab * vg.size_z];
+    let mut normals = vec![None;slab * vg.size_z];
+
+    features.par_chunks_mut(slab).zip(normals.par_chunks_mut(slab)).enumerate().for_each(|(z, (features, normals))|{
+        for y in 0..vg.size_y{
+            for x in 0..vg.size_x{
+                if let Some((feature, normal)) = calc_feature(vg, x, y, z, f, accuracy){
+                    features[y * vg.size_x + x] = Some(feature);
+                    normals[y * vg.size_x + x] = Some(normal);
+                }
             }
+        }
+    });
 
+    let mut mesh = Mesh::new();
+    let mut vertex_of_cell = vec![0u32;slab * vg.size_z];
+    for t in 0..features.len(){
+        if let Some(feature) = features[t]{
+            vertex_of_cell[t] = mesh.push_vertex(feature, normals[t].unwrap(), 0);
+        }
+    }
 
-        };
-
-        for z in 0..vg.size_z{
-            for y in 0..vg.size_y {
-                for x in 0..vg.size_x {
-                    //let p00 = vg.get(x, y, z);
-                    //let p01 = vg.get(x + 1, y, z);
-                    //let p02 = vg.get(x, y + 1, z);
-                    let p03 = vg.get(x + 1, y + 1, z);
-
-                    //let p10 = vg.get(x, y, z + 1);
-                    let p11 = vg.get(x + 1, y, z + 1);
-                    let p12 = vg.get(x, y + 1, z + 1);
-                    let p13 = vg.get(x + 1, y + 1, z + 1);
-
-                    /*let v00 = vg.get_point(x, y, z);
-                    let v01 = vg.get_point(x + 1, y, z);
-                    let v02 = vg.get_point(x, y + 1, z);
-                    let v03 = vg.get_point(x + 1, y + 1, z);
-
-                    let v10 = vg.get_point(x,y, z + 1);
-                    let v11 = vg.get_point(x + 1, y, z + 1);
-                    let v12 = vg.get_point(x, y + 1, z + 1);
-                    let v13 = vg.get_point(x + 1, y + 1, z + 1);*/
-
-
-                    let possible_feature_vertex = cached_make(x, y, z, &mut contour_data);
-
-                    match possible_feature_vertex{
-                        None => (),
-                        Some(f0) => {
-                            let t = z * vg.size_y * vg.size_x + y * vg.size_x + x;
-                            let normal = contour_data.normals[t].unwrap();
-
-
-                            
-                            
-                            //TODO incorrect normals in some places 
-                            if !const_sign(p03, p13){
-
-                                let f1 = cached_make(x + 1, y, z, &mut contour_data).unwrap();
-                                let f2 = cached_make(x + 1, y + 1, z, &mut contour_data).unwrap();
-                                let f3 = cached_make(x, y + 1, z, &mut contour_data).unwrap();
-                                //f1 && f2 && f3 all should be non-empty, as they all exhibit a sign change at least on their common edge
-
-                                //this is needed to calculate the direction of the resulting quad correctly
-                                let dir = (f2 - f0).cross(&(f3 - f0)).normalize();
-                                
-                                if dir.dot(&normal) > 0.0{ //should not be zero at any time
-                                    contour_data.triangles.push(Triangle3{p1 : f0, p2 : f2, p3 : f3});
-                                    contour_data.triangles.push(Triangle3{p1 : f0, p2 : f1, p3 : f2});
-                                    //add_line3_color(debug_renderer, Line3{start : f0, end : f0 + dir * 0.1}, Vector3::new(1.0, 1.0, 1.0));
-                                    //TODO debug
-                                    /* if (dir.dot(&debug_real_normal) <= 0.0) {
-                                        println!("bad normal at {} {} {} {}", 1, x, y, z);
-                                    } */
-                                    contour_data.triangle_normals.push(dir); //TODO inefficient
-                                }else{
-                                    contour_data.triangles.push(Triangle3{p1 : f0, p2 : f3, p3 : f2});
-                                    contour_data.triangles.push(Triangle3{p1 : f0, p2 : f2, p3 : f1});
-                                    //add_line3_color(debug_renderer, Line3{start : f0, end : f0 - dir * 0.1}, Vector3::new(1.0, 1.0, 1.0));
-                                   /*  if (-dir.dot(&debug_real_normal) <= 0.0) {
-                                        add_square3_bounds_color(debug_renderer, vg.square3(x, y, z), Vector3::new(1.0, 0.0, 0.0));
-                                        add_line3_color(debug_renderer, Line3{start : f0, end : f0 - dir * 0.1}, Vector3::new(1.0, 1.0, 1.0));
-                                        add_line3_color(debug_renderer, Line3{start : f0, end : f0 - normal.normalize()}, Vector3::new(0.0, 0.0, 0.0));
-                                        println!("bad normal at {} {}", 2, -dir.dot(&debug_real_normal));
-                                    } */
-                                    contour_data.triangle_normals.push(-dir);
-                                }
-                            }
-                            if !const_sign(p12, p13){
-                                let f1 = cached_make(x, y, z + 1, &mut contour_data).unwrap();
-                                let f2 = cached_make(x, y + 1, z + 1, &mut contour_data).unwrap();
-                                let f3 = cached_make(x, y + 1, z, &mut contour_data).unwrap();
-                                //f1 && f2 && f3 all should be non-empty, as they all exhibit a sign change at least on their common edge
-
-                                //this is needed to calculate the direction of the resulting quad correctly
-                                let dir = (f2 - f0).cross(&(f3 - f0)).normalize();
-                                if dir.dot(&normal) > 0.0{ //should not be zero at any time
-                                    contour_data.triangles.push(Triangle3{p1 : f0, p2 : f2, p3 : f3});
-                                    contour_data.triangles.push(Triangle3{p1 : f0, p2 : f1, p3 : f2});
-                                    //add_line3_color(debug_renderer, Line3{start : f0, end : f0 + dir * 0.1}, Vector3::new(1.0, 1.0, 1.0));
-                                   /*  if (dir.dot(&debug_real_normal) <= 0.0) {
-                                        println!("bad normal at {} {} {} {}", 3, x, y, z);
-                                    } */
-                                    contour_data.triangle_normals.push(dir);
-                                }else{
-                                    contour_data.triangles.push(Triangle3{p1 : f0, p2 : f3, p3 : f2});
-                                    contour_data.triangles.push(Triangle3{p1 : f0, p2 : f2, p3 : f1});
-                                    //add_line3_color(debug_renderer, Line3{start : f0, end : f0 - dir * 0.1}, Vector3::new(1.0, 1.0, 1.0));
-                                   /*  if (-dir.dot(&debug_real_normal) <= 0.0) {
-                                        add_square3_bounds_color(debug_renderer, vg.square3(x, y, z), Vector3::new(1.0, 0.0, 0.0));
-                                        add_line3_color(debug_renderer, Line3{start : f0, end : f0 - dir * 0.1}, Vector3::new(1.0, 1.0, 1.0));
-                                        add_line3_color(debug_renderer, Line3{start : f0, end : f0 - normal.normalize()}, Vector3::new(0.0, 0.0, 0.0));
-                                        
-                                        println!("bad normal at {} {}", 4, -dir.dot(&debug_real_normal));
-                                    } */
-                                    contour_data.triangle_normals.push(-dir);
-                                }
-                            }
-                            if !const_sign(p11, p13){
-                                let f1 = cached_make(x + 1, y, z, &mut contour_data).unwrap();
-                                let f2 = cached_make(x + 1, y, z + 1, &mut contour_data).unwrap();
-                                let f3 = cached_make(x, y, z + 1, &mut contour_data).unwrap();
-                                //f1 && f2 && f3 all should be non-empty, as they all exhibit a sign change at least on their common edge
-
-                                //this is needed to calculate the direction of the resulting quad correctly
-                                let dir = (f2 - f0).cross(&(f3 - f0)).normalize();
-                                if dir.dot(&normal) > 0.0{ //should not be zero at any time
-                                    contour_data.triangles.push(Triangle3{p1 : f0, p2 : f2, p3 : f3});
-                                    contour_data.triangles.push(Triangle3{p1 : f0, p2 : f1, p3 : f2});
-                                    //add_line3_color(debug_renderer, Line3{start : f0, end : f0 + dir * 0.1}, Vector3::new(1.0, 1.0, 1.0));
-                                   /*  if (dir.dot(&debug_real_normal) <= 0.0) {
-                                        println!("bad normal at {} {} {} {}", 5, x, y, z);
-                                    } */
-                                    contour_data.triangle_normals.push(dir);
-                                } else{
-                                    contour_data.triangles.push(Triangle3{p1 : f0, p2 : f3, p3 : f2});
-                                    contour_data.triangles.push(Triangle3{p1 : f0, p2 : f2, p3 : f1});
-                                    //add_line3_color(debug_renderer, Line3{start : f0, end : f0 - dir * 0.1}, Vector3::new(1.0, 1.0, 1.0));
-                                   /*  if (-dir.dot(&debug_real_normal) <= 0.0) {
-                                        add_square3_bounds_color(debug_renderer, vg.square3(x, y, z), Vector3::new(1.0, 0.0, 0.0));
-                                        add_line3_color(debug_renderer, Line3{start : f0, end : f0 - dir * 0.1}, Vector3::new(1.0, 1.0, 1.0));
-                                        println!("bad normal at {} {} {} {}", 6, x, y, z);
-                                    } */
-                                    contour_data.triangle_normals.push(-dir);
-                                } 
-                            }
-                        },
-                    }
-
-
-
+    let slabs : Vec<Vec<u32>> = (0..vg.size_z - 1).into_par_iter().map(|z|{ //the last layer of cells has no neighbours to build quads with
+        let mut indices = Vec::new();
+        //f1 && f2 && f3 all should be non-empty, as they all exhibit a sign change at least on their common edge
+        let vertex = |x : usize, y : usize, z : usize| vertex_of_cell[z * slab + y * vg.size_x + x];
+
+        for y in 0..vg.size_y - 1 {
+            for x in 0..vg.size_x - 1 {
+                let t = z * slab + y * vg.size_x + x;
+                if features[t].is_none() {continue;}
+                let v0 = vertex_of_cell[t];
+                let normal = normals[t].unwrap();
+
+                let p03 = vg.get(x + 1, y + 1, z);
+                let p11 = vg.get(x + 1, y, z + 1);
+                let p12 = vg.get(x, y + 1, z + 1);
+                let p13 = vg.get(x + 1, y + 1, z + 1);
+
+                //TODO incorrect normals in some places
+                if !const_sign(p03, p13){
+                    push_quad(&mesh.vertices, v0, vertex(x + 1, y, z), vertex(x + 1, y + 1, z), vertex(x, y + 1, z), &normal, &mut indices);
+                }
+                if !const_sign(p12, p13){
+                    push_quad(&mesh.vertices, v0, vertex(x, y, z + 1), vertex(x, y + 1, z + 1), vertex(x, y + 1, z), &normal, &mut indices);
+                }
+                if !const_sign(p11, p13){
+                    push_quad(&mesh.vertices, v0, vertex(x + 1, y, z), vertex(x + 1, y, z + 1), vertex(x, y, z + 1), &normal, &mut indices);
                 }
             }
         }
 
+        indices
+    }).collect();
+
+    for indices in slabs{
+        mesh.indices.extend(indices);
     }
 
-    contour_data
+    ContourData{lines : Vec::new(), mesh, features, normals}
+}
+
+//densities of `f` at the grid vertices for meshing: if `f` has bounds, vertices away from the surface get a bound with the right sign
+//instead of a density, so only the signs and the values next to the surface can be relied on
+pub fn fill_in_grid<D : Density3>(vg : &mut VoxelGrid3<f32>, f : &D, offset : Vector3<f32>){
+    fill(vg, f, offset, true)
+}
 
+//densities of `f` at every grid vertex, for grids that are read as densities later (`sampled_grid::SampledGrid`)
+pub fn fill_in_grid_exact<D : Density3>(vg : &mut VoxelGrid3<f32>, f : &D, offset : Vector3<f32>){
+    fill(vg, f, offset, false)
 }
 
-pub fn fill_in_grid(vg : &mut VoxelGrid3<f32>, f : &DenFn3<f32>, offset : Vector3<f32>){
-    for z in 0..vg.size_z + 1 {
-        for y in 0..vg.size_y + 1{
-            for x in 0..vg.size_x + 1 {
-                let vx = vg.vertices_x();
-                let vy = vg.vertices_y();
-                vg.grid[z * vy * vx + y * vx + x] = f(offset + Vector3::new(vg.a * (x as f32), vg.a * (y as f32), vg.a * (z as f32)));
+fn fill<D : Density3>(vg : &mut VoxelGrid3<f32>, f : &D, offset : Vector3<f32>, cull : bool){
+    let size = [vg.size_x, vg.size_y, vg.size_z];
+    let culled = if cull {cull_cells(f, offset, vg.a, size)} else {None};
+
+    //culled values are bounds with the right sign, not densities, so a vertex is given one only if every cell around it is culled
+    //and so are the cells around its neighbours: differences of neighbouring vertices (normals, interpolation) next to the surface stay exact
+    let known = |x : usize, y : usize, z : usize| -> Option<f32>{
+        let cells = culled.as_ref()?;
+        let mut value = None;
+        for cz in z.saturating_sub(2)..std::cmp::min(z + 2, size[2]){
+            for cy in y.saturating_sub(2)..std::cmp::min(y + 2, size[1]){
+                for cx in x.saturating_sub(2)..std::cmp::min(x + 2, size[0]){
+                    match cells[cz * size[1] * size[0] + cy * size[0] + cx]{
+                        Some(v) => if (cx + 1 >= x && cx <= x) && (cy + 1 >= y && cy <= y) && (cz + 1 >= z && cz <= z) {value = Some(v)},
+                        None => return None,
+                    }
+                }
             }
         }
-    }
+        value
+    };
+
+    //one z layer of vertices per task
+    let (a, vx, vy) = (vg.a, vg.vertices_x(), vg.vertices_y());
+    vg.grid.par_chunks_mut(vx * vy).enumerate().for_each(|(z, layer)|{
+        //the unknown vertices of the layer are sampled in one batch
+        let mut indices = Vec::with_capacity(vx * vy);
+        let mut points = Vec::with_capacity(vx * vy);
+        for y in 0..vy{
+            for x in 0..vx{
+                match known(x, y, z){
+                    Some(value) => layer[y * vx + x] = value,
+                    None => {
+                        indices.push(y * vx + x);
+                        points.push(offset + Vector3::new(a * (x as f32), a * (y as f32), a * (z as f32)));
+                    },
+                }
+            }
+        }
+
+        let mut densities = vec![0.0;points.len()];
+        f.density_batch(&points, &mut densities);
+        for (&i, &density) in indices.iter().zip(densities.iter()){
+            layer[i] = density;
+        }
+    });
 }
 
 
 pub struct ContourData{ // + hermite data ? (exact points of intersection of the surface with each edge that exhibits a sign change + normals for each of those points)
     pub lines : Vec<Line3<f32>>,
-    pub triangles : Vec<Triangle3<f32>>,
-    pub triangle_normals : Vec<Vector3<f32>>,
+    pub mesh : Mesh,
     pub features : Vec<Option<Vector3<f32>>>,
     pub normals : Vec<Option<Vector3<f32>>>, //normal to the surface calculated at feature vertex
 }
\ No newline at end of file
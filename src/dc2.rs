@@ -0,0 +1,360 @@
+use std;
+use na::*;
+use math::*;
+use renderer::*;
+use alga::general::SupersetOf;
+
+//2D contouring of `DenFn2`, a quick test bed for algorithm changes before they are tried in 3D
+//dual contouring (one QEF minimizing vertex per sign changing cell) and marching squares (vertices on the edges of the cells)
+//both produce the contour as `Line2` segments and the solid region (negative density) as `Triangle2`
+
+pub struct VoxelGrid2<T : Real + Copy>{
+    pub a : T,
+    pub size_x : usize,
+    pub size_y : usize,
+    pub offset : Vector2<T>, //position of the (0, 0) vertex
+    pub grid : Vec<T>,
+}
+
+impl<T : Real + SupersetOf<f32>> VoxelGrid2<T>{
+
+    pub fn vertices_x(&self) -> usize {self.size_x + 1}
+    pub fn vertices_y(&self) -> usize {self.size_y + 1}
+
+    pub fn new(a : T, size_x : usize, size_y : usize, offset : Vector2<T>) -> VoxelGrid2<T>{
+        let grid = vec![convert(0.0);(size_x + 1) * (size_y + 1)];
+
+        VoxelGrid2{a, size_x, size_y, offset, grid}
+    }
+
+    pub fn get(&self, x : usize, y : usize) -> T{
+        self.grid[y * self.vertices_x() + x]
+    }
+
+    pub fn set(&mut self, x : usize, y : usize, value : T){
+        let vx = self.vertices_x();
+        self.grid[y * vx + x] = value;
+    }
+
+    pub fn get_point(&self, x : usize, y : usize) -> Vector2<T>{
+        self.offset + Vector2::new(self.a * convert::<f32, T>(x as f32), self.a * convert::<f32, T>(y as f32))
+    }
+
+    //bounding box of the cell
+    pub fn square2(&self, x : usize, y : usize) -> Square2<T>{
+        Square2{center : self.offset + Vector2::new(convert::<f32,T>(x as f32 + 0.5) * self.a, convert::<f32,T>(y as f32 + 0.5) * self.a), extent: self.a / convert(2.0)}
+    }
+}
+
+pub struct ContourData2{
+    pub lines : Vec<Line2<f32>>,
+    pub triangles : Vec<Triangle2<f32>>,
+    pub features : Vec<Option<Vector2<f32>>>, //one per cell, dual contouring only (marching squares leaves it empty)
+}
+
+pub fn fill_in_grid(vg : &mut VoxelGrid2<f32>, f : &DenFn2<f32>){
+    for y in 0..vg.vertices_y(){
+        for x in 0..vg.vertices_x(){
+            let point = vg.get_point(x, y);
+            vg.set(x, y, f(point));
+        }
+    }
+}
+
+fn const_sign(a : f32, b : f32) -> bool {
+    if a > 0.0 { b > 0.0} else {b <= 0.0}
+}
+
+//corners of the cell in counter clockwise order, edge `i` goes from corner `i` to corner `(i + 1) % 4`
+fn cell_corners(vg : &VoxelGrid2<f32>, x : usize, y : usize) -> ([Vector2<f32>;4], [f32;4]){
+    ([vg.get_point(x, y), vg.get_point(x + 1, y), vg.get_point(x + 1, y + 1), vg.get_point(x, y + 1)],
+     [vg.get(x, y), vg.get(x + 1, y), vg.get(x + 1, y + 1), vg.get(x, y + 1)])
+}
+
+//bit `i` is set if edge `i` exhibits a sign change
+fn sign_changes(densities : &[f32;4]) -> usize{
+    let mut sit = 0;
+    for i in 0..4{
+        if !const_sign(densities[i], densities[(i + 1) % 4]) {sit |= 1 << i;}
+    }
+
+    sit
+}
+
+//binary search for the sign change, `accuracy` is the number of subdivisions of the edge
+fn sample_intersection(line : Line2<f32>, accuracy : usize, f : &DenFn2<f32>) -> Vector2<f32>{
+    let mut start = line.start;
+    let mut end = line.end;
+    let start_negative = f(start) <= 0.0;
+
+    let n = (accuracy as f32).log2() as usize + 1;
+
+    for _ in 0..n{
+        let mid = (start + end) * 0.5;
+        if (f(mid) <= 0.0) == start_negative {
+            start = mid;
+        }else{
+            end = mid;
+        }
+    }
+
+    (start + end) * 0.5
+}
+
+pub fn sample_normal2(point : &Vector2<f32>, eps : f32, f : &DenFn2<f32>) -> Vector2<f32>{
+    let dx = Vector2::new(eps, 0.0);
+    let dy = Vector2::new(0.0, eps);
+    let grad = Vector2::new(f(point + dx) - f(point - dx), f(point + dy) - f(point - dy));
+    let norm = grad.norm();
+
+    if norm > 0.0 {grad / norm} else {grad}
+}
+
+//minimizes sum (n . (x - p))^2 over the (point, normal) pairs
+//the 2x2 normal equations are solved with a truncated pseudo inverse around the mass point,
+//so parallel tangents (a straight contour) give the point on the line closest to the mass point instead of blowing up
+pub fn solve_qef2(hermite : &Vec<(Vector2<f32>, Vector2<f32>)>, bounds : &Square2<f32>) -> Vector2<f32>{
+    let mut masspoint = Vector2::new(0.0, 0.0);
+    let mut ata = Matrix2::new(0.0, 0.0, 0.0, 0.0);
+    let mut atb = Vector2::new(0.0, 0.0);
+
+    for &(p, n) in hermite{
+        masspoint += p;
+        ata += n * n.transpose();
+        atb += n * n.dot(&p);
+    }
+    masspoint /= hermite.len() as f32;
+
+    let rhs = atb - ata * masspoint;
+
+    //eigen decomposition of the symmetric 2x2 matrix
+    let (a, b, c) = (ata[(0, 0)], ata[(0, 1)], ata[(1, 1)]);
+    let half_trace = (a + c) / 2.0;
+    let disc = (((a - c) / 2.0) * ((a - c) / 2.0) + b * b).sqrt();
+    let eigenvalues = [half_trace + disc, half_trace - disc];
+    let v1 = if b.abs() > 1e-6 {
+        Vector2::new(eigenvalues[0] - c, b).normalize()
+    }else if a >= c {
+        Vector2::new(1.0, 0.0)
+    }else{
+        Vector2::new(0.0, 1.0)
+    };
+    let eigenvectors = [v1, Vector2::new(-v1.y, v1.x)];
+
+    let mut minimizer = masspoint;
+    for i in 0..2{
+        if eigenvalues[i] > eigenvalues[0] * 0.1 && eigenvalues[i] > 1e-6{ //drop near singular directions
+            minimizer += eigenvectors[i] * (eigenvectors[i].dot(&rhs) / eigenvalues[i]);
+        }
+    }
+
+    //keep the vertex inside its cell
+    let min = bounds.center - Vector2::new(bounds.extent, bounds.extent);
+    let max = bounds.center + Vector2::new(bounds.extent, bounds.extent);
+    Vector2::new(Real::min(Real::max(minimizer.x, min.x), max.x), Real::min(Real::max(minimizer.y, min.y), max.y))
+}
+
+//feature vertex of the cell plus the solid part of the cell as a fan around it
+fn make_vertex(vg : &VoxelGrid2<f32>, x : usize, y : usize, f : &DenFn2<f32>, accuracy : usize, triangles : &mut Vec<Triangle2<f32>>) -> Option<Vector2<f32>>{
+    let (corners, densities) = cell_corners(vg, x, y);
+    let sit = sign_changes(&densities);
+
+    if sit == 0 {return None;}
+
+    let eps = vg.a / 100.0;
+    let mut hermite = Vec::with_capacity(4);
+    let mut fan = Vec::with_capacity(4); //(a, b) pairs, each forms a triangle with the feature vertex
+
+    for i in 0..4{
+        let j = (i + 1) % 4;
+        if (sit & (1 << i)) > 0{
+            let ip = sample_intersection(Line2{start : corners[i], end : corners[j]}, accuracy, f);
+            hermite.push((ip, sample_normal2(&ip, eps, f)));
+
+            if densities[i] <= 0.0 {fan.push((corners[i], ip));} else {fan.push((ip, corners[j]));}
+        }else if densities[i] <= 0.0{
+            fan.push((corners[i], corners[j]));
+        }
+    }
+
+    let vertex = solve_qef2(&hermite, &vg.square2(x, y));
+
+    for (a, b) in fan{
+        triangles.push(Triangle2{p1 : vertex, p2 : a, p3 : b});
+    }
+
+    Some(vertex)
+}
+
+fn add_solid_cell(corners : &[Vector2<f32>;4], triangles : &mut Vec<Triangle2<f32>>){
+    triangles.push(Triangle2{p1 : corners[0], p2 : corners[1], p3 : corners[2]});
+    triangles.push(Triangle2{p1 : corners[0], p2 : corners[2], p3 : corners[3]});
+}
+
+//2D dual contouring: a QEF minimizing vertex in each cell that exhibits a sign change,
+//vertices of neighbouring cells are connected through their common sign changing edge
+pub fn make_contour(vg : &VoxelGrid2<f32>, f : &DenFn2<f32>, accuracy : usize) -> ContourData2{
+    let mut lines = Vec::new();
+    let mut triangles = Vec::new();
+    let mut features = vec![None;vg.size_x * vg.size_y];
+
+    for y in 0..vg.size_y{
+        for x in 0..vg.size_x{
+            let feature = make_vertex(vg, x, y, f, accuracy, &mut triangles);
+            if feature.is_none(){
+                let (corners, densities) = cell_corners(vg, x, y);
+                if densities[0] <= 0.0 {add_solid_cell(&corners, &mut triangles);}
+            }
+            features[y * vg.size_x + x] = feature;
+        }
+    }
+
+    for y in 0..vg.size_y{
+        for x in 0..vg.size_x{
+            let feature = match features[y * vg.size_x + x] {Some(v) => v, None => continue};
+            let (_, densities) = cell_corners(vg, x, y);
+            let sit = sign_changes(&densities);
+
+            if (sit & 2) > 0 && x + 1 < vg.size_x{ //right edge is shared with the cell to the right
+                lines.push(Line2{start : feature, end : features[y * vg.size_x + x + 1].unwrap()});
+            }
+            if (sit & 4) > 0 && y + 1 < vg.size_y{ //top edge is shared with the cell above
+                lines.push(Line2{start : feature, end : features[(y + 1) * vg.size_x + x].unwrap()});
+            }
+        }
+    }
+
+    ContourData2{lines, triangles, features}
+}
+
+fn lerp_zero(a : Vector2<f32>, b : Vector2<f32>, da : f32, db : f32) -> Vector2<f32>{
+    a + (b - a) * (da / (da - db))
+}
+
+//marching squares: edge intersections are linearly interpolated from the grid values alone,
+//saddle cells are resolved by the average of the four corners
+pub fn marching_squares(vg : &VoxelGrid2<f32>) -> ContourData2{
+    let mut lines = Vec::new();
+    let mut triangles = Vec::new();
+
+    for y in 0..vg.size_y{
+        for x in 0..vg.size_x{
+            let (corners, densities) = cell_corners(vg, x, y);
+            let sit = sign_changes(&densities);
+
+            if sit == 0{
+                if densities[0] <= 0.0 {add_solid_cell(&corners, &mut triangles);}
+                continue;
+            }
+
+            let mut points = [Vector2::new(0.0, 0.0);4];
+            for i in 0..4{
+                if (sit & (1 << i)) > 0{
+                    let j = (i + 1) % 4;
+                    points[i] = lerp_zero(corners[i], corners[j], densities[i], densities[j]);
+                }
+            }
+
+            if sit == 15{ //saddle: solid corners are diagonal
+                let center_solid = (densities[0] + densities[1] + densities[2] + densities[3]) / 4.0 <= 0.0;
+
+                for i in 0..4{
+                    let prev = (i + 3) % 4;
+                    if (densities[i] <= 0.0) != center_solid{ //corner is cut off from the center
+                        lines.push(Line2{start : points[prev], end : points[i]});
+                    }
+                    if densities[i] <= 0.0{
+                        triangles.push(Triangle2{p1 : points[prev], p2 : corners[i], p3 : points[i]});
+                    }
+                }
+
+                if center_solid{
+                    triangles.push(Triangle2{p1 : points[0], p2 : points[1], p3 : points[2]});
+                    triangles.push(Triangle2{p1 : points[0], p2 : points[2], p3 : points[3]});
+                }
+            }else{
+                //exactly two sign changes, the solid part of the cell is a convex polygon
+                let mut polygon = Vec::with_capacity(5);
+                let mut segment = Vec::with_capacity(2);
+                for i in 0..4{
+                    if densities[i] <= 0.0 {polygon.push(corners[i]);}
+                    if (sit & (1 << i)) > 0{
+                        polygon.push(points[i]);
+                        segment.push(points[i]);
+                    }
+                }
+
+                lines.push(Line2{start : segment[0], end : segment[1]});
+                for i in 1..polygon.len() - 1{
+                    triangles.push(Triangle2{p1 : polygon[0], p2 : polygon[i], p3 : polygon[i + 1]});
+                }
+            }
+        }
+    }
+
+    ContourData2{lines, triangles, features : Vec::new()}
+}
+
+//draws the contour in the z = `z` plane
+pub fn render_contour2(data : &ContourData2, z : f32, line_color : Vector3<f32>, fill_color : Vector3<f32>, render_lines : &mut RendererVertFragDef, render_triangles : &mut RendererVertFragDef){
+    let lift = |v : Vector2<f32>| Vector3::new(v.x, v.y, z);
+
+    for line in &data.lines{
+        add_line3_color(render_lines, Line3{start : lift(line.start), end : lift(line.end)}, line_color);
+    }
+    for tr in &data.triangles{
+        add_triangle_color(render_triangles, &Triangle3{p1 : lift(tr.p1), p2 : lift(tr.p2), p3 : lift(tr.p3)}, fill_color);
+    }
+}
+
+//contours a disk with a rectangular notch and reports how far the produced vertices are from the real contour,
+//dual contouring should reproduce the sharp corners of the notch, marching squares cuts them off
+pub fn test_contour2(){
+    let a = 0.125;
+    let size = 32;
+    let mut vg = VoxelGrid2::new(a, size, size, Vector2::new(-2.0, -2.0));
+
+    let circle = mk_circle2(Vector2::new(0.0, 0.0), 1.5);
+    let notch = mk_rectangle2(Vector2::new(1.0, 0.3), Vector2::new(0.6, 0.4));
+    let f : DenFn2<f32> = box move |p| {Real::max(circle(p), -notch(p))};
+
+    fill_in_grid(&mut vg, &f);
+
+    let report = |name : &str, data : &ContourData2, f : &DenFn2<f32>| -> (f32, f32){
+        let mut max_err : f32 = 0.0;
+        for line in &data.lines{
+            max_err = Real::max(max_err, Real::max(f(line.start).abs(), f(line.end).abs()));
+        }
+        let area : f32 = data.triangles.iter().map(|t| {let (u, v) = (t.p2 - t.p1, t.p3 - t.p1); (u.x * v.y - u.y * v.x).abs() / 2.0}).sum();
+        println!("{}: {} lines, {} triangles, area {}, max |density| at contour vertices {}", name, data.lines.len(), data.triangles.len(), area, max_err);
+        (max_err, area)
+    };
+
+    let dc = make_contour(&vg, &f, 64);
+    let ms = marching_squares(&vg);
+    let (dc_err, dc_area) = report("dual contouring", &dc, &f);
+    let (ms_err, ms_area) = report("marching squares", &ms, &f);
+
+    //disk minus the part of the notch inside it, that part is integrated in thin strips along x (the notch spans x 0.4 to 1.6, the disk ends at 1.5)
+    let strips = 10000;
+    let notch_area : f32 = (0..strips).map(|i|{
+        let x = 0.4 + (i as f32 + 0.5) * 1.1 / strips as f32;
+        let s = Real::max(1.5 * 1.5 - x * x, 0.0).sqrt();
+        Real::max(Real::min(0.7, s) - Real::max(-0.1, -s), 0.0) * 1.1 / strips as f32
+    }).sum();
+    let exact_area = std::f32::consts::PI * 1.5 * 1.5 - notch_area;
+    println!("exact area {}", exact_area);
+
+    let corner = Vector2::new(0.4, 0.7); //upper left corner of the notch, it lies inside the disk
+    let closest = |data : &ContourData2| data.lines.iter().flat_map(|l| vec![l.start, l.end]).map(|v| (v - corner).norm()).fold(std::f32::MAX, |a, b| Real::min(a, b));
+    let (dc_corner, ms_corner) = (closest(&dc), closest(&ms));
+    println!("distance to the sharp corner: dual contouring {}, marching squares {} (cell size {})", dc_corner, ms_corner, a);
+
+    assert!(dc_corner < a / 16.0, "dual contouring missed the notch corner by {}", dc_corner);
+    assert!(ms_corner > a / 4.0, "marching squares is not expected to reach the notch corner, got within {}", ms_corner);
+    //the density of the disk grows as 2 * 1.5 * distance near its contour, the notch sides as the distance itself
+    assert!(dc_err < 2.0 * 1.5 * a / 16.0, "dual contouring vertices are off the contour: max |density| {}", dc_err);
+    assert!(ms_err < 2.0 * 1.5 * a / 4.0, "marching squares vertices are off the contour: max |density| {}", ms_err);
+    assert!((dc_area - exact_area).abs() < a * a / 2.0, "dual contouring area {} differs from {}", dc_area, exact_area);
+    assert!((ms_area - exact_area).abs() < 2.0 * a * a, "marching squares area {} differs from {}", ms_area, exact_area);
+}
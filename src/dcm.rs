@@ -1,9 +1,12 @@
 use std;
 use na::*;
 use math::*;
-use renderer::*;
+use density::*;
+use mesh::*;
+use dc::push_quad;
 use alga::general::SupersetOf;
 use std::iter::FlatMap;
+use rayon::prelude::*;
 
 #[derive(Clone, Copy, Debug)]
 pub struct MaterialPoint<T : Real + Copy>{
@@ -12,16 +15,59 @@ pub struct MaterialPoint<T : Real + Copy>{
 }
 
 
-pub type DenMatFn3<T> = Box<Fn(Vector3<T>) -> MaterialPoint<T>>;
+pub type DenMatFn3<T> = Box<Fn(Vector3<T>) -> MaterialPoint<T> + Send + Sync>;
 
-pub fn intersection3_mat_a<T : Real>(a : DenMatFn3<T>, b : DenMatFn3<T>) -> DenMatFn3<T>{
+//which operand's material the solid gets where both operands decide it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MaterialSide{
+    A,
+    B,
+    Surface, //material of the operand whose surface bounds the result at that point
+}
+
+//Newton steps spent reaching the surface from outside: one for a distance field, squared implicit forms need a few
+const SURFACE_STEPS : usize = 4;
+
+//material of `f` just inside its surface, reached from `x` (outside of `f`, `density` - the value there) by Newton steps
+//along the numerical gradient, 0 if `x` is farther than `band` by the first step's estimate `density / |gradient|`
+//(the distance itself for a distance field, an underestimate for squared forms) or the steps don't reach the solid
+fn surface_material<T : Real>(f : &DenMatFn3<T>, x : Vector3<T>, density : T, band : T) -> u32{
+    let eps : T = convert(1e-3);
+    let (ex, ey, ez) = (Vector3::new(eps, T::zero(), T::zero()), Vector3::new(T::zero(), eps, T::zero()), Vector3::new(T::zero(), T::zero(), eps));
+    let (mut p, mut density) = (x, density);
+    for step in 0..SURFACE_STEPS{
+        let grad = Vector3::new(f(p + ex).density - f(p - ex).density, f(p + ey).density - f(p - ey).density, f(p + ez).density - f(p - ez).density) / (eps + eps);
+        let len = grad.norm();
+        if !(len > T::zero()) {return 0;}
+        if step == 0 && density / len > band {return 0;}
+
+        p = p - grad / len * (density / len + eps);
+        let inside = f(p);
+        if inside.density <= T::zero() {return inside.material;}
+        density = inside.density;
+    }
+
+    0
+}
+
+pub fn intersection3_mat<T : Real>(a : DenMatFn3<T>, b : DenMatFn3<T>, side : MaterialSide) -> DenMatFn3<T>{
     Box::new(move |x|{
         let a_of_x = a(x);
-        let maximum = Real::max(a_of_x.density, b(x).density);
-        MaterialPoint{density : maximum, material : if maximum <= T::zero() {a_of_x.material} else {0}}
+        let b_of_x = b(x);
+        let maximum = Real::max(a_of_x.density, b_of_x.density);
+        let material = match side{
+            MaterialSide::A => a_of_x.material,
+            MaterialSide::B => b_of_x.material,
+            MaterialSide::Surface => if a_of_x.density == maximum {a_of_x.material} else {b_of_x.material},
+        };
+        MaterialPoint{density : maximum, material : if maximum <= T::zero() {material} else {0}}
     })
 }
 
+pub fn intersection3_mat_a<T : Real>(a : DenMatFn3<T>, b : DenMatFn3<T>) -> DenMatFn3<T>{
+    intersection3_mat(a, b, MaterialSide::A)
+}
+
 pub fn union3_mat<T : Real>(a : DenMatFn3<T>, b : DenMatFn3<T>) -> DenMatFn3<T>{
     Box::new(move |x|{
         let a_of_x = a(x);
@@ -36,21 +82,308 @@ pub fn union3_mat<T : Real>(a : DenMatFn3<T>, b : DenMatFn3<T>) -> DenMatFn3<T>{
     })
 }
 
+//the solid is outside of `b`, so `b`'s material is looked up on its surface (see `surface_material`):
+//`B` paints the solid within `band` of `b` with it, `Surface` only the walls carved by `b` within `band`, the rest keeps `a`'s material
+//`band` is measured by `b`'s density over its gradient, so it should be a few cells: far from the surface of a squared form the estimate means little
+//cost: each solid point within the band evaluates `b` 7 more times per Newton step (a central difference gradient with a fixed 1e-3 step
+//and the step), one step for a distance field, the step assumes `b`'s features are much larger than 1e-3
+pub fn difference3_mat<T : Real>(a : DenMatFn3<T>, b : DenMatFn3<T>, side : MaterialSide, band : T) -> DenMatFn3<T>{
+    Box::new(move |x|{
+        let a_of_x = a(x);
+        let b_of_x = b(x);
+        let maximum = Real::max(a_of_x.density, -b_of_x.density);
+        if maximum > T::zero() {return MaterialPoint{density : maximum, material : 0};}
+
+        let carved = side == MaterialSide::B || (side == MaterialSide::Surface && a_of_x.density != maximum);
+        let material = if carved {surface_material(&b, x, b_of_x.density, band)} else {0};
+        MaterialPoint{density : maximum, material : if material != 0 {material} else {a_of_x.material}}
+    })
+}
+
 pub fn difference3_mat_a<T : Real>(a : DenMatFn3<T>, b : DenMatFn3<T>) -> DenMatFn3<T>{
+    difference3_mat(a, b, MaterialSide::A, T::zero())
+}
+
+//overrides the material of the solid inside `region` (`region` <= 0) with `mat`, the geometry of `f` is unchanged
+pub fn paint3_mat<T : Real>(f : DenMatFn3<T>, region : DenFn3<T>, mat : u32) -> DenMatFn3<T>{
+    Box::new(move |x|{
+        let p = f(x);
+        if p.density <= T::zero() && region(x) <= T::zero() {MaterialPoint{density : p.density, material : mat}} else {p}
+    })
+}
+
+//which material the solid gets inside the blend region of the smooth operators
+#[derive(Clone, Copy, Debug)]
+pub enum MaterialBlend<T : Real + Copy>{
+    Dominant, //material of the operand with the larger blend weight
+    Threshold(T), //material of `b` once its blend weight exceeds the threshold (0 to 1), otherwise of `a`
+    Seam(u32, T), //separate material where both weights are above the given value (0 to 0.5), e.g. mud between grass and rock
+}
+
+impl<T : Real + Copy> MaterialBlend<T>{
+    //`weight_a` - weight of `a` as returned by `smooth_min`/`smooth_max`
+    pub fn pick(&self, weight_a : T, a : u32, b : u32) -> u32{
+        let weight_b = T::one() - weight_a;
+        let picked = match self{
+            &MaterialBlend::Dominant => if weight_a >= weight_b {a} else {b},
+            &MaterialBlend::Threshold(t) => if weight_b > t {b} else {a},
+            &MaterialBlend::Seam(seam, t) => if weight_a > t && weight_b > t {seam} else if weight_a >= weight_b {a} else {b},
+        };
+
+        //the picked operand may be air at this point while the blend is solid
+        if picked == 0 {if a != 0 {a} else {b}} else {picked}
+    }
+}
+
+fn smooth_mat<T : Real + Copy>(a : MaterialPoint<T>, b : MaterialPoint<T>, (density, weight_a) : (T, T), blend : &MaterialBlend<T>) -> MaterialPoint<T>{
+    MaterialPoint{density, material : if density <= T::zero() {blend.pick(weight_a, a.material, b.material)} else {0}}
+}
+
+pub fn smooth_union3_mat<T : Real + Copy>(a : DenMatFn3<T>, b : DenMatFn3<T>, radius : T, kind : BlendKind, blend : MaterialBlend<T>) -> DenMatFn3<T>{
     Box::new(move |x|{
         let a_of_x = a(x);
-        let minimum = Real::max(a_of_x.density, -b(x).density);
-        MaterialPoint{density : minimum, material : if minimum <= T::zero() {a_of_x.material} else {0}}
+        let b_of_x = b(x);
+        smooth_mat(a_of_x, b_of_x, smooth_min(a_of_x.density, b_of_x.density, radius, kind), &blend)
     })
 }
 
-pub fn mk_sphere_mat<T : Real + Copy>(sphere : Sphere<T>, mat : u32) -> DenMatFn3<T>{
+pub fn smooth_intersection3_mat<T : Real + Copy>(a : DenMatFn3<T>, b : DenMatFn3<T>, radius : T, kind : BlendKind, blend : MaterialBlend<T>) -> DenMatFn3<T>{
+    Box::new(move |x|{
+        let a_of_x = a(x);
+        let b_of_x = b(x);
+        smooth_mat(a_of_x, b_of_x, smooth_max(a_of_x.density, b_of_x.density, radius, kind), &blend)
+    })
+}
+
+//the material of `b` can show up on the carved surface (within the blend region), depending on `blend`
+pub fn smooth_difference3_mat<T : Real + Copy>(a : DenMatFn3<T>, b : DenMatFn3<T>, radius : T, kind : BlendKind, blend : MaterialBlend<T>) -> DenMatFn3<T>{
+    Box::new(move |x|{
+        let a_of_x = a(x);
+        let b_of_x = b(x);
+        smooth_mat(a_of_x, b_of_x, smooth_max(a_of_x.density, -b_of_x.density, radius, kind), &blend)
+    })
+}
+
+//affine transforms, see `translate3`, `rotate3`, ... in math.rs; materials are unchanged
+
+pub fn translate3_mat<T : Real>(f : DenMatFn3<T>, offset : Vector3<T>) -> DenMatFn3<T>{
+    Box::new(move |x| {f(x - offset)})
+}
+
+pub fn rotate3_mat<T : Real>(f : DenMatFn3<T>, rotation : UnitQuaternion<T>) -> DenMatFn3<T>{
+    let inv = rotation.inverse();
+    Box::new(move |x| {f(inv * x)})
+}
+
+pub fn rotate3_axis_angle_mat<T : Real>(f : DenMatFn3<T>, axis : Vector3<T>, angle : T) -> DenMatFn3<T>{
+    rotate3_mat(f, UnitQuaternion::from_axis_angle(&Unit::new_normalize(axis), angle))
+}
+
+pub fn scale3_mat<T : Real>(f : DenMatFn3<T>, factor : T) -> DenMatFn3<T>{
+    Box::new(move |x| {
+        let p = f(x / factor);
+        MaterialPoint{density : p.density * factor, material : p.material}
+    })
+}
+
+pub fn scale3_non_uniform_mat<T : Real>(f : DenMatFn3<T>, factor : Vector3<T>) -> DenMatFn3<T>{
+    let min_factor = Real::min(factor.x, Real::min(factor.y, factor.z));
+    Box::new(move |x| {
+        let p = f(Vector3::new(x.x / factor.x, x.y / factor.y, x.z / factor.z));
+        MaterialPoint{density : p.density * min_factor, material : p.material}
+    })
+}
+
+pub fn mirror3_mat<T : Real>(f : DenMatFn3<T>, plane : Plane<T>) -> DenMatFn3<T>{
+    Box::new(move |x| {f(reflect_point3(x, &plane))})
+}
+
+//solid of material `mat` where `f` <= 0, air (material 0) elsewhere
+pub fn with_material<T : Real>(f : DenFn3<T>, mat : u32) -> DenMatFn3<T>{
     Box::new(move |x|{
-        let dist = x - sphere.center;
-        MaterialPoint{density : dist.dot(&dist) - sphere.rad * sphere.rad, material : mat}
+        let density = f(x);
+        MaterialPoint{density, material : if density <= T::zero() {mat} else {0}}
     })
 }
 
+pub fn mk_sphere_mat<T : Real + Copy>(sphere : Sphere<T>, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_sphere(sphere), mat)
+}
+
+pub fn mk_capsule_mat<T : Real>(a : Vector3<T>, b : Vector3<T>, rad : T, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_capsule(a, b, rad), mat)
+}
+
+pub fn mk_capped_cylinder_mat<T : Real>(a : Vector3<T>, b : Vector3<T>, rad : T, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_capped_cylinder(a, b, rad), mat)
+}
+
+pub fn mk_capped_cone_mat<T : Real>(a : Vector3<T>, b : Vector3<T>, rad_a : T, rad_b : T, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_capped_cone(a, b, rad_a, rad_b), mat)
+}
+
+pub fn mk_cone_mat<T : Real>(base : Vector3<T>, apex : Vector3<T>, rad : T, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_cone(base, apex, rad), mat)
+}
+
+pub fn mk_rounded_box_mat<T : Real>(center : Vector3<T>, extent : Vector3<T>, radius : T, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_rounded_box(center, extent, radius), mat)
+}
+
+pub fn mk_ellipsoid_mat<T : Real>(center : Vector3<T>, radii : Vector3<T>, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_ellipsoid(center, radii), mat)
+}
+
+pub fn mk_hex_prism_mat<T : Real>(center : Vector3<T>, rad : T, half_height : T, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_hex_prism(center, rad, half_height), mat)
+}
+
+pub fn mk_tri_prism_mat<T : Real>(center : Vector3<T>, rad : T, half_height : T, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_tri_prism(center, rad, half_height), mat)
+}
+
+pub fn mk_torus_mat<T : Real>(center : Vector3<T>, axis : Vector3<T>, R : T, r : T, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_torus(center, axis, R, r), mat)
+}
+
+pub fn mk_half_space_x_neg_mat<T : Real + Copy>(x : T, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_half_space_x_neg(x), mat)
+}
+
+pub fn mk_half_space_x_pos_mat<T : Real + Copy>(x : T, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_half_space_x_pos(x), mat)
+}
+
+pub fn mk_half_space_y_neg_mat<T : Real + Copy>(y : T, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_half_space_y_neg(y), mat)
+}
+
+pub fn mk_half_space_y_pos_mat<T : Real + Copy>(y : T, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_half_space_y_pos(y), mat)
+}
+
+pub fn mk_half_space_z_neg_mat<T : Real + Copy>(z : T, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_half_space_z_neg(z), mat)
+}
+
+pub fn mk_half_space_z_pos_mat<T : Real + Copy>(z : T, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_half_space_z_pos(z), mat)
+}
+
+pub fn mk_half_space_pos_mat<T : Real + Copy>(plane : Plane<T>, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_half_space_pos(plane), mat)
+}
+
+pub fn mk_half_space_neg_mat<T : Real + Copy>(plane : Plane<T>, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_half_space_neg(plane), mat)
+}
+
+pub fn mk_aabb_mat<T : Real + Copy>(center : Vector3<T>, extent : Vector3<T>, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_aabb(center, extent), mat)
+}
+
+pub fn mk_obb_mat<T : Real + Copy>(center : Vector3<T>, right : Vector3<T>, up : Vector3<T>, extent : Vector3<T>, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_obb(center, right, up, extent), mat)
+}
+
+pub fn mk_torus_z_mat<T : Real>(R : T, r : T, offset : Vector3<T>, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_torus_z(R, r, offset), mat)
+}
+
+pub fn mk_torus_y_mat<T : Real>(R : T, r : T, offset : Vector3<T>, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_torus_y(R, r, offset), mat)
+}
+
+pub fn mk_sphere_displacement_mat<T : Real + Copy>(sphere : Sphere<T>, f : Box<Fn(Vector3<T>) -> T + Send + Sync>, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_sphere_displacement(sphere, f), mat)
+}
+
+pub fn mk_sphere_sdf_mat<T : Real + Copy>(sphere : Sphere<T>, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_sphere_sdf(sphere), mat)
+}
+
+pub fn mk_aabb_sdf_mat<T : Real + Copy>(center : Vector3<T>, extent : Vector3<T>, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_aabb_sdf(center, extent), mat)
+}
+
+pub fn mk_obb_sdf_mat<T : Real + Copy>(center : Vector3<T>, right : Vector3<T>, up : Vector3<T>, extent : Vector3<T>, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_obb_sdf(center, right, up, extent), mat)
+}
+
+pub fn mk_torus_z_sdf_mat<T : Real>(R : T, r : T, offset : Vector3<T>, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_torus_z_sdf(R, r, offset), mat)
+}
+
+pub fn mk_torus_y_sdf_mat<T : Real>(R : T, r : T, offset : Vector3<T>, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_torus_y_sdf(R, r, offset), mat)
+}
+
+pub fn mk_half_space_pos_sdf_mat<T : Real + Copy>(plane : Plane<T>, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_half_space_pos_sdf(plane), mat)
+}
+
+pub fn mk_half_space_neg_sdf_mat<T : Real + Copy>(plane : Plane<T>, mat : u32) -> DenMatFn3<T>{
+    with_material(mk_half_space_neg_sdf(plane), mat)
+}
+
+//anything the material meshers can sample, see `Density3`
+pub trait DensityMat3 : Send + Sync{
+    fn material_point(&self, p : Vector3<f32>) -> MaterialPoint<f32>;
+
+    //see `Density3::density_batch`
+    fn material_point_batch(&self, points : &[Vector3<f32>], out : &mut [MaterialPoint<f32>]){
+        for (p, m) in points.iter().zip(out.iter_mut()){
+            *m = self.material_point(*p);
+        }
+    }
+
+    //exact gradient of the density when it is known
+    fn gradient(&self, _p : Vector3<f32>) -> Option<Vector3<f32>>{
+        None
+    }
+
+    //see `Density3::bounds`
+    fn bounds(&self, _min : Vector3<f32>, _max : Vector3<f32>) -> Option<(f32, f32)>{
+        None
+    }
+}
+
+impl DensityMat3 for DenMatFn3<f32>{
+    fn material_point(&self, p : Vector3<f32>) -> MaterialPoint<f32>{
+        self(p)
+    }
+}
+
+//density graph filled with a single material, exact normals come from `DenNode::eval_grad`
+#[derive(Clone, Debug)]
+pub struct MatNode{
+    pub node : DenNode,
+    pub material : u32,
+}
+
+impl DensityMat3 for MatNode{
+    fn material_point(&self, p : Vector3<f32>) -> MaterialPoint<f32>{
+        let density = self.node.eval(p);
+        MaterialPoint{density, material : if density <= 0.0 {self.material} else {0}}
+    }
+
+    fn material_point_batch(&self, points : &[Vector3<f32>], out : &mut [MaterialPoint<f32>]){
+        let mut densities = vec![0.0;points.len()];
+        self.node.eval_batch(points, &mut densities);
+        for (m, &density) in out.iter_mut().zip(densities.iter()){
+            *m = MaterialPoint{density, material : if density <= 0.0 {self.material} else {0}};
+        }
+    }
+
+    fn gradient(&self, p : Vector3<f32>) -> Option<Vector3<f32>>{
+        self.node.eval_grad(p).1
+    }
+
+    fn bounds(&self, min : Vector3<f32>, max : Vector3<f32>) -> Option<(f32, f32)>{
+        Some(self.node.bounds(min, max))
+    }
+}
+
 pub struct VoxelMaterialGrid3<T : Real + Copy>{
     pub a : T,
     pub size_x : usize,
@@ -143,7 +476,7 @@ fn sample_qef_brute(square : Square3<f32>, n : usize, planes : &Vec<Plane<f32>>)
 //so the algorithm should use some interpolation methods assuming the surface is smooth(does not change too much within one cube of the grid)
 //interpolation can operate on 8 corner vertices of the cube
 //TODO or maybe save generator to disk ??, in case of random(presudo-random) generator - its seed can be saved
-fn sample_intersection_brute(line : Line3<f32>, n : usize, f : &DenMatFn3<f32>) -> Vector3<f32>{
+fn sample_intersection_brute<D : DensityMat3>(line : Line3<f32>, n : usize, f : &D) -> Vector3<f32>{
 
     let ext = line.end - line.start;
     let norm = ext.norm();
@@ -155,7 +488,7 @@ fn sample_intersection_brute(line : Line3<f32>, n : usize, f : &DenMatFn3<f32>)
 
     for i in 0..n {
         let point = line.start + ext * ( i as f32 + 0.5) / n as f32;
-        let den = f(point).density.abs();
+        let den = f.material_point(point).density.abs();
 
         if den < best{
            best = den;
@@ -168,10 +501,12 @@ fn sample_intersection_brute(line : Line3<f32>, n : usize, f : &DenMatFn3<f32>)
 
 
 //why haven't I come up with this one at the start ? :)
-pub fn sample_normal(point : &Vector3<f32>, eps : f32, f : &DenMatFn3<f32>) -> Vector3<f32>{
-    Vector3::new( f(Vector3::new(point.x + eps, point.y, point.z)).density - f(Vector3::new(point.x - eps, point.y, point.z)).density,
-                  f(Vector3::new(point.x, point.y + eps, point.z)).density - f(Vector3::new(point.x, point.y - eps, point.z)).density,
-                  f(Vector3::new(point.x, point.y, point.z + eps)).density - f(Vector3::new(point.x, point.y, point.z - eps)).density ).normalize()
+pub fn sample_normal<D : DensityMat3>(point : &Vector3<f32>, eps : f32, f : &D) -> Vector3<f32>{
+    if let Some(g) = f.gradient(*point) {return g.normalize()}
+
+    Vector3::new( f.material_point(Vector3::new(point.x + eps, point.y, point.z)).density - f.material_point(Vector3::new(point.x - eps, point.y, point.z)).density,
+                  f.material_point(Vector3::new(point.x, point.y + eps, point.z)).density - f.material_point(Vector3::new(point.x, point.y - eps, point.z)).density,
+                  f.material_point(Vector3::new(point.x, point.y, point.z + eps)).density - f.material_point(Vector3::new(point.x, point.y, point.z - eps)).density ).normalize()
 }
 
 
@@ -181,8 +516,9 @@ pub fn sample_normal(point : &Vector3<f32>, eps : f32, f : &DenMatFn3<f32>) -> V
 
 //feature is a vertex that may or may not be calculated for each cube of the grid. It is calculated for each cube that exhibits a sign change(this means that the cube
 // intersects the surface) and not calculated otherwise
-fn calc_feature(vg : &VoxelMaterialGrid3<f32>, x : usize, y : usize, z : usize,
-               f : &DenMatFn3<f32>, accuracy : usize, contour_data : &mut ContourData, debug_render : &mut RendererVertFragDef) -> Option<Vector3<f32>>{
+//feature vertex of the cell, the surface normal and the material at it
+fn calc_feature<D : DensityMat3>(vg : &VoxelMaterialGrid3<f32>, x : usize, y : usize, z : usize,
+               f : &D, accuracy : usize) -> Option<(Vector3<f32>, Vector3<f32>, u32)>{
     //let epsilon = vg.a / accuracy as f32;
 
     // let p00 = vg.get(x, y, z);
@@ -341,237 +677,158 @@ fn calc_feature(vg : &VoxelMaterialGrid3<f32>, x : usize, y : usize, z : usize,
         }
         
 
-        let t = z * vg.size_y * vg.size_x + y * vg.size_x + x;
-
-        contour_data.features[t] = Some(feature_vertex);
-        //contour_data.normals[t] = Some(sample_normal(&Sphere{center : feature_vertex, rad : rad_for_normal}, accuracy, f));
-        contour_data.normals[t] = Some(sample_normal(&feature_vertex, vg.a / 100.0, f));//TODO it should not sample density function anymore
+        //let normal = sample_normal(&Sphere{center : feature_vertex, rad : rad_for_normal}, accuracy, f);
+        let normal = sample_normal(&feature_vertex, vg.a / 100.0, f);//TODO it should not sample density function anymore
         //see https://github.com/Lin20/isosurface/blob/57b5c5e16e9de321e3f4a919d2f14c85811a28e7/Isosurface/Isosurface/UniformDualContouring/DC3D.cs#L143
-        contour_data.materials[t] = f(feature_vertex).material;
-
-        Some(feature_vertex)
+        //the feature vertex lies on the surface, where the material may already read as empty
+        let material = match f.material_point(feature_vertex).material{
+            0 => [m00, m01, m02, m03, m10, m11, m12, m13].iter().cloned().find(|&m| m != 0).unwrap_or(0),
+            m => m,
+        };
+        Some((feature_vertex, normal, material))
         
     }else{
         None
     }
 }
 
-//TODO debug_renderer is for debug only
-pub fn make_contour(vg : &VoxelMaterialGrid3<f32>, f : &DenMatFn3<f32>, accuracy : usize, debug_renderer : &mut RendererVertFragDef) -> ContourData{
-
-    //TODO inefficient Vec::new() creation vvv
-    let mut contour_data = ContourData{lines : Vec::new(),
-                                       triangles : Vec::new(),
-                                       triangle_normals : Vec::new(),
-                                       triangle_colors : Vec::new(),
-                                       features : vec![None;vg.size_x * vg.size_y * vg.size_z],
-                                       normals : vec![None;vg.size_x * vg.size_y * vg.size_z],
-                                       materials : vec![0;vg.size_x * vg.size_y * vg.size_z]};
-    let mut cache_already_calculated = vec![false;vg.size_x * vg.size_y * vg.size_z]; //this cache is used to mark cubes that have already been calculated for feature vertex
-
-    {
-        //&mut contour_data, cache_already_calculated
-        let mut cached_make = |x: usize, y: usize, z : usize, contour_data : &mut ContourData| -> Option<Vector3<f32>>{
-            let t = z * vg.size_y * vg.size_x + y * vg.size_x + x;
-
-            if cache_already_calculated[t] {
-                contour_data.features[t]
-            }else{
-                cache_already_calculated[t] = true;
-                calc_feature(&vg, x, y, z, f, accuracy, contour_data, debug_renderer)
-            }
-
-
-        };
-
-        for z in 0..vg.size_z{
-            for y in 0..vg.size_y {
-                for x in 0..vg.size_x {
-                    //let p00 = vg.get(x, y, z);
-                    //let p01 = vg.get(x + 1, y, z);
-                    //let p02 = vg.get(x, y + 1, z);
-                    //let p03 = vg.get(x + 1, y + 1, z);
-                    let m03 = vg.get_material(x + 1, y + 1, z);
-
-                    //let p10 = vg.get(x, y, z + 1);
-                    //let p11 = vg.get(x + 1, y, z + 1);
-                    //let p12 = vg.get(x, y + 1, z + 1);
-                    //let p13 = vg.get(x + 1, y + 1, z + 1);
-
-                    let m11 = vg.get_material(x + 1, y, z + 1);
-                    let m12 = vg.get_material(x, y + 1, z + 1);
-                    let m13 = vg.get_material(x + 1, y + 1, z + 1);
-
-                    /*let v00 = vg.get_point(x, y, z);
-                    let v01 = vg.get_point(x + 1, y, z);
-                    let v02 = vg.get_point(x, y + 1, z);
-                    let v03 = vg.get_point(x + 1, y + 1, z);
-
-                    let v10 = vg.get_point(x,y, z + 1);
-                    let v11 = vg.get_point(x + 1, y, z + 1);
-                    let v12 = vg.get_point(x, y + 1, z + 1);
-                    let v13 = vg.get_point(x + 1, y + 1, z + 1);*/
-
-                    fn color_map(edge0 : u32, edge1 : u32) -> Vector3<f32>{
-                        match edge0{
-                            1 => Vector3::new(1.0, 0.0, 0.0),
-                            2 => Vector3::new(0.0, 1.0, 0.0),
-                            _ => {
-                                match edge1{
-                                    1 => Vector3::new(1.0, 0.0, 0.0),
-                                    2 => Vector3::new(0.0, 1.0, 0.0),
-                                    _ => Vector3::new(0.0,0.0,0.0),
-                                }
-                            },
-                        }
-                    }
-
-                    
-
-
-                    let possible_feature_vertex = cached_make(x, y, z, &mut contour_data);
-
-                    match possible_feature_vertex{
-                        None => (),
-                        Some(f0) => {
-                            let t = z * vg.size_y * vg.size_x + y * vg.size_x + x;
-                            let normal = contour_data.normals[t].unwrap();
-
-
-                            
-                            
-                            //TODO incorrect normals in some places 
-                            if !const_sign(m03, m13){
-
-                                let f1 = cached_make(x + 1, y, z, &mut contour_data).unwrap();
-                                let f2 = cached_make(x + 1, y + 1, z, &mut contour_data).unwrap();
-                                let f3 = cached_make(x, y + 1, z, &mut contour_data).unwrap();
-                                //f1 && f2 && f3 all should be non-empty, as they all exhibit a sign change at least on their common edge
-
-                                //this is needed to calculate the direction of the resulting quad correctly
-                                let dir = (f2 - f0).cross(&(f3 - f0)).normalize();
-                                
-                                contour_data.triangle_colors.push(color_map(m03, m13));
-
-                                if dir.dot(&normal) > 0.0{ //should not be zero at any time
-                                    contour_data.triangles.push(Triangle3{p1 : f0, p2 : f2, p3 : f3});
-                                    contour_data.triangles.push(Triangle3{p1 : f0, p2 : f1, p3 : f2});
-                                    
-                                    //add_line3_color(debug_renderer, Line3{start : f0, end : f0 + dir * 0.1}, Vector3::new(1.0, 1.0, 1.0));
-                                    //TODO debug
-                                    /* if (dir.dot(&debug_real_normal) <= 0.0) {
-                                        println!("bad normal at {} {} {} {}", 1, x, y, z);
-                                    } */
-                                    contour_data.triangle_normals.push(dir); //TODO inefficient
-                                }else{
-                                    contour_data.triangles.push(Triangle3{p1 : f0, p2 : f3, p3 : f2});
-                                    contour_data.triangles.push(Triangle3{p1 : f0, p2 : f2, p3 : f1});
-                                    //add_line3_color(debug_renderer, Line3{start : f0, end : f0 - dir * 0.1}, Vector3::new(1.0, 1.0, 1.0));
-                                   /*  if (-dir.dot(&debug_real_normal) <= 0.0) {
-                                        add_square3_bounds_color(debug_renderer, vg.square3(x, y, z), Vector3::new(1.0, 0.0, 0.0));
-                                        add_line3_color(debug_renderer, Line3{start : f0, end : f0 - dir * 0.1}, Vector3::new(1.0, 1.0, 1.0));
-                                        add_line3_color(debug_renderer, Line3{start : f0, end : f0 - normal.normalize()}, Vector3::new(0.0, 0.0, 0.0));
-                                        println!("bad normal at {} {}", 2, -dir.dot(&debug_real_normal));
-                                    } */
-                                    contour_data.triangle_normals.push(-dir);
-                                }
-                            }
-                            if !const_sign(m12, m13){
-                                let f1 = cached_make(x, y, z + 1, &mut contour_data).unwrap();
-                                let f2 = cached_make(x, y + 1, z + 1, &mut contour_data).unwrap();
-                                let f3 = cached_make(x, y + 1, z, &mut contour_data).unwrap();
-                                //f1 && f2 && f3 all should be non-empty, as they all exhibit a sign change at least on their common edge
-
-                                contour_data.triangle_colors.push(color_map(m12, m13));
-
-                                //this is needed to calculate the direction of the resulting quad correctly
-                                let dir = (f2 - f0).cross(&(f3 - f0)).normalize();
-                                if dir.dot(&normal) > 0.0{ //should not be zero at any time
-                                    contour_data.triangles.push(Triangle3{p1 : f0, p2 : f2, p3 : f3});
-                                    contour_data.triangles.push(Triangle3{p1 : f0, p2 : f1, p3 : f2});
-                                    //add_line3_color(debug_renderer, Line3{start : f0, end : f0 + dir * 0.1}, Vector3::new(1.0, 1.0, 1.0));
-                                   /*  if (dir.dot(&debug_real_normal) <= 0.0) {
-                                        println!("bad normal at {} {} {} {}", 3, x, y, z);
-                                    } */
-                                    contour_data.triangle_normals.push(dir);
-                                }else{
-                                    contour_data.triangles.push(Triangle3{p1 : f0, p2 : f3, p3 : f2});
-                                    contour_data.triangles.push(Triangle3{p1 : f0, p2 : f2, p3 : f1});
-                                    //add_line3_color(debug_renderer, Line3{start : f0, end : f0 - dir * 0.1}, Vector3::new(1.0, 1.0, 1.0));
-                                   /*  if (-dir.dot(&debug_real_normal) <= 0.0) {
-                                        add_square3_bounds_color(debug_renderer, vg.square3(x, y, z), Vector3::new(1.0, 0.0, 0.0));
-                                        add_line3_color(debug_renderer, Line3{start : f0, end : f0 - dir * 0.1}, Vector3::new(1.0, 1.0, 1.0));
-                                        add_line3_color(debug_renderer, Line3{start : f0, end : f0 - normal.normalize()}, Vector3::new(0.0, 0.0, 0.0));
-                                        
-                                        println!("bad normal at {} {}", 4, -dir.dot(&debug_real_normal));
-                                    } */
-                                    contour_data.triangle_normals.push(-dir);
-                                }
-                            }
-                            if !const_sign(m11, m13){
-                                let f1 = cached_make(x + 1, y, z, &mut contour_data).unwrap();
-                                let f2 = cached_make(x + 1, y, z + 1, &mut contour_data).unwrap();
-                                let f3 = cached_make(x, y, z + 1, &mut contour_data).unwrap();
-                                //f1 && f2 && f3 all should be non-empty, as they all exhibit a sign change at least on their common edge
-
-                                contour_data.triangle_colors.push(color_map(m11, m13));
-
-                                //this is needed to calculate the direction of the resulting quad correctly
-                                let dir = (f2 - f0).cross(&(f3 - f0)).normalize();
-                                if dir.dot(&normal) > 0.0{ //should not be zero at any time
-                                    contour_data.triangles.push(Triangle3{p1 : f0, p2 : f2, p3 : f3});
-                                    contour_data.triangles.push(Triangle3{p1 : f0, p2 : f1, p3 : f2});
-                                    //add_line3_color(debug_renderer, Line3{start : f0, end : f0 + dir * 0.1}, Vector3::new(1.0, 1.0, 1.0));
-                                   /*  if (dir.dot(&debug_real_normal) <= 0.0) {
-                                        println!("bad normal at {} {} {} {}", 5, x, y, z);
-                                    } */
-                                    contour_data.triangle_normals.push(dir);
-                                } else{
-                                    contour_data.triangles.push(Triangle3{p1 : f0, p2 : f3, p3 : f2});
-                                    contour_data.triangles.push(Triangle3{p1 : f0, p2 : f2, p3 : f1});
-                                    //add_line3_color(debug_renderer, Line3{start : f0, end : f0 - dir * 0.1}, Vector3::new(1.0, 1.0, 1.0));
-                                   /*  if (-dir.dot(&debug_real_normal) <= 0.0) {
-                                        add_square3_bounds_color(debug_renderer, vg.square3(x, y, z), Vector3::new(1.0, 0.0, 0.0));
-                                        add_line3_color(debug_renderer, Line3{start : f0, end : f0 - dir * 0.1}, Vector3::new(1.0, 1.0, 1.0));
-                                        println!("bad normal at {} {} {} {}", 6, x, y, z);
-                                    } */
-                                    contour_data.triangle_normals.push(-dir);
-                                } 
-                            }
-                        },
-                    }
+//render color of a material id
+pub fn material_color(material : u32) -> Vector3<f32>{
+    match material{
+        1 => Vector3::new(1.0, 0.0, 0.0),
+        2 => Vector3::new(0.0, 1.0, 0.0),
+        _ => Vector3::new(0.0, 0.0, 0.0),
+    }
+}
 
+//same scheme as `dc::make_contour`: features of all cells, then quads, both in parallel z-slabs concatenated in z order
+//mesh vertices carry the material of their feature point
+pub fn make_contour<D : DensityMat3>(vg : &VoxelMaterialGrid3<f32>, f : &D, accuracy : usize) -> ContourData{
+    let slab = vg.size_x * vg.size_y;
+    let mut features = vec![None;slab * vg.size_z];
+    let mut normals = vec![None;slab * vg.size_z];
+    let mut materials = vec![0;slab * vg.size_z];
+
+    features.par_chunks_mut(slab).zip(normals.par_chunks_mut(slab)).zip(materials.par_chunks_mut(slab)).enumerate().for_each(|(z, ((features, normals), materials))|{
+        for y in 0..vg.size_y{
+            for x in 0..vg.size_x{
+                if let Some((feature, normal, material)) = calc_feature(vg, x, y, z, f, accuracy){
+                    features[y * vg.size_x + x] = Some(feature);
+                    normals[y * vg.size_x + x] = Some(normal);
+                    materials[y * vg.size_x + x] = material;
+                }
+            }
+        }
+    });
 
+    let mut mesh = Mesh::new();
+    let mut vertex_of_cell = vec![0u32;slab * vg.size_z];
+    for t in 0..features.len(){
+        if let Some(feature) = features[t]{
+            vertex_of_cell[t] = mesh.push_vertex(feature, normals[t].unwrap(), materials[t]);
+        }
+    }
 
+    let slabs : Vec<Vec<u32>> = (0..vg.size_z - 1).into_par_iter().map(|z|{ //the last layer of cells has no neighbours to build quads with
+        let mut indices = Vec::new();
+        //f1 && f2 && f3 all should be non-empty, as they all exhibit a sign change at least on their common edge
+        let vertex = |x : usize, y : usize, z : usize| vertex_of_cell[z * slab + y * vg.size_x + x];
+
+        for y in 0..vg.size_y - 1 {
+            for x in 0..vg.size_x - 1 {
+                let t = z * slab + y * vg.size_x + x;
+                if features[t].is_none() {continue;}
+                let v0 = vertex_of_cell[t];
+                let normal = normals[t].unwrap();
+
+                let m03 = vg.get_material(x + 1, y + 1, z);
+                let m11 = vg.get_material(x + 1, y, z + 1);
+                let m12 = vg.get_material(x, y + 1, z + 1);
+                let m13 = vg.get_material(x + 1, y + 1, z + 1);
+
+                //TODO incorrect normals in some places
+                if !const_sign(m03, m13){
+                    push_quad(&mesh.vertices, v0, vertex(x + 1, y, z), vertex(x + 1, y + 1, z), vertex(x, y + 1, z), &normal, &mut indices);
+                }
+                if !const_sign(m12, m13){
+                    push_quad(&mesh.vertices, v0, vertex(x, y, z + 1), vertex(x, y + 1, z + 1), vertex(x, y + 1, z), &normal, &mut indices);
+                }
+                if !const_sign(m11, m13){
+                    push_quad(&mesh.vertices, v0, vertex(x + 1, y, z), vertex(x + 1, y, z + 1), vertex(x, y, z + 1), &normal, &mut indices);
                 }
             }
         }
 
-    }
+        indices
+    }).collect();
 
-    contour_data
+    for indices in slabs{
+        mesh.indices.extend(indices);
+    }
 
+    ContourData{lines : Vec::new(), mesh, features, normals, materials}
 }
 
-pub fn fill_in_grid(vg : &mut VoxelMaterialGrid3<f32>, f : &DenMatFn3<f32>, offset : Vector3<f32>){
-    for z in 0..vg.size_z + 1 {
-        for y in 0..vg.size_y + 1{
-            for x in 0..vg.size_x + 1 {
-                let vx = vg.vertices_x();
-                let vy = vg.vertices_y();
-                vg.grid[z * vy * vx + y * vx + x] = f(offset + Vector3::new(vg.a * (x as f32), vg.a * (y as f32), vg.a * (z as f32)));
+//one z layer of vertices per task, see `dc::fill_in_grid`
+pub fn fill_in_grid<D : DensityMat3>(vg : &mut VoxelMaterialGrid3<f32>, f : &D, offset : Vector3<f32>){
+    let (a, vx, vy) = (vg.a, vg.vertices_x(), vg.vertices_y());
+    vg.grid.par_chunks_mut(vx * vy).enumerate().for_each(|(z, layer)|{
+        let mut points = Vec::with_capacity(vx * vy);
+        for y in 0..vy{
+            for x in 0..vx{
+                points.push(offset + Vector3::new(a * (x as f32), a * (y as f32), a * (z as f32)));
             }
         }
-    }
+        f.material_point_batch(&points, layer);
+    });
 }
 
 
 pub struct ContourData{ // + hermite data ? (exact points of intersection of the surface with each edge that exhibits a sign change + normals for each of those points)
     pub lines : Vec<Line3<f32>>,
-    pub triangles : Vec<Triangle3<f32>>,
-    pub triangle_normals : Vec<Vector3<f32>>,
-    pub triangle_colors : Vec<Vector3<f32>>,
+    pub mesh : Mesh,
     pub features : Vec<Option<Vector3<f32>>>,
     pub normals : Vec<Option<Vector3<f32>>>, //normal to the surface calculated at feature vertex
     pub materials : Vec<u32>,
-}
\ No newline at end of file
+}
+
+//materials picked by the CSG operators and the paint operator at a few points of two overlapping spheres
+pub fn test_material_csg(){
+    let a = || mk_sphere_sdf_mat(Sphere{center : Vector3::new(0.0, 0.0, 0.0), rad : 1.0f32}, 1);
+    let b = || mk_sphere_sdf_mat(Sphere{center : Vector3::new(1.0, 0.0, 0.0), rad : 1.0f32}, 2);
+    let left = Vector3::new(0.2, 0.0, 0.0); //closer to the surface of `b`
+    let right = Vector3::new(0.8, 0.0, 0.0); //closer to the surface of `a`
+    let far = Vector3::new(-0.9, 0.0, 0.0); //only in `a`
+    let wall = Vector3::new(-0.05, 0.0, 0.0); //in `a`, right next to the wall carved by `b`
+
+    let i = intersection3_mat(a(), b(), MaterialSide::A);
+    assert_eq!((i(left).material, i(right).material, i(far).material), (1, 1, 0));
+    let i = intersection3_mat(a(), b(), MaterialSide::B);
+    assert_eq!((i(left).material, i(right).material), (2, 2));
+    let i = intersection3_mat(a(), b(), MaterialSide::Surface);
+    assert_eq!((i(left).material, i(right).material), (2, 1));
+
+    let d = difference3_mat(a(), b(), MaterialSide::A, 0.0);
+    assert_eq!((d(far).material, d(wall).material, d(left).material), (1, 1, 0));
+    let d = difference3_mat(a(), b(), MaterialSide::B, 1.0);
+    assert_eq!((d(far).material, d(wall).material), (2, 2));
+    let d = difference3_mat(a(), b(), MaterialSide::B, 0.5);
+    assert_eq!((d(far).material, d(wall).material), (1, 2)); //`far` is 0.9 away from `b`
+    let d = difference3_mat(a(), b(), MaterialSide::Surface, 1.0);
+    assert_eq!((d(far).material, d(wall).material), (1, 2));
+    assert!((d(wall).density - difference3_mat_a(a(), b())(wall).density).abs() < 1e-6);
+
+    //an implicit (squared) `b`: one Newton step from `deep` falls short of the surface, more reach it
+    let b_implicit = || mk_sphere_mat(Sphere{center : Vector3::new(1.0, 0.0, 0.0), rad : 1.0f32}, 2);
+    let deep = Vector3::new(-0.3, 0.0, 0.0);
+    let d = difference3_mat(a(), b_implicit(), MaterialSide::B, 0.5);
+    assert_eq!((d(far).material, d(deep).material, d(wall).material), (1, 2, 2));
+    let d = difference3_mat(a(), b_implicit(), MaterialSide::Surface, 0.5);
+    assert_eq!((d(far).material, d(deep).material, d(wall).material), (1, 2, 2));
+
+    let p = paint3_mat(a(), mk_half_space_y_pos(0.5), 3);
+    assert_eq!((p(far).material, p(Vector3::new(0.0, 0.75, 0.0)).material, p(Vector3::new(0.0, 1.5, 0.0)).material), (1, 3, 0));
+    assert_eq!(p(far).density, a()(far).density);
+
+    println!("material CSG test passed");
+}
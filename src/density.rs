@@ -0,0 +1,1198 @@
+use std;
+use std::fmt;
+use na::*;
+use math::*;
+use noise::{Perlin, Seedable};
+use time::precise_time_ns;
+
+//node based density function
+//unlike `DenFn3` (an opaque boxed closure) the graph can be printed, analysed and simplified
+//every node evaluates to exactly the same values as its `mk_*` counterpart from math.rs
+#[derive(Clone, Debug)]
+pub enum DenNode{
+    Sphere{center : Vector3<f32>, rad : f32},
+    Aabb{center : Vector3<f32>, extent : Vector3<f32>},
+    Obb{center : Vector3<f32>, right : Vector3<f32>, up : Vector3<f32>, extent : Vector3<f32>},
+    TorusZ{major_rad : f32, minor_rad : f32, offset : Vector3<f32>},
+    TorusY{major_rad : f32, minor_rad : f32, offset : Vector3<f32>},
+    HalfSpacePos{plane : Plane<f32>}, //solid on the side the normal points to
+    HalfSpaceNeg{plane : Plane<f32>}, //solid on the opposite side
+    HeightNoise{perlin : Perlin, cube : Square3<f32>, octaves : usize, persistence : f32},
+
+    //exact signed distance primitives
+    SdfSphere{center : Vector3<f32>, rad : f32},
+    SdfAabb{center : Vector3<f32>, extent : Vector3<f32>},
+    SdfObb{center : Vector3<f32>, right : Vector3<f32>, up : Vector3<f32>, extent : Vector3<f32>}, //`right` and `up` are unit and orthogonal
+    SdfTorusZ{major_rad : f32, minor_rad : f32, offset : Vector3<f32>},
+    SdfTorusY{major_rad : f32, minor_rad : f32, offset : Vector3<f32>},
+    Capsule{a : Vector3<f32>, b : Vector3<f32>, rad : f32},
+    CappedCylinder{a : Vector3<f32>, b : Vector3<f32>, rad : f32},
+    CappedCone{a : Vector3<f32>, b : Vector3<f32>, rad_a : f32, rad_b : f32},
+    RoundedBox{center : Vector3<f32>, extent : Vector3<f32>, radius : f32},
+    Ellipsoid{center : Vector3<f32>, radii : Vector3<f32>}, //distance bound only
+    HexPrism{center : Vector3<f32>, rad : f32, half_height : f32},
+    TriPrism{center : Vector3<f32>, rad : f32, half_height : f32},
+    Torus{center : Vector3<f32>, axis : Vector3<f32>, major_rad : f32, minor_rad : f32},
+
+    Union(Box<DenNode>, Box<DenNode>),
+    Intersection(Box<DenNode>, Box<DenNode>),
+    Difference(Box<DenNode>, Box<DenNode>),
+    SmoothUnion{a : Box<DenNode>, b : Box<DenNode>, radius : f32, kind : BlendKind},
+    SmoothIntersection{a : Box<DenNode>, b : Box<DenNode>, radius : f32, kind : BlendKind},
+    SmoothDifference{a : Box<DenNode>, b : Box<DenNode>, radius : f32, kind : BlendKind},
+
+    Translate{offset : Vector3<f32>, node : Box<DenNode>},
+    Rotate{rotation : UnitQuaternion<f32>, node : Box<DenNode>},
+    Scale{factor : f32, node : Box<DenNode>},
+    ScaleNonUniform{factor : Vector3<f32>, node : Box<DenNode>},
+    Mirror{plane : Plane<f32>, node : Box<DenNode>}, //normal is unit
+
+    //domain deformations, see `repeat3`, `twist3`, ... in math.rs
+    Repeat{period : Vector3<f32>, limit : Option<Vector3<f32>>, node : Box<DenNode>},
+    Twist{rate : f32, node : Box<DenNode>},
+    Bend{rate : f32, node : Box<DenNode>},
+    Elongate{extent : Vector3<f32>, node : Box<DenNode>},
+    Symmetry{plane : Plane<f32>, node : Box<DenNode>}, //normal is unit
+    LipschitzCorrect{lipschitz : f32, node : Box<DenNode>},
+}
+
+impl DenNode{
+
+    pub fn sphere(sphere : Sphere<f32>) -> DenNode{
+        DenNode::Sphere{center : sphere.center, rad : sphere.rad}
+    }
+
+    pub fn aabb(center : Vector3<f32>, extent : Vector3<f32>) -> DenNode{
+        DenNode::Aabb{center, extent}
+    }
+
+    pub fn obb(center : Vector3<f32>, right : Vector3<f32>, up : Vector3<f32>, extent : Vector3<f32>) -> DenNode{
+        DenNode::Obb{center, right, up, extent}
+    }
+
+    pub fn torus_z(major_rad : f32, minor_rad : f32, offset : Vector3<f32>) -> DenNode{
+        DenNode::TorusZ{major_rad, minor_rad, offset}
+    }
+
+    pub fn torus_y(major_rad : f32, minor_rad : f32, offset : Vector3<f32>) -> DenNode{
+        DenNode::TorusY{major_rad, minor_rad, offset}
+    }
+
+    pub fn half_space_pos(plane : Plane<f32>) -> DenNode{
+        DenNode::HalfSpacePos{plane}
+    }
+
+    pub fn half_space_neg(plane : Plane<f32>) -> DenNode{
+        DenNode::HalfSpaceNeg{plane}
+    }
+
+    //same parameters as `noise_f32`
+    pub fn height_noise(perlin : Perlin, cube : Square3<f32>) -> DenNode{
+        DenNode::HeightNoise{perlin, cube, octaves : 4, persistence : 0.56}
+    }
+
+    pub fn sdf_sphere(sphere : Sphere<f32>) -> DenNode{
+        DenNode::SdfSphere{center : sphere.center, rad : sphere.rad}
+    }
+
+    pub fn sdf_aabb(center : Vector3<f32>, extent : Vector3<f32>) -> DenNode{
+        DenNode::SdfAabb{center, extent}
+    }
+
+    pub fn sdf_obb(center : Vector3<f32>, right : Vector3<f32>, up : Vector3<f32>, extent : Vector3<f32>) -> DenNode{
+        DenNode::SdfObb{center, right : right.normalize(), up : up.normalize(), extent}
+    }
+
+    pub fn sdf_torus_z(major_rad : f32, minor_rad : f32, offset : Vector3<f32>) -> DenNode{
+        DenNode::SdfTorusZ{major_rad, minor_rad, offset}
+    }
+
+    pub fn sdf_torus_y(major_rad : f32, minor_rad : f32, offset : Vector3<f32>) -> DenNode{
+        DenNode::SdfTorusY{major_rad, minor_rad, offset}
+    }
+
+    pub fn capsule(a : Vector3<f32>, b : Vector3<f32>, rad : f32) -> DenNode{
+        DenNode::Capsule{a, b, rad}
+    }
+
+    pub fn capped_cylinder(a : Vector3<f32>, b : Vector3<f32>, rad : f32) -> DenNode{
+        DenNode::CappedCylinder{a, b, rad}
+    }
+
+    pub fn capped_cone(a : Vector3<f32>, b : Vector3<f32>, rad_a : f32, rad_b : f32) -> DenNode{
+        DenNode::CappedCone{a, b, rad_a, rad_b}
+    }
+
+    pub fn cone(base : Vector3<f32>, apex : Vector3<f32>, rad : f32) -> DenNode{
+        DenNode::CappedCone{a : base, b : apex, rad_a : rad, rad_b : 0.0}
+    }
+
+    pub fn rounded_box(center : Vector3<f32>, extent : Vector3<f32>, radius : f32) -> DenNode{
+        DenNode::RoundedBox{center, extent, radius}
+    }
+
+    pub fn ellipsoid(center : Vector3<f32>, radii : Vector3<f32>) -> DenNode{
+        DenNode::Ellipsoid{center, radii}
+    }
+
+    pub fn hex_prism(center : Vector3<f32>, rad : f32, half_height : f32) -> DenNode{
+        DenNode::HexPrism{center, rad, half_height}
+    }
+
+    pub fn tri_prism(center : Vector3<f32>, rad : f32, half_height : f32) -> DenNode{
+        DenNode::TriPrism{center, rad, half_height}
+    }
+
+    pub fn torus(center : Vector3<f32>, axis : Vector3<f32>, major_rad : f32, minor_rad : f32) -> DenNode{
+        DenNode::Torus{center, axis : axis.normalize(), major_rad, minor_rad}
+    }
+
+    //normalizes the normal, so the result is an exact distance
+    pub fn sdf_half_space_pos(plane : Plane<f32>) -> DenNode{
+        DenNode::HalfSpacePos{plane : Plane{point : plane.point, normal : plane.normal.normalize()}}
+    }
+
+    pub fn sdf_half_space_neg(plane : Plane<f32>) -> DenNode{
+        DenNode::HalfSpaceNeg{plane : Plane{point : plane.point, normal : plane.normal.normalize()}}
+    }
+
+    pub fn union(self, other : DenNode) -> DenNode{
+        DenNode::Union(Box::new(self), Box::new(other))
+    }
+
+    pub fn intersection(self, other : DenNode) -> DenNode{
+        DenNode::Intersection(Box::new(self), Box::new(other))
+    }
+
+    pub fn difference(self, other : DenNode) -> DenNode{
+        DenNode::Difference(Box::new(self), Box::new(other))
+    }
+
+    pub fn smooth_union(self, other : DenNode, radius : f32, kind : BlendKind) -> DenNode{
+        DenNode::SmoothUnion{a : Box::new(self), b : Box::new(other), radius, kind}
+    }
+
+    pub fn smooth_intersection(self, other : DenNode, radius : f32, kind : BlendKind) -> DenNode{
+        DenNode::SmoothIntersection{a : Box::new(self), b : Box::new(other), radius, kind}
+    }
+
+    pub fn smooth_difference(self, other : DenNode, radius : f32, kind : BlendKind) -> DenNode{
+        DenNode::SmoothDifference{a : Box::new(self), b : Box::new(other), radius, kind}
+    }
+
+    pub fn translate(self, offset : Vector3<f32>) -> DenNode{
+        DenNode::Translate{offset, node : Box::new(self)}
+    }
+
+    pub fn rotate(self, rotation : UnitQuaternion<f32>) -> DenNode{
+        DenNode::Rotate{rotation, node : Box::new(self)}
+    }
+
+    //`angle` in radians
+    pub fn rotate_axis_angle(self, axis : Vector3<f32>, angle : f32) -> DenNode{
+        self.rotate(UnitQuaternion::from_axis_angle(&Unit::new_normalize(axis), angle))
+    }
+
+    pub fn scale(self, factor : f32) -> DenNode{
+        DenNode::Scale{factor, node : Box::new(self)}
+    }
+
+    pub fn scale_non_uniform(self, factor : Vector3<f32>) -> DenNode{
+        DenNode::ScaleNonUniform{factor, node : Box::new(self)}
+    }
+
+    pub fn mirror(self, plane : Plane<f32>) -> DenNode{
+        DenNode::Mirror{plane : Plane{point : plane.point, normal : plane.normal.normalize()}, node : Box::new(self)}
+    }
+
+    pub fn repeat(self, period : Vector3<f32>) -> DenNode{
+        DenNode::Repeat{period, limit : None, node : Box::new(self)}
+    }
+
+    pub fn repeat_bounded(self, period : Vector3<f32>, limit : Vector3<f32>) -> DenNode{
+        DenNode::Repeat{period, limit : Some(limit), node : Box::new(self)}
+    }
+
+    pub fn twist(self, rate : f32) -> DenNode{
+        DenNode::Twist{rate, node : Box::new(self)}
+    }
+
+    pub fn bend(self, rate : f32) -> DenNode{
+        DenNode::Bend{rate, node : Box::new(self)}
+    }
+
+    pub fn elongate(self, extent : Vector3<f32>) -> DenNode{
+        DenNode::Elongate{extent, node : Box::new(self)}
+    }
+
+    pub fn symmetry(self, plane : Plane<f32>) -> DenNode{
+        DenNode::Symmetry{plane : Plane{point : plane.point, normal : plane.normal.normalize()}, node : Box::new(self)}
+    }
+
+    //divides the density by `lipschitz`, the caller guarantees it is at least the lipschitz constant of the graph
+    //(for twists and bends it depends on the region the graph is evaluated in), otherwise the result is no distance bound
+    pub fn lipschitz_correct(self, lipschitz : f32) -> DenNode{
+        DenNode::LipschitzCorrect{lipschitz, node : Box::new(self)}
+    }
+
+
+    pub fn eval(&self, p : Vector3<f32>) -> f32{
+        match self{
+            &DenNode::Sphere{center, rad} => {
+                let dist = p - center;
+                dist.dot(&dist) - rad * rad
+            },
+            &DenNode::Aabb{center, extent} => {
+                let d = p - center;
+                (d.x.abs() - extent.x).max(d.y.abs() - extent.y).max(d.z.abs() - extent.z)
+            },
+            &DenNode::Obb{center, right, up, extent} => {
+                let look = right.cross(&up);
+                let d = p - center;
+                let dx = d.dot(&right).abs() - extent.x * right.norm_squared();
+                let dy = d.dot(&up).abs() - extent.y * up.norm_squared();
+                let dz = d.dot(&look).abs() - extent.z * look.norm_squared();
+                dx.max(dy).max(dz)
+            },
+            &DenNode::TorusZ{major_rad, minor_rad, offset} => {
+                let x = p - offset;
+                let a = (x.x * x.x + x.y * x.y).sqrt() - major_rad;
+                a * a + x.z * x.z - minor_rad * minor_rad
+            },
+            &DenNode::TorusY{major_rad, minor_rad, offset} => {
+                let x = p - offset;
+                let a = (x.x * x.x + x.z * x.z).sqrt() - major_rad;
+                a * a + x.y * x.y - minor_rad * minor_rad
+            },
+            &DenNode::HalfSpacePos{plane} => -(p - plane.point).dot(&plane.normal),
+            &DenNode::HalfSpaceNeg{plane} => (p - plane.point).dot(&plane.normal),
+            &DenNode::HeightNoise{ref perlin, ref cube, octaves, persistence} => height_noise(perlin, cube, octaves, persistence, p),
+
+            &DenNode::SdfSphere{center, rad} => (p - center).norm() - rad,
+            &DenNode::SdfAabb{center, extent} => sdf_box(p - center, extent),
+            &DenNode::SdfObb{center, right, up, extent} => {
+                let d = p - center;
+                sdf_box(Vector3::new(d.dot(&right), d.dot(&up), d.dot(&right.cross(&up))), extent)
+            },
+            &DenNode::SdfTorusZ{major_rad, minor_rad, offset} => {
+                let x = p - offset;
+                let a = (x.x * x.x + x.y * x.y).sqrt() - major_rad;
+                (a * a + x.z * x.z).sqrt() - minor_rad
+            },
+            &DenNode::SdfTorusY{major_rad, minor_rad, offset} => {
+                let x = p - offset;
+                let a = (x.x * x.x + x.z * x.z).sqrt() - major_rad;
+                (a * a + x.y * x.y).sqrt() - minor_rad
+            },
+            &DenNode::Capsule{a, b, rad} => sdf_capsule(p, a, b, rad),
+            &DenNode::CappedCylinder{a, b, rad} => sdf_capped_cylinder(p, a, b, rad),
+            &DenNode::CappedCone{a, b, rad_a, rad_b} => sdf_capped_cone(p, a, b, rad_a, rad_b),
+            &DenNode::RoundedBox{center, extent, radius} => sdf_rounded_box(p - center, extent, radius),
+            &DenNode::Ellipsoid{center, radii} => sdf_ellipsoid(p - center, radii),
+            &DenNode::HexPrism{center, rad, half_height} => sdf_hex_prism(p - center, rad, half_height),
+            &DenNode::TriPrism{center, rad, half_height} => sdf_tri_prism(p - center, rad, half_height),
+            &DenNode::Torus{center, axis, major_rad, minor_rad} => sdf_torus(p - center, axis, major_rad, minor_rad),
+
+            &DenNode::Union(ref a, ref b) => a.eval(p).min(b.eval(p)),
+            &DenNode::Intersection(ref a, ref b) => a.eval(p).max(b.eval(p)),
+            &DenNode::Difference(ref a, ref b) => a.eval(p).max(-b.eval(p)),
+            &DenNode::SmoothUnion{ref a, ref b, radius, kind} => smooth_min(a.eval(p), b.eval(p), radius, kind).0,
+            &DenNode::SmoothIntersection{ref a, ref b, radius, kind} => smooth_max(a.eval(p), b.eval(p), radius, kind).0,
+            &DenNode::SmoothDifference{ref a, ref b, radius, kind} => smooth_max(a.eval(p), -b.eval(p), radius, kind).0,
+
+            &DenNode::Translate{offset, ref node} => node.eval(p - offset),
+            &DenNode::Rotate{rotation, ref node} => node.eval(rotation.inverse() * p),
+            &DenNode::Scale{factor, ref node} => node.eval(p / factor) * factor,
+            &DenNode::ScaleNonUniform{factor, ref node} =>
+                node.eval(Vector3::new(p.x / factor.x, p.y / factor.y, p.z / factor.z)) * factor.x.min(factor.y).min(factor.z),
+            &DenNode::Mirror{ref plane, ref node} => node.eval(reflect_point3(p, plane)),
+            &DenNode::Repeat{period, limit, ref node} => node.eval(repeat_point3(p, period, limit)),
+            &DenNode::Twist{rate, ref node} => node.eval(twist_point3(p, rate)),
+            &DenNode::Bend{rate, ref node} => node.eval(bend_point3(p, rate)),
+            &DenNode::Elongate{extent, ref node} => node.eval(elongate_point3(p, extent)),
+            &DenNode::Symmetry{ref plane, ref node} => node.eval(symmetry_point3(p, plane)),
+            &DenNode::LipschitzCorrect{lipschitz, ref node} => node.eval(p) / lipschitz,
+        }
+    }
+
+    //`eval` of many points at once, `out` has the same length as `points`
+    //each node is visited once per batch instead of once per point and the primitives run tight loops over the points
+    pub fn eval_batch(&self, points : &[Vector3<f32>], out : &mut [f32]){
+        match self{
+            &DenNode::Union(ref a, ref b) => combine_batch(a, b, points, out, |x, y| x.min(y)),
+            &DenNode::Intersection(ref a, ref b) => combine_batch(a, b, points, out, |x, y| x.max(y)),
+            &DenNode::Difference(ref a, ref b) => combine_batch(a, b, points, out, |x, y| x.max(-y)),
+            &DenNode::SmoothUnion{ref a, ref b, radius, kind} => combine_batch(a, b, points, out, |x, y| smooth_min(x, y, radius, kind).0),
+            &DenNode::SmoothIntersection{ref a, ref b, radius, kind} => combine_batch(a, b, points, out, |x, y| smooth_max(x, y, radius, kind).0),
+            &DenNode::SmoothDifference{ref a, ref b, radius, kind} => combine_batch(a, b, points, out, |x, y| smooth_max(x, -y, radius, kind).0),
+
+            &DenNode::Translate{offset, ref node} => mapped_batch(node, points, out, |p| p - offset),
+            &DenNode::Rotate{rotation, ref node} => {
+                let inv = rotation.inverse();
+                mapped_batch(node, points, out, |p| inv * p)
+            },
+            &DenNode::Scale{factor, ref node} => {
+                mapped_batch(node, points, out, |p| p / factor);
+                for d in out.iter_mut() {*d *= factor;}
+            },
+            &DenNode::ScaleNonUniform{factor, ref node} => {
+                mapped_batch(node, points, out, |p| Vector3::new(p.x / factor.x, p.y / factor.y, p.z / factor.z));
+                let min_factor = factor.x.min(factor.y).min(factor.z);
+                for d in out.iter_mut() {*d *= min_factor;}
+            },
+            &DenNode::Mirror{ref plane, ref node} => mapped_batch(node, points, out, |p| reflect_point3(p, plane)),
+            &DenNode::Repeat{period, limit, ref node} => mapped_batch(node, points, out, |p| repeat_point3(p, period, limit)),
+            &DenNode::Twist{rate, ref node} => mapped_batch(node, points, out, |p| twist_point3(p, rate)),
+            &DenNode::Bend{rate, ref node} => mapped_batch(node, points, out, |p| bend_point3(p, rate)),
+            &DenNode::Elongate{extent, ref node} => mapped_batch(node, points, out, |p| elongate_point3(p, extent)),
+            &DenNode::Symmetry{ref plane, ref node} => mapped_batch(node, points, out, |p| symmetry_point3(p, plane)),
+            &DenNode::LipschitzCorrect{lipschitz, ref node} => {
+                node.eval_batch(points, out);
+                for d in out.iter_mut() {*d /= lipschitz;}
+            },
+
+            &DenNode::SdfSphere{center, rad} => {
+                for (p, d) in points.iter().zip(out.iter_mut()) {*d = (p - center).norm() - rad;}
+            },
+            &DenNode::SdfAabb{center, extent} => {
+                for (p, d) in points.iter().zip(out.iter_mut()) {*d = sdf_box(p - center, extent);}
+            },
+            _ => {
+                for (p, d) in points.iter().zip(out.iter_mut()) {*d = self.eval(*p);}
+            },
+        }
+    }
+
+    //the graph still can be used anywhere a `DenFn3` is expected (construct_grid, fill_in_grid, ...)
+    pub fn to_den_fn(self) -> DenFn3<f32>{
+        Box::new(move |p| self.eval(p))
+    }
+
+    //value and gradient at `p`
+    //gradients are hand-written for the common primitives and all combinators (chain rule),
+    //the remaining nodes (noise, twist, bend, the more involved primitives) have none, so the caller can pick its own finite differences
+    pub fn eval_grad(&self, p : Vector3<f32>) -> (f32, Option<Vector3<f32>>){
+        match self{
+            &DenNode::Sphere{center, rad} => {
+                let dist = p - center;
+                (dist.dot(&dist) - rad * rad, Some(dist * 2.0))
+            },
+            &DenNode::TorusZ{major_rad, minor_rad, offset} => {
+                let x = p - offset;
+                let len = (x.x * x.x + x.y * x.y).sqrt();
+                let a = len - major_rad;
+                let s = if len > 0.0 {2.0 * a / len} else {0.0};
+                (a * a + x.z * x.z - minor_rad * minor_rad, Some(Vector3::new(x.x * s, x.y * s, 2.0 * x.z)))
+            },
+            &DenNode::TorusY{major_rad, minor_rad, offset} => {
+                let x = p - offset;
+                let len = (x.x * x.x + x.z * x.z).sqrt();
+                let a = len - major_rad;
+                let s = if len > 0.0 {2.0 * a / len} else {0.0};
+                (a * a + x.y * x.y - minor_rad * minor_rad, Some(Vector3::new(x.x * s, 2.0 * x.y, x.z * s)))
+            },
+            &DenNode::HalfSpacePos{plane} => (-(p - plane.point).dot(&plane.normal), Some(-plane.normal)),
+            &DenNode::HalfSpaceNeg{plane} => ((p - plane.point).dot(&plane.normal), Some(plane.normal)),
+
+            &DenNode::SdfSphere{center, rad} => {
+                let d = p - center;
+                let len = d.norm();
+                (len - rad, Some(if len > 0.0 {d / len} else {Vector3::y()}))
+            },
+            &DenNode::SdfAabb{center, extent} => (sdf_box(p - center, extent), Some(sdf_box_grad(p - center, extent))),
+            &DenNode::SdfObb{center, right, up, extent} => {
+                let look = right.cross(&up);
+                let d = p - center;
+                let local = Vector3::new(d.dot(&right), d.dot(&up), d.dot(&look));
+                let g = sdf_box_grad(local, extent);
+                (sdf_box(local, extent), Some(right * g.x + up * g.y + look * g.z))
+            },
+            &DenNode::RoundedBox{center, extent, radius} => {
+                let d = p - center;
+                (sdf_rounded_box(d, extent, radius), Some(sdf_box_grad(d, extent - Vector3::new(radius, radius, radius))))
+            },
+            &DenNode::Capsule{a, b, rad} => {
+                let pa = p - a;
+                let ba = b - a;
+                let h = (pa.dot(&ba) / ba.dot(&ba)).max(0.0).min(1.0);
+                let d = pa - ba * h;
+                let len = d.norm();
+                (len - rad, Some(if len > 0.0 {d / len} else {Vector3::y()}))
+            },
+            &DenNode::SdfTorusZ{major_rad, offset, ..} => (self.eval(p), Some(torus_grad(p - offset, Vector3::z(), major_rad))),
+            &DenNode::SdfTorusY{major_rad, offset, ..} => (self.eval(p), Some(torus_grad(p - offset, Vector3::y(), major_rad))),
+            &DenNode::Torus{center, axis, major_rad, ..} => (self.eval(p), Some(torus_grad(p - center, axis, major_rad))),
+
+            &DenNode::Union(ref a, ref b) => {
+                let (va, ga) = a.eval_grad(p);
+                let (vb, gb) = b.eval_grad(p);
+                if va <= vb {(va, ga)} else {(vb, gb)}
+            },
+            &DenNode::Intersection(ref a, ref b) => {
+                let (va, ga) = a.eval_grad(p);
+                let (vb, gb) = b.eval_grad(p);
+                if va >= vb {(va, ga)} else {(vb, gb)}
+            },
+            &DenNode::Difference(ref a, ref b) => {
+                let (va, ga) = a.eval_grad(p);
+                let (vb, gb) = b.eval_grad(p);
+                if va >= -vb {(va, ga)} else {(-vb, gb.map(|g| -g))}
+            },
+            //the blend weight is also the partial derivative of `smooth_min`/`smooth_max` by `a`
+            &DenNode::SmoothUnion{ref a, ref b, radius, kind} => {
+                let (va, ga) = a.eval_grad(p);
+                let (vb, gb) = b.eval_grad(p);
+                let (v, w) = smooth_min(va, vb, radius, kind);
+                (v, blend_grad(ga, gb, w, 1.0 - w))
+            },
+            &DenNode::SmoothIntersection{ref a, ref b, radius, kind} => {
+                let (va, ga) = a.eval_grad(p);
+                let (vb, gb) = b.eval_grad(p);
+                let (v, w) = smooth_max(va, vb, radius, kind);
+                (v, blend_grad(ga, gb, w, 1.0 - w))
+            },
+            &DenNode::SmoothDifference{ref a, ref b, radius, kind} => {
+                let (va, ga) = a.eval_grad(p);
+                let (vb, gb) = b.eval_grad(p);
+                let (v, w) = smooth_max(va, -vb, radius, kind);
+                (v, blend_grad(ga, gb, w, w - 1.0))
+            },
+
+            &DenNode::Translate{offset, ref node} => node.eval_grad(p - offset),
+            &DenNode::Rotate{rotation, ref node} => {
+                let (v, g) = node.eval_grad(rotation.inverse() * p);
+                (v, g.map(|g| rotation * g))
+            },
+            &DenNode::Scale{factor, ref node} => {
+                let (v, g) = node.eval_grad(p / factor);
+                (v * factor, g)
+            },
+            &DenNode::ScaleNonUniform{factor, ref node} => {
+                let m = factor.x.min(factor.y).min(factor.z);
+                let (v, g) = node.eval_grad(Vector3::new(p.x / factor.x, p.y / factor.y, p.z / factor.z));
+                (v * m, g.map(|g| Vector3::new(g.x * m / factor.x, g.y * m / factor.y, g.z * m / factor.z)))
+            },
+            &DenNode::Mirror{ref plane, ref node} => {
+                let (v, g) = node.eval_grad(reflect_point3(p, plane));
+                (v, g.map(|g| g - plane.normal * (2.0 * g.dot(&plane.normal))))
+            },
+            &DenNode::Repeat{period, limit, ref node} => node.eval_grad(repeat_point3(p, period, limit)),
+            &DenNode::Elongate{extent, ref node} => {
+                let (v, g) = node.eval_grad(elongate_point3(p, extent));
+                let mask = |x : f32, e : f32, g : f32| if x.abs() > e {g} else {0.0};
+                (v, g.map(|g| Vector3::new(mask(p.x, extent.x, g.x), mask(p.y, extent.y, g.y), mask(p.z, extent.z, g.z))))
+            },
+            &DenNode::Symmetry{ref plane, ref node} => {
+                let q = symmetry_point3(p, plane);
+                let (v, g) = node.eval_grad(q);
+                (v, if q == p {g} else {g.map(|g| g - plane.normal * (2.0 * g.dot(&plane.normal)))})
+            },
+            &DenNode::LipschitzCorrect{lipschitz, ref node} => {
+                let (v, g) = node.eval_grad(p);
+                (v / lipschitz, g.map(|g| g / lipschitz))
+            },
+
+            _ => (self.eval(p), None),
+        }
+    }
+
+    //conservative range of values over the box [min, max] (interval arithmetic)
+    //if the range excludes zero the box is proven to be fully inside or outside of the solid
+    pub fn bounds(&self, min : Vector3<f32>, max : Vector3<f32>) -> (f32, f32){
+        match self{
+            &DenNode::Sphere{center, rad} => {
+                let (lo, hi) = add_range(add_range(sq_range(min.x - center.x, max.x - center.x), sq_range(min.y - center.y, max.y - center.y)), sq_range(min.z - center.z, max.z - center.z));
+                (lo - rad * rad, hi - rad * rad)
+            },
+            &DenNode::Aabb{center, extent} => {
+                let x = abs_range(min.x - center.x, max.x - center.x);
+                let y = abs_range(min.y - center.y, max.y - center.y);
+                let z = abs_range(min.z - center.z, max.z - center.z);
+                ((x.0 - extent.x).max(y.0 - extent.y).max(z.0 - extent.z), (x.1 - extent.x).max(y.1 - extent.y).max(z.1 - extent.z))
+            },
+            &DenNode::Obb{center, right, up, extent} => {
+                let look = right.cross(&up);
+                let axis = |u : Vector3<f32>, e : f32| {
+                    let (lo, hi) = abs_range_of(linear_range(min - center, max - center, u));
+                    (lo - e * u.norm_squared(), hi - e * u.norm_squared())
+                };
+                let (x, y, z) = (axis(right, extent.x), axis(up, extent.y), axis(look, extent.z));
+                (x.0.max(y.0).max(z.0), x.1.max(y.1).max(z.1))
+            },
+            &DenNode::TorusZ{major_rad, minor_rad, offset} => torus_range(abs_range(min.x - offset.x, max.x - offset.x), abs_range(min.y - offset.y, max.y - offset.y), (min.z - offset.z, max.z - offset.z), major_rad, minor_rad),
+            &DenNode::TorusY{major_rad, minor_rad, offset} => torus_range(abs_range(min.x - offset.x, max.x - offset.x), abs_range(min.z - offset.z, max.z - offset.z), (min.y - offset.y, max.y - offset.y), major_rad, minor_rad),
+            &DenNode::HalfSpacePos{plane} => {
+                let (lo, hi) = linear_range(min - plane.point, max - plane.point, plane.normal);
+                (-hi, -lo)
+            },
+            &DenNode::HalfSpaceNeg{plane} => linear_range(min - plane.point, max - plane.point, plane.normal),
+            &DenNode::HeightNoise{ref cube, ..} => {
+                let (cmin, cmax) = (cube.min(), cube.max());
+                let overlap_min = Vector3::new(min.x.max(cmin.x), min.y.max(cmin.y), min.z.max(cmin.z));
+                let overlap_max = Vector3::new(max.x.min(cmax.x), max.y.min(cmax.y), max.z.min(cmax.z));
+                if overlap_min.x > overlap_max.x || overlap_min.y > overlap_max.y || overlap_min.z > overlap_max.z {
+                    (0.01, 0.01)
+                }else{
+                    //the octave sum is in [0, 1], with some slack as perlin noise can slightly overshoot [-1, 1]
+                    let lo = overlap_min.y - cmin.y - 2.2 * cube.extent;
+                    let hi = overlap_max.y - cmin.y + 0.2 * cube.extent;
+                    let inside = min == overlap_min && max == overlap_max;
+                    if inside {(lo, hi)} else {(lo.min(0.01), hi.max(0.01))}
+                }
+            },
+
+            //distances change at most as fast as the point moves
+            &DenNode::SdfSphere{..} | &DenNode::SdfAabb{..} | &DenNode::SdfObb{..} | &DenNode::SdfTorusZ{..} | &DenNode::SdfTorusY{..} |
+            &DenNode::Capsule{..} | &DenNode::CappedCylinder{..} | &DenNode::CappedCone{..} | &DenNode::RoundedBox{..} |
+            &DenNode::Ellipsoid{..} | &DenNode::HexPrism{..} | &DenNode::TriPrism{..} | &DenNode::Torus{..} => {
+                let v = self.eval((min + max) * 0.5);
+                let r = (max - min).norm() * 0.5;
+                (v - r, v + r)
+            },
+
+            &DenNode::Union(ref a, ref b) => {
+                let (a, b) = (a.bounds(min, max), b.bounds(min, max));
+                (a.0.min(b.0), a.1.min(b.1))
+            },
+            &DenNode::Intersection(ref a, ref b) => {
+                let (a, b) = (a.bounds(min, max), b.bounds(min, max));
+                (a.0.max(b.0), a.1.max(b.1))
+            },
+            &DenNode::Difference(ref a, ref b) => {
+                let (a, b) = (a.bounds(min, max), b.bounds(min, max));
+                (a.0.max(-b.1), a.1.max(-b.0))
+            },
+            //smooth minimum is at most `blend_offset` below the hard one
+            &DenNode::SmoothUnion{ref a, ref b, radius, kind} => {
+                let (a, b) = (a.bounds(min, max), b.bounds(min, max));
+                (a.0.min(b.0) - blend_offset(radius, kind), a.1.min(b.1))
+            },
+            &DenNode::SmoothIntersection{ref a, ref b, radius, kind} => {
+                let (a, b) = (a.bounds(min, max), b.bounds(min, max));
+                (a.0.max(b.0), a.1.max(b.1) + blend_offset(radius, kind))
+            },
+            &DenNode::SmoothDifference{ref a, ref b, radius, kind} => {
+                let (a, b) = (a.bounds(min, max), b.bounds(min, max));
+                (a.0.max(-b.1), a.1.max(-b.0) + blend_offset(radius, kind))
+            },
+
+            &DenNode::Translate{offset, ref node} => node.bounds(min - offset, max - offset),
+            &DenNode::Rotate{rotation, ref node} => {
+                let inv = rotation.inverse();
+                let (lo, hi) = aabb_of_corners(min, max, |p| inv * p);
+                node.bounds(lo, hi)
+            },
+            &DenNode::Scale{factor, ref node} => {
+                let (lo, hi) = node.bounds(min / factor, max / factor);
+                (lo * factor, hi * factor)
+            },
+            &DenNode::ScaleNonUniform{factor, ref node} => {
+                let m = factor.x.min(factor.y).min(factor.z);
+                let (lo, hi) = node.bounds(Vector3::new(min.x / factor.x, min.y / factor.y, min.z / factor.z), Vector3::new(max.x / factor.x, max.y / factor.y, max.z / factor.z));
+                (lo * m, hi * m)
+            },
+            &DenNode::Mirror{ref plane, ref node} => {
+                let (lo, hi) = aabb_of_corners(min, max, |p| reflect_point3(p, plane));
+                node.bounds(lo, hi)
+            },
+            &DenNode::Repeat{period, limit, ref node} => {
+                let x = repeat_range(min.x, max.x, period.x, limit.map(|l| l.x));
+                let y = repeat_range(min.y, max.y, period.y, limit.map(|l| l.y));
+                let z = repeat_range(min.z, max.z, period.z, limit.map(|l| l.z));
+                node.bounds(Vector3::new(x.0, y.0, z.0), Vector3::new(x.1, y.1, z.1))
+            },
+            //rotations around the axis keep the distance to it
+            &DenNode::Twist{ref node, ..} => {
+                let r = (abs_range(min.x, max.x).1.powi(2) + abs_range(min.z, max.z).1.powi(2)).sqrt();
+                node.bounds(Vector3::new(-r, min.y, -r), Vector3::new(r, max.y, r))
+            },
+            &DenNode::Bend{ref node, ..} => {
+                let r = (abs_range(min.x, max.x).1.powi(2) + abs_range(min.y, max.y).1.powi(2)).sqrt();
+                node.bounds(Vector3::new(-r, -r, min.z), Vector3::new(r, r, max.z))
+            },
+            &DenNode::Elongate{extent, ref node} => node.bounds(elongate_point3(min, extent), elongate_point3(max, extent)), //monotone per axis
+            &DenNode::Symmetry{ref plane, ref node} => {
+                let (dist_lo, dist_hi) = linear_range(min - plane.point, max - plane.point, plane.normal);
+                if dist_lo >= 0.0{
+                    node.bounds(min, max)
+                }else{
+                    let (lo, hi) = aabb_of_corners(min, max, |p| reflect_point3(p, plane));
+                    if dist_hi < 0.0{
+                        node.bounds(lo, hi)
+                    }else{
+                        node.bounds(Vector3::new(lo.x.min(min.x), lo.y.min(min.y), lo.z.min(min.z)), Vector3::new(hi.x.max(max.x), hi.y.max(max.y), hi.z.max(max.z)))
+                    }
+                }
+            },
+            &DenNode::LipschitzCorrect{lipschitz, ref node} => {
+                let (lo, hi) = node.bounds(min, max);
+                (lo / lipschitz, hi / lipschitz)
+            },
+        }
+    }
+
+    //whether density values of this graph are real distances, see `DistanceKind`
+    pub fn distance_kind(&self) -> DistanceKind{
+        self.kind(false)
+    }
+
+    //`warps_corrected` - a `LipschitzCorrect` node above compensates twists and bends
+    fn kind(&self, warps_corrected : bool) -> DistanceKind{
+        match self{
+            &DenNode::SdfSphere{..} | &DenNode::SdfAabb{..} | &DenNode::SdfObb{..} | &DenNode::SdfTorusZ{..} | &DenNode::SdfTorusY{..} |
+            &DenNode::Capsule{..} | &DenNode::CappedCylinder{..} | &DenNode::CappedCone{..} | &DenNode::RoundedBox{..} |
+            &DenNode::HexPrism{..} | &DenNode::TriPrism{..} | &DenNode::Torus{..} => DistanceKind::Exact,
+            &DenNode::Ellipsoid{..} => DistanceKind::Bound,
+            &DenNode::HalfSpacePos{plane} | &DenNode::HalfSpaceNeg{plane} => {
+                if (plane.normal.norm() - 1.0).abs() < 1e-5 {DistanceKind::Exact} else {DistanceKind::Implicit}
+            },
+            &DenNode::Aabb{..} => DistanceKind::Bound, //max of distances to the faces
+            &DenNode::Obb{right, up, ..} => {
+                let unit = |v : Vector3<f32>| (v.norm() - 1.0).abs() < 1e-5;
+                if unit(right) && unit(up) && right.dot(&up).abs() < 1e-5 {DistanceKind::Bound} else {DistanceKind::Implicit}
+            },
+            &DenNode::Sphere{..} | &DenNode::TorusZ{..} | &DenNode::TorusY{..} | &DenNode::HeightNoise{..} => DistanceKind::Implicit,
+
+            //min/max (smooth ones too, see `smooth_min`) of distance bounds is a distance bound, but not an exact distance anymore
+            &DenNode::Union(ref a, ref b) | &DenNode::Intersection(ref a, ref b) | &DenNode::Difference(ref a, ref b) |
+            &DenNode::SmoothUnion{ref a, ref b, ..} | &DenNode::SmoothIntersection{ref a, ref b, ..} | &DenNode::SmoothDifference{ref a, ref b, ..} => {
+                if a.kind(warps_corrected) == DistanceKind::Implicit || b.kind(warps_corrected) == DistanceKind::Implicit {
+                    DistanceKind::Implicit
+                }else{
+                    DistanceKind::Bound
+                }
+            },
+            &DenNode::Translate{ref node, ..} | &DenNode::Rotate{ref node, ..} | &DenNode::Scale{ref node, ..} | &DenNode::Mirror{ref node, ..} => node.kind(warps_corrected),
+            &DenNode::ScaleNonUniform{ref node, ..} | &DenNode::Repeat{ref node, ..} | &DenNode::Elongate{ref node, ..} | &DenNode::Symmetry{ref node, ..} =>
+                if node.kind(warps_corrected) == DistanceKind::Implicit {DistanceKind::Implicit} else {DistanceKind::Bound},
+            &DenNode::Twist{ref node, ..} | &DenNode::Bend{ref node, ..} =>
+                if warps_corrected && node.kind(true) != DistanceKind::Implicit {DistanceKind::Bound} else {DistanceKind::Implicit},
+            //a bound stays one when divided by at least 1, under warps the constant is the caller's guarantee (see `lipschitz_correct`)
+            &DenNode::LipschitzCorrect{lipschitz, ref node} => match (node.kind(false), node.kind(true)){
+                (DistanceKind::Implicit, DistanceKind::Implicit) => DistanceKind::Implicit,
+                (DistanceKind::Implicit, _) => DistanceKind::Bound,
+                _ => if lipschitz >= 1.0 {DistanceKind::Bound} else {DistanceKind::Implicit},
+            },
+        }
+    }
+
+    pub fn is_exact_sdf(&self) -> bool{
+        self.distance_kind() == DistanceKind::Exact
+    }
+
+    pub fn node_count(&self) -> usize{
+        match self{
+            &DenNode::Union(ref a, ref b) | &DenNode::Intersection(ref a, ref b) | &DenNode::Difference(ref a, ref b) |
+            &DenNode::SmoothUnion{ref a, ref b, ..} | &DenNode::SmoothIntersection{ref a, ref b, ..} | &DenNode::SmoothDifference{ref a, ref b, ..} => 1 + a.node_count() + b.node_count(),
+            &DenNode::Translate{ref node, ..} | &DenNode::Rotate{ref node, ..} | &DenNode::Scale{ref node, ..} |
+            &DenNode::ScaleNonUniform{ref node, ..} | &DenNode::Mirror{ref node, ..} |
+            &DenNode::Repeat{ref node, ..} | &DenNode::Twist{ref node, ..} | &DenNode::Bend{ref node, ..} |
+            &DenNode::Elongate{ref node, ..} | &DenNode::Symmetry{ref node, ..} | &DenNode::LipschitzCorrect{ref node, ..} => 1 + node.node_count(),
+            _ => 1,
+        }
+    }
+
+    pub fn depth(&self) -> usize{
+        match self{
+            &DenNode::Union(ref a, ref b) | &DenNode::Intersection(ref a, ref b) | &DenNode::Difference(ref a, ref b) |
+            &DenNode::SmoothUnion{ref a, ref b, ..} | &DenNode::SmoothIntersection{ref a, ref b, ..} | &DenNode::SmoothDifference{ref a, ref b, ..} => 1 + std::cmp::max(a.depth(), b.depth()),
+            &DenNode::Translate{ref node, ..} | &DenNode::Rotate{ref node, ..} | &DenNode::Scale{ref node, ..} |
+            &DenNode::ScaleNonUniform{ref node, ..} | &DenNode::Mirror{ref node, ..} |
+            &DenNode::Repeat{ref node, ..} | &DenNode::Twist{ref node, ..} | &DenNode::Bend{ref node, ..} |
+            &DenNode::Elongate{ref node, ..} | &DenNode::Symmetry{ref node, ..} | &DenNode::LipschitzCorrect{ref node, ..} => 1 + node.depth(),
+            _ => 1,
+        }
+    }
+
+    //folds translations into primitives where possible, merges nested translations
+    //the result evaluates to the same values as the original graph
+    pub fn simplify(self) -> DenNode{
+        match self{
+            DenNode::Union(a, b) => a.simplify().union(b.simplify()),
+            DenNode::Intersection(a, b) => a.simplify().intersection(b.simplify()),
+            DenNode::Difference(a, b) => a.simplify().difference(b.simplify()),
+            DenNode::SmoothUnion{a, b, radius, kind} => a.simplify().smooth_union(b.simplify(), radius, kind),
+            DenNode::SmoothIntersection{a, b, radius, kind} => a.simplify().smooth_intersection(b.simplify(), radius, kind),
+            DenNode::SmoothDifference{a, b, radius, kind} => a.simplify().smooth_difference(b.simplify(), radius, kind),
+            DenNode::Translate{offset, node} => node.simplify().moved(offset),
+            DenNode::Rotate{rotation, node} => node.simplify().rotate(rotation),
+            DenNode::Scale{factor, node} => node.simplify().scale(factor),
+            DenNode::ScaleNonUniform{factor, node} => node.simplify().scale_non_uniform(factor),
+            DenNode::Mirror{plane, node} => node.simplify().mirror(plane),
+            DenNode::Repeat{period, limit, node} => DenNode::Repeat{period, limit, node : Box::new(node.simplify())},
+            DenNode::Twist{rate, node} => node.simplify().twist(rate),
+            DenNode::Bend{rate, node} => node.simplify().bend(rate),
+            DenNode::Elongate{extent, node} => node.simplify().elongate(extent),
+            DenNode::Symmetry{plane, node} => node.simplify().symmetry(plane),
+            DenNode::LipschitzCorrect{lipschitz, node} => node.simplify().lipschitz_correct(lipschitz),
+            other => other,
+        }
+    }
+
+    //pushes translation down to the leaves, `self` is assumed to be simplified
+    fn moved(self, by : Vector3<f32>) -> DenNode{
+        if by == Vector3::zeros() {return self}
+
+        match self{
+            DenNode::Sphere{center, rad} => DenNode::Sphere{center : center + by, rad},
+            DenNode::Aabb{center, extent} => DenNode::Aabb{center : center + by, extent},
+            DenNode::Obb{center, right, up, extent} => DenNode::Obb{center : center + by, right, up, extent},
+            DenNode::TorusZ{major_rad, minor_rad, offset} => DenNode::TorusZ{major_rad, minor_rad, offset : offset + by},
+            DenNode::TorusY{major_rad, minor_rad, offset} => DenNode::TorusY{major_rad, minor_rad, offset : offset + by},
+            DenNode::HalfSpacePos{plane} => DenNode::HalfSpacePos{plane : Plane{point : plane.point + by, normal : plane.normal}},
+            DenNode::HalfSpaceNeg{plane} => DenNode::HalfSpaceNeg{plane : Plane{point : plane.point + by, normal : plane.normal}},
+            DenNode::HeightNoise{perlin, cube, octaves, persistence} => DenNode::HeightNoise{perlin, cube : Square3{center : cube.center + by, extent : cube.extent}, octaves, persistence}, //noise is sampled relative to its cube
+            DenNode::SdfSphere{center, rad} => DenNode::SdfSphere{center : center + by, rad},
+            DenNode::SdfAabb{center, extent} => DenNode::SdfAabb{center : center + by, extent},
+            DenNode::SdfObb{center, right, up, extent} => DenNode::SdfObb{center : center + by, right, up, extent},
+            DenNode::SdfTorusZ{major_rad, minor_rad, offset} => DenNode::SdfTorusZ{major_rad, minor_rad, offset : offset + by},
+            DenNode::SdfTorusY{major_rad, minor_rad, offset} => DenNode::SdfTorusY{major_rad, minor_rad, offset : offset + by},
+            DenNode::Capsule{a, b, rad} => DenNode::Capsule{a : a + by, b : b + by, rad},
+            DenNode::CappedCylinder{a, b, rad} => DenNode::CappedCylinder{a : a + by, b : b + by, rad},
+            DenNode::CappedCone{a, b, rad_a, rad_b} => DenNode::CappedCone{a : a + by, b : b + by, rad_a, rad_b},
+            DenNode::RoundedBox{center, extent, radius} => DenNode::RoundedBox{center : center + by, extent, radius},
+            DenNode::Ellipsoid{center, radii} => DenNode::Ellipsoid{center : center + by, radii},
+            DenNode::HexPrism{center, rad, half_height} => DenNode::HexPrism{center : center + by, rad, half_height},
+            DenNode::TriPrism{center, rad, half_height} => DenNode::TriPrism{center : center + by, rad, half_height},
+            DenNode::Torus{center, axis, major_rad, minor_rad} => DenNode::Torus{center : center + by, axis, major_rad, minor_rad},
+            DenNode::Union(a, b) => a.moved(by).union(b.moved(by)),
+            DenNode::Intersection(a, b) => a.moved(by).intersection(b.moved(by)),
+            DenNode::Difference(a, b) => a.moved(by).difference(b.moved(by)),
+            DenNode::SmoothUnion{a, b, radius, kind} => a.moved(by).smooth_union(b.moved(by), radius, kind),
+            DenNode::SmoothIntersection{a, b, radius, kind} => a.moved(by).smooth_intersection(b.moved(by), radius, kind),
+            DenNode::SmoothDifference{a, b, radius, kind} => a.moved(by).smooth_difference(b.moved(by), radius, kind),
+            DenNode::Translate{offset, node} => node.moved(offset + by),
+
+            //the translation is moved into the local frame of the transform
+            DenNode::Rotate{rotation, node} => node.moved(rotation.inverse() * by).rotate(rotation),
+            DenNode::Scale{factor, node} => node.moved(by / factor).scale(factor),
+            DenNode::ScaleNonUniform{factor, node} => node.moved(Vector3::new(by.x / factor.x, by.y / factor.y, by.z / factor.z)).scale_non_uniform(factor),
+            DenNode::Mirror{plane, node} => node.moved(by - plane.normal * (2.0 * by.dot(&plane.normal))).mirror(plane),
+            DenNode::Symmetry{plane, node} => node.moved(by).symmetry(Plane{point : plane.point + by, normal : plane.normal}),
+            DenNode::LipschitzCorrect{lipschitz, node} => node.moved(by).lipschitz_correct(lipschitz),
+
+            //deformations depend on the position relative to their origin
+            other @ DenNode::Repeat{..} | other @ DenNode::Twist{..} | other @ DenNode::Bend{..} | other @ DenNode::Elongate{..} =>
+                other.translate(by),
+        }
+    }
+}
+
+//`ga` * `wa` + `gb` * `wb`, a gradient with zero weight may be unknown
+fn blend_grad(ga : Option<Vector3<f32>>, gb : Option<Vector3<f32>>, wa : f32, wb : f32) -> Option<Vector3<f32>>{
+    match (ga, gb){
+        (Some(ga), Some(gb)) => Some(ga * wa + gb * wb),
+        (ga, _) if wb == 0.0 => ga.map(|g| g * wa),
+        (_, gb) if wa == 0.0 => gb.map(|g| g * wb),
+        _ => None,
+    }
+}
+
+//gradient of `sdf_box`, `d` - point in the local frame of the box
+fn sdf_box_grad(d : Vector3<f32>, extent : Vector3<f32>) -> Vector3<f32>{
+    let sign = |x : f32| if x < 0.0 {-1.0} else {1.0};
+    let q = Vector3::new(d.x.abs() - extent.x, d.y.abs() - extent.y, d.z.abs() - extent.z);
+    let outside = Vector3::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0));
+    let len = outside.norm();
+
+    let local = if len > 0.0 {
+        outside / len
+    }else if q.x >= q.y && q.x >= q.z {
+        Vector3::x()
+    }else if q.y >= q.z {
+        Vector3::y()
+    }else{
+        Vector3::z()
+    };
+
+    Vector3::new(local.x * sign(d.x), local.y * sign(d.y), local.z * sign(d.z))
+}
+
+//gradient of `sdf_torus`, `d` - point relative to the center
+fn torus_grad(d : Vector3<f32>, axis : Vector3<f32>, major_rad : f32) -> Vector3<f32>{
+    let n = axis.normalize();
+    let h = d.dot(&n);
+    let radial = d - n * h;
+    let len = radial.norm();
+    let ring = if len > 0.0 {radial * (major_rad / len)} else {Vector3::zeros()}; //closest point on the center circle
+    let to = d - ring;
+    let to_len = to.norm();
+    if to_len > 0.0 {to / to_len} else {n}
+}
+
+//interval helpers for `DenNode::bounds`, ranges are (lo, hi) pairs
+
+fn add_range(a : (f32, f32), b : (f32, f32)) -> (f32, f32){
+    (a.0 + b.0, a.1 + b.1)
+}
+
+fn abs_range(lo : f32, hi : f32) -> (f32, f32){
+    if lo >= 0.0 {(lo, hi)} else if hi <= 0.0 {(-hi, -lo)} else {(0.0, hi.max(-lo))}
+}
+
+fn abs_range_of(r : (f32, f32)) -> (f32, f32){
+    abs_range(r.0, r.1)
+}
+
+fn sq_range(lo : f32, hi : f32) -> (f32, f32){
+    let (lo, hi) = abs_range(lo, hi);
+    (lo * lo, hi * hi)
+}
+
+//range of d.dot(n) for d in the box [min, max]
+fn linear_range(min : Vector3<f32>, max : Vector3<f32>, n : Vector3<f32>) -> (f32, f32){
+    let center = (min + max) * 0.5;
+    let half = (max - min) * 0.5;
+    let c = center.dot(&n);
+    let r = half.x * n.x.abs() + half.y * n.y.abs() + half.z * n.z.abs();
+    (c - r, c + r)
+}
+
+//`u`, `v` - ranges of the absolute in-plane coordinates, `h` - coordinate along the axis
+fn torus_range(u : (f32, f32), v : (f32, f32), h : (f32, f32), major_rad : f32, minor_rad : f32) -> (f32, f32){
+    let radial = ((u.0 * u.0 + v.0 * v.0).sqrt(), (u.1 * u.1 + v.1 * v.1).sqrt());
+    let (lo, hi) = add_range(sq_range(radial.0 - major_rad, radial.1 - major_rad), sq_range(h.0, h.1));
+    (lo - minor_rad * minor_rad, hi - minor_rad * minor_rad)
+}
+
+//how far below the hard minimum `smooth_min` can go
+fn blend_offset(radius : f32, kind : BlendKind) -> f32{
+    match kind{
+        BlendKind::Polynomial => radius * 0.25,
+        BlendKind::Exponential => radius * std::f32::consts::LN_2,
+    }
+}
+
+//bounding box of the box [min, max] mapped by an affine `f`
+fn aabb_of_corners<F : Fn(Vector3<f32>) -> Vector3<f32>>(min : Vector3<f32>, max : Vector3<f32>, f : F) -> (Vector3<f32>, Vector3<f32>){
+    let mut lo = Vector3::new(std::f32::MAX, std::f32::MAX, std::f32::MAX);
+    let mut hi = -lo;
+    for i in 0..8{
+        let corner = Vector3::new(if i & 1 == 0 {min.x} else {max.x}, if i & 2 == 0 {min.y} else {max.y}, if i & 4 == 0 {min.z} else {max.z});
+        let p = f(corner);
+        lo = Vector3::new(lo.x.min(p.x), lo.y.min(p.y), lo.z.min(p.z));
+        hi = Vector3::new(hi.x.max(p.x), hi.y.max(p.y), hi.z.max(p.z));
+    }
+    (lo, hi)
+}
+
+//range of the repeated coordinate (see `repeat_point3`) over [lo, hi]
+fn repeat_range(lo : f32, hi : f32, period : f32, limit : Option<f32>) -> (f32, f32){
+    if period <= 0.0 {return (lo, hi)}
+    let cell = |x : f32| {
+        let c = (x / period).round();
+        match limit {Some(l) => c.max(-l).min(l), None => c}
+    };
+    let (cell_lo, cell_hi) = (cell(lo), cell(hi));
+    let (q_lo, q_hi) = (lo - period * cell_lo, hi - period * cell_hi);
+
+    //within one cell the mapping is a shift, across cells it covers the whole cell (and the clamped tails)
+    if cell_lo == cell_hi {(q_lo, q_hi)} else {(q_lo.min(-period * 0.5), q_hi.max(period * 0.5))}
+}
+
+//batch of two operands combined point by point with `op`
+fn combine_batch<F : Fn(f32, f32) -> f32>(a : &DenNode, b : &DenNode, points : &[Vector3<f32>], out : &mut [f32], op : F){
+    a.eval_batch(points, out);
+    let mut other = vec![0.0;points.len()];
+    b.eval_batch(points, &mut other);
+    for (d, o) in out.iter_mut().zip(other.iter()) {*d = op(*d, *o);}
+}
+
+//batch of `node` at the points moved by `map`
+fn mapped_batch<F : Fn(Vector3<f32>) -> Vector3<f32>>(node : &DenNode, points : &[Vector3<f32>], out : &mut [f32], map : F){
+    let mapped : Vec<Vector3<f32>> = points.iter().map(|p| map(*p)).collect();
+    node.eval_batch(&mapped, out);
+}
+
+//anything the meshers can sample, shared between the threads that sample a grid
+pub trait Density3 : Send + Sync{
+    fn density(&self, p : Vector3<f32>) -> f32;
+
+    //densities at all `points` written to `out` (same length), grids and octrees are sampled through this
+    //the default samples the points one by one, override it to amortise dispatch or vectorise over the batch
+    fn density_batch(&self, points : &[Vector3<f32>], out : &mut [f32]){
+        for (p, d) in points.iter().zip(out.iter_mut()){
+            *d = self.density(*p);
+        }
+    }
+
+    //exact gradient when it is known, `None` lets the caller fall back to finite differences
+    fn gradient(&self, _p : Vector3<f32>) -> Option<Vector3<f32>>{
+        None
+    }
+
+    //conservative range of densities over the box [min, max], `None` if it can't be estimated
+    fn bounds(&self, _min : Vector3<f32>, _max : Vector3<f32>) -> Option<(f32, f32)>{
+        None
+    }
+}
+
+impl Density3 for DenFn3<f32>{
+    fn density(&self, p : Vector3<f32>) -> f32{
+        self(p)
+    }
+}
+
+impl Density3 for DenNode{
+    fn density(&self, p : Vector3<f32>) -> f32{
+        self.eval(p)
+    }
+
+    fn density_batch(&self, points : &[Vector3<f32>], out : &mut [f32]){
+        self.eval_batch(points, out)
+    }
+
+    fn gradient(&self, p : Vector3<f32>) -> Option<Vector3<f32>>{
+        self.eval_grad(p).1
+    }
+
+    fn bounds(&self, min : Vector3<f32>, max : Vector3<f32>) -> Option<(f32, f32)>{
+        Some(DenNode::bounds(self, min, max))
+    }
+}
+
+//hierarchical culling of a grid of `size` cells with edge `a`, `offset` - min corner of the grid
+//returns for each cell (x fastest, then y, then z) a density that has the sign of the whole cell,
+//or `None` if the cell may contain the surface; `None` overall if `f` has no bounds
+//blocks of cells are split in half until their range excludes zero, so empty space costs a few bound evaluations
+pub fn cull_cells<D : Density3>(f : &D, offset : Vector3<f32>, a : f32, size : [usize;3]) -> Option<Vec<Option<f32>>>{
+    if f.bounds(offset, offset).is_none() {return None}
+
+    let mut cells = vec![None; size[0] * size[1] * size[2]];
+    cull_block(f, offset, a, size, [0, 0, 0], size, &mut cells);
+    Some(cells)
+}
+
+fn cull_block<D : Density3>(f : &D, offset : Vector3<f32>, a : f32, size : [usize;3], lo : [usize;3], hi : [usize;3], cells : &mut Vec<Option<f32>>){
+    if lo[0] >= hi[0] || lo[1] >= hi[1] || lo[2] >= hi[2] {return}
+
+    let min = offset + Vector3::new(lo[0] as f32, lo[1] as f32, lo[2] as f32) * a;
+    let max = offset + Vector3::new(hi[0] as f32, hi[1] as f32, hi[2] as f32) * a;
+    let (d_lo, d_hi) = f.bounds(min, max).unwrap();
+
+    let uniform = if d_lo > 0.0 {Some(d_lo)} else if d_hi < 0.0 {Some(d_hi)} else {None};
+    match uniform{
+        Some(value) => {
+            for z in lo[2]..hi[2]{
+                for y in lo[1]..hi[1]{
+                    for x in lo[0]..hi[0]{
+                        cells[z * size[1] * size[0] + y * size[0] + x] = Some(value);
+                    }
+                }
+            }
+        },
+        None => {
+            //split the longest side
+            let ext = [hi[0] - lo[0], hi[1] - lo[1], hi[2] - lo[2]];
+            if ext[0] * ext[1] * ext[2] == 1 {return}
+            let axis = if ext[0] >= ext[1] && ext[0] >= ext[2] {0} else if ext[1] >= ext[2] {1} else {2};
+            let mid = lo[axis] + ext[axis] / 2;
+            let mut hi_first = hi;
+            hi_first[axis] = mid;
+            let mut lo_second = lo;
+            lo_second[axis] = mid;
+            cull_block(f, offset, a, size, lo, hi_first, cells);
+            cull_block(f, offset, a, size, lo_second, hi, cells);
+        },
+    }
+}
+
+//unit normal of the level set through `point`, central differences with step `eps` if `f` has no gradient
+//or it vanishes there (a sphere centre, the middle of a blend), zero if the differences vanish too: a zero normal adds nothing to a qef
+pub fn surface_normal<D : Density3>(f : &D, point : &Vector3<f32>, eps : f32) -> Vector3<f32>{
+    let usable = |g : &Vector3<f32>| {let len = g.norm(); len > 0.0 && len.is_finite()};
+    match f.gradient(*point){
+        Some(ref g) if usable(g) => return g.normalize(),
+        _ => (),
+    }
+
+    let g = Vector3::new(f.density(Vector3::new(point.x + eps, point.y, point.z)) - f.density(Vector3::new(point.x - eps, point.y, point.z)),
+                         f.density(Vector3::new(point.x, point.y + eps, point.z)) - f.density(Vector3::new(point.x, point.y - eps, point.z)),
+                         f.density(Vector3::new(point.x, point.y, point.z + eps)) - f.density(Vector3::new(point.x, point.y, point.z - eps)));
+    if usable(&g) {g.normalize()} else {Vector3::new(0.0, 0.0, 0.0)}
+}
+
+pub struct Vec3Display<'a>(pub &'a Vector3<f32>);
+
+impl<'a> fmt::Display for Vec3Display<'a>{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result{
+        write!(f, "({}, {}, {})", self.0.x, self.0.y, self.0.z)
+    }
+}
+
+pub fn blend_kind_name(kind : BlendKind) -> &'static str{
+    match kind{
+        BlendKind::Polynomial => "poly",
+        BlendKind::Exponential => "exp",
+    }
+}
+
+//prints the graph as a nested call expression with named parameters:
+//union(sphere(center = (0, 2, -4), rad = 1), aabb(center = (0, 3, -4), extent = (1.5, 1.5, 1.5)))
+impl fmt::Display for DenNode{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result{
+        match self{
+            &DenNode::Sphere{ref center, rad} => write!(f, "sphere(center = {}, rad = {})", Vec3Display(center), rad),
+            &DenNode::Aabb{ref center, ref extent} => write!(f, "aabb(center = {}, extent = {})", Vec3Display(center), Vec3Display(extent)),
+            &DenNode::Obb{ref center, ref right, ref up, ref extent} =>
+                write!(f, "obb(center = {}, right = {}, up = {}, extent = {})", Vec3Display(center), Vec3Display(right), Vec3Display(up), Vec3Display(extent)),
+            &DenNode::TorusZ{major_rad, minor_rad, ref offset} => write!(f, "torus_z(major_rad = {}, minor_rad = {}, offset = {})", major_rad, minor_rad, Vec3Display(offset)),
+            &DenNode::TorusY{major_rad, minor_rad, ref offset} => write!(f, "torus_y(major_rad = {}, minor_rad = {}, offset = {})", major_rad, minor_rad, Vec3Display(offset)),
+            &DenNode::HalfSpacePos{ref plane} => write!(f, "half_space_pos(point = {}, normal = {})", Vec3Display(&plane.point), Vec3Display(&plane.normal)),
+            &DenNode::HalfSpaceNeg{ref plane} => write!(f, "half_space_neg(point = {}, normal = {})", Vec3Display(&plane.point), Vec3Display(&plane.normal)),
+            &DenNode::HeightNoise{ref perlin, ref cube, octaves, persistence} =>
+                write!(f, "height_noise(seed = {}, center = {}, extent = {}, octaves = {}, persistence = {})", perlin.seed(), Vec3Display(&cube.center), cube.extent, octaves, persistence),
+
+            &DenNode::SdfSphere{ref center, rad} => write!(f, "sdf_sphere(center = {}, rad = {})", Vec3Display(center), rad),
+            &DenNode::SdfAabb{ref center, ref extent} => write!(f, "sdf_aabb(center = {}, extent = {})", Vec3Display(center), Vec3Display(extent)),
+            &DenNode::SdfObb{ref center, ref right, ref up, ref extent} =>
+                write!(f, "sdf_obb(center = {}, right = {}, up = {}, extent = {})", Vec3Display(center), Vec3Display(right), Vec3Display(up), Vec3Display(extent)),
+            &DenNode::SdfTorusZ{major_rad, minor_rad, ref offset} => write!(f, "sdf_torus_z(major_rad = {}, minor_rad = {}, offset = {})", major_rad, minor_rad, Vec3Display(offset)),
+            &DenNode::SdfTorusY{major_rad, minor_rad, ref offset} => write!(f, "sdf_torus_y(major_rad = {}, minor_rad = {}, offset = {})", major_rad, minor_rad, Vec3Display(offset)),
+            &DenNode::Capsule{ref a, ref b, rad} => write!(f, "capsule(a = {}, b = {}, rad = {})", Vec3Display(a), Vec3Display(b), rad),
+            &DenNode::CappedCylinder{ref a, ref b, rad} => write!(f, "capped_cylinder(a = {}, b = {}, rad = {})", Vec3Display(a), Vec3Display(b), rad),
+            &DenNode::CappedCone{ref a, ref b, rad_a, rad_b} => write!(f, "capped_cone(a = {}, b = {}, rad_a = {}, rad_b = {})", Vec3Display(a), Vec3Display(b), rad_a, rad_b),
+            &DenNode::RoundedBox{ref center, ref extent, radius} => write!(f, "rounded_box(center = {}, extent = {}, radius = {})", Vec3Display(center), Vec3Display(extent), radius),
+            &DenNode::Ellipsoid{ref center, ref radii} => write!(f, "ellipsoid(center = {}, radii = {})", Vec3Display(center), Vec3Display(radii)),
+            &DenNode::HexPrism{ref center, rad, half_height} => write!(f, "hex_prism(center = {}, rad = {}, half_height = {})", Vec3Display(center), rad, half_height),
+            &DenNode::TriPrism{ref center, rad, half_height} => write!(f, "tri_prism(center = {}, rad = {}, half_height = {})", Vec3Display(center), rad, half_height),
+            &DenNode::Torus{ref center, ref axis, major_rad, minor_rad} =>
+                write!(f, "torus(center = {}, axis = {}, major_rad = {}, minor_rad = {})", Vec3Display(center), Vec3Display(axis), major_rad, minor_rad),
+
+            &DenNode::Union(ref a, ref b) => write!(f, "union({}, {})", a, b),
+            &DenNode::Intersection(ref a, ref b) => write!(f, "intersection({}, {})", a, b),
+            &DenNode::Difference(ref a, ref b) => write!(f, "difference({}, {})", a, b),
+            &DenNode::SmoothUnion{ref a, ref b, radius, kind} => write!(f, "smooth_union({}, {}, radius = {}, kind = {})", a, b, radius, blend_kind_name(kind)),
+            &DenNode::SmoothIntersection{ref a, ref b, radius, kind} => write!(f, "smooth_intersection({}, {}, radius = {}, kind = {})", a, b, radius, blend_kind_name(kind)),
+            &DenNode::SmoothDifference{ref a, ref b, radius, kind} => write!(f, "smooth_difference({}, {}, radius = {}, kind = {})", a, b, radius, blend_kind_name(kind)),
+
+            &DenNode::Translate{ref offset, ref node} => write!(f, "translate({}, offset = {})", node, Vec3Display(offset)),
+            &DenNode::Rotate{ref rotation, ref node} => {
+                let axis = match rotation.axis() {Some(axis) => axis.unwrap(), None => Vector3::z()};
+                write!(f, "rotate({}, axis = {}, angle = {})", node, Vec3Display(&axis), rotation.angle())
+            },
+            &DenNode::Scale{factor, ref node} => write!(f, "scale({}, factor = {})", node, factor),
+            &DenNode::ScaleNonUniform{ref factor, ref node} => write!(f, "scale_non_uniform({}, factor = {})", node, Vec3Display(factor)),
+            &DenNode::Mirror{ref plane, ref node} => write!(f, "mirror({}, point = {}, normal = {})", node, Vec3Display(&plane.point), Vec3Display(&plane.normal)),
+            &DenNode::Repeat{ref period, limit : None, ref node} => write!(f, "repeat({}, period = {})", node, Vec3Display(period)),
+            &DenNode::Repeat{ref period, limit : Some(ref limit), ref node} => write!(f, "repeat_bounded({}, period = {}, limit = {})", node, Vec3Display(period), Vec3Display(limit)),
+            &DenNode::Twist{rate, ref node} => write!(f, "twist({}, rate = {})", node, rate),
+            &DenNode::Bend{rate, ref node} => write!(f, "bend({}, rate = {})", node, rate),
+            &DenNode::Elongate{ref extent, ref node} => write!(f, "elongate({}, extent = {})", node, Vec3Display(extent)),
+            &DenNode::Symmetry{ref plane, ref node} => write!(f, "symmetry({}, point = {}, normal = {})", node, Vec3Display(&plane.point), Vec3Display(&plane.normal)),
+            &DenNode::LipschitzCorrect{lipschitz, ref node} => write!(f, "lipschitz_correct({}, lipschitz = {})", node, lipschitz),
+        }
+    }
+}
+
+//`eval_batch` must agree exactly with `eval`, and with the per-point default of `Density3::density_batch`
+pub fn test_density_batch(){
+    let f = DenNode::sdf_sphere(Sphere{center : Vector3::new(0.0, 0.0, 0.0), rad : 0.4})
+        .smooth_union(DenNode::sdf_aabb(Vector3::new(0.3, 0.0, 0.0), Vector3::new(0.2, 0.3, 0.1)), 0.1, BlendKind::Polynomial)
+        .difference(DenNode::capsule(Vector3::new(-1.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0), 0.1))
+        .twist(0.5)
+        .rotate(UnitQuaternion::from_axis_angle(&Vector3::y_axis(), 0.7))
+        .repeat(Vector3::new(1.5, 1.5, 1.5))
+        .scale(1.3);
+    let den_fn = f.clone().to_den_fn();
+
+    let points : Vec<Vector3<f32>> = (0..4096).map(|i| Vector3::new((i % 16) as f32, ((i / 16) % 16) as f32, (i / 256) as f32) * 0.21 - Vector3::new(1.7, 1.6, 1.5)).collect();
+    let mut batch = vec![0.0;points.len()];
+    let mut fallback = vec![0.0;points.len()];
+
+    let start = precise_time_ns();
+    f.density_batch(&points, &mut batch);
+    let batch_time = precise_time_ns() - start;
+    let start = precise_time_ns();
+    den_fn.density_batch(&points, &mut fallback);
+    let fallback_time = precise_time_ns() - start;
+
+    assert!(points.iter().zip(batch.iter()).all(|(p, d)| f.eval(*p) == *d));
+    assert!(batch == fallback);
+    println!("density batch of {} points: {} us, per point: {} us", points.len(), batch_time / 1000, fallback_time / 1000);
+}
+
+//analytic gradients of every primitive and combinator that has one agree with central differences,
+//the nodes without one return `None` so the caller falls back to its own differences
+pub fn test_gradients(){
+    let sphere = || DenNode::sdf_sphere(Sphere{center : Vector3::new(0.1, -0.2, 0.05), rad : 0.6});
+    let cube = || DenNode::sdf_aabb(Vector3::new(0.4, 0.1, 0.0), Vector3::new(0.3, 0.5, 0.4));
+    let plane = Plane{point : Vector3::new(0.1, 0.2, -0.1), normal : Vector3::new(1.0, 2.0, 0.5).normalize()};
+    let rotation = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), 0.7);
+
+    let analytic = vec![
+        ("sphere", DenNode::sphere(Sphere{center : Vector3::new(0.1, 0.0, 0.0), rad : 0.7})),
+        ("torus_z", DenNode::torus_z(0.8, 0.3, Vector3::new(0.0, 0.1, 0.0))),
+        ("torus_y", DenNode::torus_y(0.8, 0.3, Vector3::new(0.0, 0.1, 0.0))),
+        ("half_space_pos", DenNode::half_space_pos(plane)),
+        ("half_space_neg", DenNode::half_space_neg(plane)),
+        ("sdf_sphere", sphere()),
+        ("sdf_aabb", cube()),
+        ("sdf_obb", DenNode::sdf_obb(Vector3::new(0.0, 0.1, 0.0), Vector3::new(0.8, 0.6, 0.0), Vector3::new(-0.6, 0.8, 0.0), Vector3::new(0.5, 0.3, 0.4))),
+        ("sdf_torus_z", DenNode::sdf_torus_z(0.8, 0.3, Vector3::new(0.0, 0.1, 0.0))),
+        ("sdf_torus_y", DenNode::sdf_torus_y(0.8, 0.3, Vector3::new(0.0, 0.1, 0.0))),
+        ("capsule", DenNode::capsule(Vector3::new(-0.5, 0.0, 0.1), Vector3::new(0.4, 0.3, 0.0), 0.3)),
+        ("rounded_box", DenNode::rounded_box(Vector3::new(0.0, 0.1, 0.0), Vector3::new(0.6, 0.4, 0.5), 0.1)),
+        ("torus", DenNode::torus(Vector3::new(0.0, 0.1, 0.0), Vector3::new(1.0, 1.0, 0.0).normalize(), 0.8, 0.3)),
+        ("union", sphere().union(cube())),
+        ("intersection", sphere().intersection(cube())),
+        ("difference", sphere().difference(cube())),
+        ("smooth_union", sphere().smooth_union(cube(), 0.2, BlendKind::Polynomial)),
+        ("smooth_intersection", sphere().smooth_intersection(cube(), 0.2, BlendKind::Exponential)),
+        ("smooth_difference", sphere().smooth_difference(cube(), 0.2, BlendKind::Polynomial)),
+        ("translate", cube().translate(Vector3::new(0.2, -0.1, 0.3))),
+        ("rotate", cube().rotate(rotation)),
+        ("scale", cube().scale(1.4)),
+        ("scale_non_uniform", sphere().scale_non_uniform(Vector3::new(1.2, 0.8, 1.0))),
+        ("mirror", cube().mirror(plane)),
+        ("repeat", sphere().scale(0.3).repeat(Vector3::new(0.7, 0.7, 0.7))),
+        ("elongate", sphere().elongate(Vector3::new(0.2, 0.0, 0.1))),
+        ("symmetry", cube().symmetry(plane)),
+        ("lipschitz_correct", cube().lipschitz_correct(1.5)),
+    ];
+    let numeric = vec![
+        ("aabb", DenNode::aabb(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.5, 0.4, 0.3))),
+        ("obb", DenNode::obb(Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.5, 0.4, 0.3))),
+        ("height_noise", DenNode::height_noise(Perlin::new(), Square3{center : Vector3::new(0.0, 0.0, 0.0), extent : 2.0})),
+        ("capped_cylinder", DenNode::capped_cylinder(Vector3::new(0.0, -0.5, 0.0), Vector3::new(0.0, 0.5, 0.0), 0.3)),
+        ("capped_cone", DenNode::capped_cone(Vector3::new(0.0, -0.5, 0.0), Vector3::new(0.0, 0.5, 0.0), 0.4, 0.1)),
+        ("ellipsoid", DenNode::ellipsoid(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.5, 0.3, 0.4))),
+        ("hex_prism", DenNode::hex_prism(Vector3::new(0.0, 0.0, 0.0), 0.4, 0.3)),
+        ("tri_prism", DenNode::tri_prism(Vector3::new(0.0, 0.0, 0.0), 0.4, 0.3)),
+        ("twist", cube().twist(0.5)),
+        ("bend", cube().bend(0.5)),
+        ("smooth_union with twist", sphere().smooth_union(cube().twist(0.5), 0.2, BlendKind::Exponential)),
+    ];
+
+    let points : Vec<Vector3<f32>> = (0..1000).map(|i| Vector3::new((i % 10) as f32, ((i / 10) % 10) as f32, (i / 100) as f32) * 0.23 - Vector3::new(1.02, 1.05, 0.98)).collect();
+    //forward differences with step `h` (negative - backward)
+    let one_sided = |f : &DenNode, p : Vector3<f32>, h : f32|{
+        let d = |axis : usize| {let mut e = Vector3::new(0.0, 0.0, 0.0); e[axis] = h; (f.eval(p + e) - f.eval(p)) / h};
+        Vector3::new(d(0), d(1), d(2))
+    };
+    for &(name, ref f) in analytic.iter(){
+        let mut checked = 0;
+        for p in points.iter(){
+            let g = f.gradient(*p).expect(name);
+            assert_eq!(f.eval_grad(*p).0, f.eval(*p));
+            //points on a seam or an edge of a box, where the forward and backward differences disagree, are skipped
+            let (forward, backward) = (one_sided(f, *p, 1e-3), one_sided(f, *p, -1e-3));
+            if (forward - backward).norm() > 1e-2 {continue;}
+            checked += 1;
+            let central = (forward + backward) / 2.0;
+            assert!((g - central).norm() < 1e-2, "{}: gradient {:?}, central differences {:?} at {:?}", name, g, central, p);
+        }
+        println!("{}: {} of {} points checked", name, checked, points.len());
+        assert!(checked * 2 > points.len());
+    }
+    for &(name, ref f) in numeric.iter(){
+        assert!(points.iter().all(|p| f.gradient(*p).is_none()), "{} has no analytic gradient", name);
+    }
+    //where the gradient vanishes the normal falls back to differences and stays finite
+    let pair = DenNode::sdf_sphere(Sphere{center : Vector3::new(-0.5, 0.0, 0.0), rad : 0.4}).smooth_union(DenNode::sdf_sphere(Sphere{center : Vector3::new(0.5, 0.0, 0.0), rad : 0.4}), 0.5, BlendKind::Polynomial);
+    for &(name, ref f, p) in [("sphere centre", sphere(), Vector3::new(0.1, -0.2, 0.05)), ("middle of a blend", pair, Vector3::new(0.0, 0.0, 0.0))].iter(){
+        let n = surface_normal(f, &p, 1e-3);
+        println!("{}: normal {:?}", name, n);
+        assert!(n.x.is_finite() && n.y.is_finite() && n.z.is_finite(), "{}: normal is not finite", name);
+    }
+}
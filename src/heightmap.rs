@@ -0,0 +1,287 @@
+use std;
+use std::fs::File;
+use std::io::Read;
+use na::*;
+use png;
+use math::*;
+use dcm::*;
+
+//grayscale heightmaps (PNG 8/16 bit, binary or ASCII PGM) as terrain densities, optionally with a splat map choosing
+//the material of each column, so real DEM tiles can be meshed with UMDC or material DC
+//pixel (i, j) covers the column at `offset + (i, _, j) * scale_xz`, its value v in 0..1 is the height `offset.y + v * scale_y`
+
+//decoded image, samples are stored row by row with `channels` interleaved values per pixel, each in 0..=max
+pub struct Image{
+    pub width : usize,
+    pub height : usize,
+    pub channels : usize,
+    pub max : u16,
+    pub data : Vec<u16>,
+}
+
+impl Image{
+    pub fn get(&self, x : usize, y : usize, channel : usize) -> u16{
+        self.data[(y * self.width + x) * self.channels + channel]
+    }
+
+    //average of the color channels in 0..1, alpha is ignored
+    pub fn luminance(&self, x : usize, y : usize) -> f32{
+        let colors = if self.channels == 2 || self.channels == 4 {self.channels - 1} else {self.channels};
+        let sum : u32 = (0..colors).map(|c| self.get(x, y, c) as u32).sum();
+        sum as f32 / (colors as f32 * self.max as f32)
+    }
+}
+
+//palette images are expanded to RGB, 16 bit samples are kept
+pub fn parse_png(bytes : &[u8]) -> Result<Image, String>{
+    use png::HasParameters;
+
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set(png::Transformations::EXPAND);
+    let (info, mut reader) = decoder.read_info().map_err(|e| format!("invalid PNG: {}", e))?;
+    //expansion turns 1, 2 and 4 bit samples into bytes but keeps 16 bit ones, `info.bit_depth` claims 8 in both cases
+    let sixteen = reader.info().bit_depth == png::BitDepth::Sixteen;
+    let mut buf = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buf).map_err(|e| format!("invalid PNG: {}", e))?;
+
+    let channels = info.color_type.samples();
+    let (width, height) = (info.width as usize, info.height as usize);
+    let mut data = Vec::with_capacity(width * height * channels);
+    let max = if sixteen {
+        for y in 0..height{
+            let row = &buf[y * info.line_size..];
+            for i in 0..width * channels{
+                data.push((row[2 * i] as u16) << 8 | row[2 * i + 1] as u16); //big endian
+            }
+        }
+        65535
+    }else{
+        for y in 0..height{
+            data.extend(buf[y * info.line_size..y * info.line_size + width * channels].iter().map(|&b| b as u16));
+        }
+        255
+    };
+
+    Ok(Image{width, height, channels, max, data})
+}
+
+//P2 (ASCII) and P5 (binary, 8 bit or big endian 16 bit) graymaps, comments start with `#`
+pub fn parse_pgm(bytes : &[u8]) -> Result<Image, String>{
+    let mut pos = 0;
+    let mut header = Vec::with_capacity(4);
+    while header.len() < 4{
+        //skip whitespace and comments
+        while pos < bytes.len() && (bytes[pos].is_ascii_whitespace() || bytes[pos] == b'#'){
+            if bytes[pos] == b'#' {while pos < bytes.len() && bytes[pos] != b'\n' {pos += 1;}} else {pos += 1;}
+        }
+        let start = pos;
+        while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {pos += 1;}
+        if start == pos {return Err(String::from("truncated PGM header"));}
+        header.push(std::str::from_utf8(&bytes[start..pos]).unwrap_or("").to_string());
+    }
+
+    let binary = match header[0].as_str(){
+        "P5" => true,
+        "P2" => false,
+        magic => return Err(format!("not a PGM file (magic `{}`)", magic)),
+    };
+    let number = |s : &String, what : &str| s.parse::<usize>().map_err(|_| format!("invalid PGM {} `{}`", what, s));
+    let width = number(&header[1], "width")?;
+    let height = number(&header[2], "height")?;
+    let max = number(&header[3], "maximum value")?;
+    if width == 0 || height == 0 {return Err(String::from("empty PGM image"));}
+    if max == 0 || max > 65535 {return Err(format!("PGM maximum value {} out of range", max));}
+
+    let count = width * height;
+    let data : Vec<u16> = if binary{
+        let body = &bytes[std::cmp::min(pos + 1, bytes.len())..]; //a single whitespace separates the header from the samples
+        let sample_size = if max > 255 {2} else {1};
+        if body.len() < count * sample_size {return Err(String::from("truncated PGM data"));}
+        (0..count).map(|i| if sample_size == 2 {(body[2 * i] as u16) << 8 | body[2 * i + 1] as u16} else {body[i] as u16}).collect()
+    }else{
+        let body = std::str::from_utf8(&bytes[pos..]).map_err(|_| String::from("invalid ASCII PGM data"))?;
+        let samples : Vec<u16> = body.split_whitespace().take(count).map(|w| w.parse()).collect::<Result<_, _>>().map_err(|_| String::from("invalid ASCII PGM sample"))?;
+        if samples.len() < count {return Err(String::from("truncated PGM data"));}
+        samples
+    };
+
+    if data.iter().any(|&v| v as usize > max) {return Err(String::from("PGM sample exceeds the maximum value"));}
+    Ok(Image{width, height, channels : 1, max : max as u16, data})
+}
+
+//picks the parser by extension: `.png` or `.pgm`
+pub fn load_image(path : &str) -> Result<Image, String>{
+    let mut bytes = Vec::new();
+    File::open(path).and_then(|mut file| file.read_to_end(&mut bytes)).map_err(|e| format!("{}: {}", path, e))?;
+
+    let lower = path.to_lowercase();
+    let result = if lower.ends_with(".png") {
+        parse_png(&bytes)
+    }else if lower.ends_with(".pgm") {
+        parse_pgm(&bytes)
+    }else{
+        Err(String::from("unknown image format, expected .png or .pgm"))
+    };
+
+    result.map_err(|e| format!("{}: {}", path, e))
+}
+
+pub struct Heightmap{
+    pub width : usize,
+    pub depth : usize,
+    pub heights : Vec<f32>, //0..1, row by row along z
+    pub scale_xz : f32, //world size of a pixel
+    pub scale_y : f32, //world height of the brightest value
+    pub offset : Vector3<f32>, //world position of pixel (0, 0) at height 0
+}
+
+impl Heightmap{
+    pub fn from_image(image : &Image, scale_xz : f32, scale_y : f32, offset : Vector3<f32>) -> Heightmap{
+        let mut heights = Vec::with_capacity(image.width * image.height);
+        for y in 0..image.height{
+            for x in 0..image.width{
+                heights.push(image.luminance(x, y));
+            }
+        }
+
+        Heightmap{width : image.width, depth : image.height, heights, scale_xz, scale_y, offset}
+    }
+
+    //world size of the covered area along x and z
+    pub fn extent(&self) -> Vector2<f32>{
+        Vector2::new((self.width - 1) as f32 * self.scale_xz, (self.depth - 1) as f32 * self.scale_xz)
+    }
+
+    //bilinear height at world (x, z), the border pixels are extended outside of the map
+    pub fn height(&self, x : f32, z : f32) -> f32{
+        let u = Real::min(Real::max((x - self.offset.x) / self.scale_xz, 0.0), (self.width - 1) as f32);
+        let v = Real::min(Real::max((z - self.offset.z) / self.scale_xz, 0.0), (self.depth - 1) as f32);
+        let i = std::cmp::min(u as usize, std::cmp::max(self.width, 2) - 2);
+        let j = std::cmp::min(v as usize, std::cmp::max(self.depth, 2) - 2);
+        let (tu, tv) = (u - i as f32, v - j as f32);
+
+        let get = |x : usize, z : usize| self.heights[std::cmp::min(z, self.depth - 1) * self.width + std::cmp::min(x, self.width - 1)];
+        let h = (get(i, j) * (1.0 - tu) + get(i + 1, j) * tu) * (1.0 - tv) + (get(i, j + 1) * (1.0 - tu) + get(i + 1, j + 1) * tu) * tv;
+
+        self.offset.y + h * self.scale_y
+    }
+
+    //negative below the surface, like `mk_terrain_height` this is a height difference, not a distance
+    pub fn density(&self, p : Vector3<f32>) -> f32{
+        p.y - self.height(p.x, p.z)
+    }
+
+    pub fn to_den_fn(self) -> DenFn3<f32>{
+        box move |p| {self.density(p)}
+    }
+}
+
+//material of each column: single channel images store the material id directly, for color images the strongest
+//channel picks the id from `palette` (the usual RGBA weight splat maps)
+pub struct Splatmap{
+    pub width : usize,
+    pub depth : usize,
+    pub materials : Vec<u32>,
+}
+
+impl Splatmap{
+    pub fn from_image(image : &Image, palette : &[u32]) -> Result<Splatmap, String>{
+        if image.channels > 1 && palette.len() < image.channels{
+            return Err(format!("splat map has {} channels but the palette only {} materials", image.channels, palette.len()));
+        }
+
+        let mut materials = Vec::with_capacity(image.width * image.height);
+        for y in 0..image.height{
+            for x in 0..image.width{
+                let material = if image.channels == 1 {
+                    image.get(x, y, 0) as u32
+                }else{
+                    let strongest = (0..image.channels).max_by_key(|&c| image.get(x, y, c)).unwrap();
+                    palette[strongest]
+                };
+                materials.push(material);
+            }
+        }
+
+        Ok(Splatmap{width : image.width, depth : image.height, materials})
+    }
+
+    //the splat map is stretched over the area of the heightmap, so both can have different resolutions
+    pub fn material(&self, heightmap : &Heightmap, x : f32, z : f32) -> u32{
+        let extent = heightmap.extent();
+        let u = Real::min(Real::max((x - heightmap.offset.x) / Real::max(extent.x, 1e-6), 0.0), 1.0);
+        let v = Real::min(Real::max((z - heightmap.offset.z) / Real::max(extent.y, 1e-6), 0.0), 1.0);
+        let i = (u * (self.width - 1) as f32).round() as usize;
+        let j = (v * (self.depth - 1) as f32).round() as usize;
+
+        self.materials[j * self.width + i]
+    }
+}
+
+//material 0 is air, so columns painted with 0 become holes in the material grid
+pub fn mk_heightmap_mat(heightmap : Heightmap, splatmap : Splatmap) -> DenMatFn3<f32>{
+    box move |p| {
+        let density = heightmap.density(p);
+        MaterialPoint{density, material : if density <= 0.0 {splatmap.material(&heightmap, p.x, p.z)} else {0}}
+    }
+}
+
+pub fn mk_heightmap(path : &str, scale_xz : f32, scale_y : f32, offset : Vector3<f32>) -> Result<DenFn3<f32>, String>{
+    let image = load_image(path)?;
+    Ok(Heightmap::from_image(&image, scale_xz, scale_y, offset).to_den_fn())
+}
+
+pub fn mk_heightmap_splat(height_path : &str, splat_path : &str, palette : &[u32], scale_xz : f32, scale_y : f32, offset : Vector3<f32>) -> Result<DenMatFn3<f32>, String>{
+    let heightmap = Heightmap::from_image(&load_image(height_path)?, scale_xz, scale_y, offset);
+    let splatmap = Splatmap::from_image(&load_image(splat_path)?, palette).map_err(|e| format!("{}: {}", splat_path, e))?;
+    Ok(mk_heightmap_mat(heightmap, splatmap))
+}
+
+//round trips a 16 bit PNG and both PGM flavours and checks heights and materials of the resulting densities
+pub fn test_heightmap(){
+    use png::HasParameters;
+
+    let (w, h) = (5, 4);
+    let sample = |x : usize, y : usize| (x * 1000 + y * 7000) as u16;
+
+    let mut encoded = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut encoded, w as u32, h as u32);
+        encoder.set(png::ColorType::Grayscale).set(png::BitDepth::Sixteen);
+        let mut writer = encoder.write_header().unwrap();
+        let data : Vec<u8> = (0..w * h).flat_map(|i| {let v = sample(i % w, i / w); vec![(v >> 8) as u8, v as u8]}).collect();
+        writer.write_image_data(&data).unwrap();
+    }
+    let png16 = parse_png(&encoded).unwrap();
+    assert_eq!((png16.width, png16.height, png16.channels, png16.max), (w, h, 1, 65535));
+    assert!((0..w * h).all(|i| png16.get(i % w, i / w, 0) == sample(i % w, i / w)));
+
+    let mut p5 = format!("P5\n# comment\n{} {}\n65535\n", w, h).into_bytes();
+    for i in 0..w * h {let v = sample(i % w, i / w); p5.push((v >> 8) as u8); p5.push(v as u8);}
+    let pgm16 = parse_pgm(&p5).unwrap();
+    assert_eq!(pgm16.data, png16.data);
+
+    let p2 = "P2\n3 2\n# materials\n4\n1 2 3\n4 0 1\n";
+    let pgm8 = parse_pgm(p2.as_bytes()).unwrap();
+    assert_eq!(pgm8.data, vec![1, 2, 3, 4, 0, 1]);
+    assert!(parse_pgm(b"P2\n3 2\n4\n1 2 3\n4 5 1\n").is_err());
+
+    let heightmap = Heightmap::from_image(&pgm16, 2.0, 10.0, Vector3::new(-1.0, 5.0, 0.0));
+    for &(x, z) in [(0usize, 0usize), (3, 1), (4, 3)].iter(){
+        let expected = 5.0 + sample(x, z) as f32 / 65535.0 * 10.0;
+        let world = (-1.0 + x as f32 * 2.0, z as f32 * 2.0);
+        assert!((heightmap.height(world.0, world.1) - expected).abs() < 1e-4);
+    }
+    //halfway between two pixels the height is their average
+    let mid = 5.0 + (sample(1, 2) as f32 + sample(2, 2) as f32) / 2.0 / 65535.0 * 10.0;
+    assert!((heightmap.density(Vector3::new(2.0, mid, 4.0))).abs() < 1e-4);
+
+    let splatmap = Splatmap::from_image(&pgm8, &[]).unwrap();
+    let f = mk_heightmap_mat(heightmap, splatmap);
+    assert_eq!(f(Vector3::new(-1.0, 0.0, 0.0)).material, 1);
+    assert_eq!(f(Vector3::new(7.0, 0.0, 6.0)).material, 1);
+    assert_eq!(f(Vector3::new(-1.0, 0.0, 6.0)).material, 4);
+    assert_eq!(f(Vector3::new(-1.0, 100.0, 6.0)).material, 0);
+
+    println!("heightmap test passed: {}x{} PNG and PGM, bilinear heights and splat materials", w, h);
+}
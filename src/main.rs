@@ -11,24 +11,38 @@ extern crate time;
 extern crate num;
 extern crate rand;
 extern crate noise;
+extern crate png;
+extern crate rayon;
 
 use na::*;
 use na::core::Unit;
 
 mod cms;
 mod qef_bindings;
+mod qef;
 mod adaptive_dc;
 mod graphics;
 mod graphics_util;
 mod renderer;
 #[macro_use]
 mod math;
+mod mesh;
+mod mesher;
+mod marching_cubes;
 mod voxel_renderer;
 mod dc;
+mod dc2;
 mod dcm;
 mod matrix;
 mod uniform_manifold_dc;
+mod adaptive_manifold_dc;
 mod cubic;
+mod density;
+mod terrain;
+mod scene;
+mod mesh_sdf;
+mod sampled_grid;
+mod heightmap;
 
 use noise::{NoiseModule, Perlin};
 use graphics::*;
@@ -49,6 +63,7 @@ use core::storage::*;
 use generic_array::*;
 use adaptive_dc::*;
 use uniform_manifold_dc::*;
+use density::*;
 
 use time::precise_time_ns;
 
@@ -326,8 +341,26 @@ fn run_voxelized() {
     
 
 
+    let seed = terrain::WorldSeed::from_env();
+    println!("world seed: {} (set V3D_SEED to reproduce another world)", seed.0);
+
     unsafe{
-        cubic::test_cubic_octree(&mut renderer_tr_light);
+        let cubes = cubic::test_cubic_octree(seed);
+        add_mesh_color_normal(&mut renderer_tr_light, &cubes, &|_| Vector3::new(0.6, 0.4, 0.4));
+    }
+
+    //a scene file given on the command line is meshed next to the hard-coded test geometry
+    if let Some(path) = std::env::args().nth(1){
+        match scene::load_scene(&path){
+            Ok(scene) => {
+                let out = scene::mesh_scene(&scene);
+                match scene.mesher{
+                    mesher::MesherKind::Dcm => add_mesh_color_normal(&mut renderer_tr_light, &out.mesh, &dcm::material_color),
+                    _ => add_mesh_color_normal(&mut renderer_tr_light, &out.mesh, &|_| Vector3::new(1.0, 1.0, 0.0)),
+                }
+            },
+            Err(e) => println!("failed to load scene {}", e),
+        }
     }
 
     add_grid3_color(&mut renderer_lines, zero, Vector3::new(0.0, 0.0, -1.0), Vector3::new(0.0, 1.0, 0.0), 1.0, 8, white);
@@ -374,13 +407,31 @@ fn run_voxelized() {
     //let den = union3(den1, aabb);
 
     //dc::test_sample_normal();
+    //dc::test_thread_count_determinism();
+    //dc::test_culled_grid();
+    //dc2::test_contour2();
+    //terrain::test_world_reproducibility();
+    //scene::test_scene_parser();
+    //mesh_sdf::test_mesh_sdf();
+    //sampled_grid::test_sampled_grid();
+    //heightmap::test_heightmap();
+    //dcm::test_material_csg();
+    //density::test_density_batch();
+    //density::test_gradients();
+    //mesher::test_meshers();
+    //marching_cubes::test_marching_cubes();
+    //cms::test_cms();
+    //qef::test_qef();
+    //adaptive_manifold_dc::test_amdc();
+    //adaptive_dc::test_adc();
 
 
     //ADAPTIVE---------
     // let sp_num = mk_sphere(Sphere{center : Vector3::new(-4.0, -4.0, -4.0), rad : 1.0});
-    // let tree = timed(&|dt| format!("make tree took {} ms", dt / 1000000), &mut ||{
-    //    make_tree(&sp_num, Vector3::new(-5.0, -5.0, -5.0), BLOCK_SIZE, CHUNK_SIZE, &mut renderer_lines)
+    // let (tree, debug) = timed(&|dt| format!("make tree took {} ms", dt / 1000000), &mut ||{
+    //    make_tree(&sp_num, Vector3::new(-5.0, -5.0, -5.0), BLOCK_SIZE, CHUNK_SIZE, &qef::CollapseSettings::new(8), true)
     // });
+    // add_debug_geometry_color(&mut renderer_lines, &debug.unwrap());
 
     //-----------------
 
@@ -390,18 +441,17 @@ fn run_voxelized() {
     let rec1 = mk_aabb(Vector3::new(2.0,2.0,0.0), Vector3::new(0.2,0.2,0.2));
     let den1 = union3(sp_num1, sp_num2);
     let den = union3(rec1, den1);
-    let torusz = mk_torus_z(2.0, 0.8,Vector3::new(0.0,0.0,-4.0));
-    let torusy = mk_torus_y(1.6, 0.67,Vector3::new(2.0,0.0,-4.0));
-    let perlin = Perlin::new();
+    let torusz = DenNode::torus_z(2.0, 0.8,Vector3::new(0.0,0.0,-4.0));
+    let torusy = DenNode::torus_y(1.6, 0.67,Vector3::new(2.0,0.0,-4.0));
+    let perlin = seed.perlin(terrain::NoiseSource::Terrain);
     let noise = noise_f32(perlin, Square3{center : Vector3::new(1.0,-1.0,1.0), extent : 3.5} );//perlin.get([p.x,p.y,p.z])  ;
-    let two_torus = union3(torusz, torusy);
-    let den2 = difference3(two_torus, mk_aabb(Vector3::new(0.0, 3.0, -4.0), Vector3::new(1.5,1.5,1.5)));
-    let den3 = union3(den2, mk_sphere(Sphere{center : Vector3::new(0.0, 2.0, -4.0), rad : 1.0}));
-    let den4 = union3(den3, mk_obb(Vector3::new(1.0, 1.0, 0.0), Vector3::new(1.0, -1.0, 0.0).normalize(), Vector3::new(1.0, 1.0, 0.5).normalize(), Vector3::new(1.0, 0.5, 0.2)));
-    //let den4 = union3(den3, mk_half_space_pos(Plane{point : Vector3::new(0.0, 2.0, -4.0), normal : Vector3::new(1.0, 1.0, 0.0).normalize()}));
-    //let den = f;
-    //TODO implement DenFn differently, like noise library
-    //construct_grid(&den4, Vector3::new(-3.0, -3.0, -8.0), BLOCK_SIZE, CHUNK_SIZE, 8, &mut renderer_tr_light, &mut renderer_lines);
+    let two_torus = torusz.union(torusy);
+    let den2 = two_torus.difference(DenNode::aabb(Vector3::new(0.0, 3.0, -4.0), Vector3::new(1.5,1.5,1.5)));
+    let den3 = den2.union(DenNode::sphere(Sphere{center : Vector3::new(0.0, 2.0, -4.0), rad : 1.0}));
+    let den4 = den3.union(DenNode::obb(Vector3::new(1.0, 1.0, 0.0), Vector3::new(1.0, -1.0, 0.0).normalize(), Vector3::new(1.0, 1.0, 0.5).normalize(), Vector3::new(1.0, 0.5, 0.2)));
+    //let den4 = den3.union(DenNode::half_space_pos(Plane{point : Vector3::new(0.0, 2.0, -4.0), normal : Vector3::new(1.0, 1.0, 0.0).normalize()}));
+    //let (_, mesh, _) = construct_grid(&den4, Vector3::new(-3.0, -3.0, -8.0), BLOCK_SIZE, CHUNK_SIZE, 8, false);
+    //add_mesh_color_normal(&mut renderer_tr_light, &mesh, &|_| red + green);
 
     let test_sphere = Sphere{center : Vector3::new(2.7, 1.0, 0.0), rad : 2.4};
     let test_sphere2 = Sphere{center : Vector3::new(2.7, 3.0, 0.0), rad : 2.4};
@@ -412,12 +462,13 @@ fn run_voxelized() {
     let ts3 = difference3(ts1, ts2);
     let ts4 = difference3(ts3, ts22);
     //add_sphere_color(&mut renderer_tr_light, &test_sphere, 100, 100, Vector3::new(1.0, 1.0, 1.0));
-    //construct_grid(&ts4, Vector3::new(-0.5, -2.5, -2.5), 1.0/8.0, 2*8*8, 32, &mut renderer_tr_light, &mut renderer_lines);
+    //let (_, mesh, _) = construct_grid(&ts4, Vector3::new(-0.5, -2.5, -2.5), 1.0/8.0, 2*8*8, 32, false);
+    //add_mesh_color_normal(&mut renderer_tr_light, &mesh, &|_| red + green);
     ///------------------
 
     // let contour_data = timed(&|dt| format!("op took {} ms", dt / 1000000), &mut ||{
     //     dcm::fill_in_grid(&mut grid, &den, Vector3::new(0.0, 0.0, 0.0));
-    //     dcm::make_contour(&grid, &den, 16, &mut renderer_lines) //accurary depends on grid resolution
+    //     dcm::make_contour(&grid, &den, 16) //accurary depends on grid resolution
     // });
 
 
@@ -425,11 +476,9 @@ fn run_voxelized() {
     shaders.get("lighting").unwrap().set_vec3f("pointLight.pos" ,Vector3::new(0.0, 8.0,0.0));
     shaders.get("lighting").unwrap().set_vec3f("pointLight.color" ,(red + green + blue) * 15.0);
 
-    // println!("generated {} triangles", contour_data.triangles.len());
+    // println!("generated {} triangles", contour_data.mesh.triangle_count());
 
-    // for i in 0..contour_data.triangles.len(){
-    //     //add_triangle_color_normal(&mut renderer_tr_light, &contour_data.triangles[i], &contour_data.triangle_colors[i / 2], &contour_data.triangle_normals[i / 2]);
-    // }
+    // add_mesh_color_normal(&mut renderer_tr_light, &contour_data.mesh, &dcm::material_color);
     //===================================
 
 
@@ -534,387 +583,3 @@ fn run_voxelized() {
 
     glfw_terminate();
 }
-
-
-/* pub struct VoxelGrid2<T : Real + Copy>{
-    pub a : T,
-    pub size_x : usize,
-    pub size_y : usize,
-    pub grid : Vec<T>,
-}
-
-impl<T : Real + SupersetOf<f32>> VoxelGrid2<T>{
-
-    pub fn vertices_x(&self) -> usize {self.size_x + 1}
-    pub fn vertices_y(&self) -> usize {self.size_y + 1}
-
-    pub fn new(a : T, size_x : usize, size_y : usize) -> VoxelGrid2<T>{
-        let grid = vec![convert(0.0);(size_x + 1) * (size_y + 1)];
-
-        VoxelGrid2{a,size_x, size_y, grid}
-    }
-
-    pub fn get(&self, x : usize, y : usize) -> T{
-        self.grid[y * self.vertices_x() + x]
-    }
-
-    pub fn set(&mut self, x : usize, y : usize, value : T){
-        let vx = self.vertices_x();
-        self.grid[y * vx + x] = value;
-    }
-
-    pub fn get_point(&self, x : usize, y : usize) -> Vector2<T>{
-        Vector2::new(self.a * convert::<f32, T>(x as f32), self.a * convert::<f32, T>(y as f32))
-    }
-
-    pub fn square2(&self, x : usize, y : usize) -> Square2<T>{
-        Square2{center : Vector2::new(convert::<f32,T>(x as f32 + 0.5) * self.a, convert::<f32,T>(y as f32 + 0.5) * self.a), extent: self.a / convert(2.0)}
-    }
-} */
-
-
-/*fn calc_qef(point : &Vector2<f32>, lines : &Vec<Line2<f32>>) -> f32{
-    let mut qef : f32 = 0.0;
-    for line in lines{
-        let dist = distance_point2_line2(point, line);
-        qef += dist * dist;
-    }
-
-    qef
-}
-
-fn const_sign(a : f32, b : f32) -> bool {
-    if a > 0.0 { b > 0.0} else {b <= 0.0}
-}
-
-fn sample_qef_brute(square : Square2<f32>, n : usize, lines : &Vec<Line2<f32>>) -> Vector2<f32> {
-    let ext = Vector2::new(square.extent, square.extent);
-    let min = square.center - ext;
-
-    let mut best_qef = 100000000000.0; //TODO placeholder
-    let mut best_point = min;
-
-    for i in 0..n{
-        for j in 0..n{
-            let point = min + Vector2::new(ext.x * (2.0 * (i as f32) + 1.0) / (n as f32), ext.y * (2.0 * (j as f32) + 1.0) / (n as f32));
-            let qef = calc_qef(&point, &lines);
-
-            if qef < best_qef{
-                best_qef = qef;
-                best_point = point;
-            }
-        }
-    }
-
-    best_point
-}
-
-
-fn sample_intersection_brute(line : Line2<f32>, n : usize, f : &DenFn2<f32>) -> Vector2<f32>{
-    let ext = line.end - line.start;
-
-    let mut best_abs = 1000000000.0; //TODO placeholder
-    let mut best_point : Option<Vector2<f32>> = None;
-
-    for i in 0..n {
-        let point = line.start + ext * (i as f32 / n as f32);
-        let den = f(point);
-        let abs = den.abs();
-
-        if abs < best_abs {
-            best_abs = abs;
-            best_point = Some(point);
-        }
-    }
-
-    best_point.unwrap()
-}
-
-fn sample_tangent(square : Square2<f32>, n : usize, f : &DenFn2<f32>) -> Vector2<f32>{
-    let ext = Vector2::new(square.extent, square.extent);
-    let min = square.center - ext;
-
-    let den_at_center = f(square.center);
-
-    let mut closest = den_at_center + 100000000.0; //TODO placeholder\
-    let mut closest_point = square.center;
-
-    for i in 0..n{
-        for j in 0..n{
-            let point = min + Vector2::new(ext.x * (2.0 * i as f32) / n as f32,
-                ext.y * (2.0 * j as f32) / n as f32);
-            let den = f(point);
-            let attempt = (den - den_at_center).abs();
-            if attempt < closest && (point - square.center).norm() != 0.0{
-                closest = attempt;
-                closest_point = point;
-            }
-        }
-    }
-
-    closest_point - square.center
-}
-
-fn ext_for_normal(block_size : f32) -> f32 {block_size / 100.0} //TODO why so ?
-
-
-fn make_lines(vg : &VoxelGrid2<f32>, features : &Vec<Option<Vector2<f32>>>) -> Vec<Line2<f32>>{
-    let mut ret = Vec::<Line2<f32>>::new();
-
-    for y in 0..vg.size_y - 1{
-        for x in 0..vg.size_x - 1{
-            let feature = features[y * vg.size_x + x];
-            if feature.is_some(){
-                let p1 = vg.get(x + 1, y);
-                let p2 = vg.get(x, y + 1);
-                let p3 = vg.get(x + 1, y + 1);
-
-                let mut vert1 : Option<Vector2<f32>> = None;
-                let mut vert2 : Option<Vector2<f32>> = None;
-
-                if !const_sign(p1,p3){
-                    vert1 = features[y * vg.size_x + (x + 1)];
-                }
-                if !const_sign(p3,p2){
-                    vert2 = features[(y+1) * vg.size_x + x];
-                }
-
-                if vert1.is_some(){
-                    ret.push(Line2{start : feature.unwrap(), end : vert1.unwrap()});
-                }
-            }
-        }
-    }
-
-    ret
-}
-
-fn make_triangles(vg : &VoxelGrid2<f32>, features : &Vec<Option<Vector2<f32>>>, intersections : &Vec<Option<Vec<Vector2<f32>>>>,
-    extra : &Vec<Option<Vec<Vector2<f32>>>>) -> Vec<Triangle2<f32>>{
-    let mut ret = Vec::<Triangle2<f32>>::new();
-
-    for y in 0..vg.size_y{
-        for x in 0.. vg.size_x{
-            let t = y * vg.size_x + x;
-            let cur_intersections = &intersections[t];
-            let cur_extras = &extra[t];
-
-            let p0 = vg.get(x, y);
-            let p1 = vg.get(x + 1, y);
-            let p2 = vg.get(x, y + 1);
-            let p3 = vg.get(x + 1, y + 1);
-
-            let v0 = vg.get_point(x,y);
-            let v1 = vg.get_point(x + 1, y);
-            let v2 = vg.get_point(x, y + 1);
-            let v3 = vg.get_point(x + 1, y + 1);
-
-            let mut sit = 0;
-
-            if !const_sign(p0, p1){sit |= 1;}
-            if !const_sign(p1, p3){sit |= 2;}
-            if !const_sign(p3, p2){sit |= 4;}
-            if !const_sign(p2, p0){sit |= 8;}
-
-            if sit == 0{ //fully inside or fully outside
-                let negative = p0 < 0.0;
-
-                if negative{ //render if it is inside
-                    let tr1 = Triangle2{p1: v0, p2 : v1, p3 : v3};
-                    let tr2 = Triangle2{p1: v0, p2 : v3, p3 : v2};
-
-                    ret.push(tr1);
-                    ret.push(tr2);
-                }
-
-            }else{ //contains surface
-                if cur_intersections.is_some() && features[t].is_some(){
-                    let len = cur_intersections.as_ref().unwrap().len();
-                    for i in 0..len{
-                        ret.push(Triangle2{p1 : features[t].as_ref().unwrap().clone(), p2 : cur_intersections.as_ref().unwrap()[i].clone(), p3 : cur_extras.as_ref().unwrap()[i].clone()});
-                    }
-                }
-            }
-        }
-    }
-
-    ret
-}
-
-
-fn make_vertex(vg : &VoxelGrid2<f32>, tr : &mut Vec<Triangle2<f32>>, x : usize, y : usize,
-    f : &DenFn2<f32>, accuracy : usize, features : &mut Vec<Option<Vector2<f32>>>, out_intersections : &mut Vec<Vector2<f32>>, out_extra : &mut Vec<Vector2<f32>>) -> Option<Vector2<f32>>{
-    let epsilon = vg.a / accuracy as f32;
-
-    let p0 = vg.get(x, y);
-    let p1 = vg.get(x + 1, y);
-    let p2 = vg.get(x, y + 1);
-    let p3 = vg.get(x + 1, y + 1);
-
-    let v0 = vg.get_point(x,y);
-    let v1 = vg.get_point(x + 1, y);
-    let v2 = vg.get_point(x, y + 1);
-    let v3 = vg.get_point(x + 1, y + 1);
-
-    let mut sit = 0;
-
-    if !const_sign(p0, p1){sit |= 1;}
-    if !const_sign(p1, p3){sit |= 2;}
-    if !const_sign(p3, p2){sit |= 4;}
-    if !const_sign(p2, p0){sit |= 8;}
-
-    let ext_for_normal = ext_for_normal(vg.a);
-
-    if sit > 0{
-        let mut tangents = Vec::<Line2<f32>>::new();
-
-        let mut vert1 : Option<Vector2<f32>> = None;
-        let mut vert2 : Option<Vector2<f32>> = None;
-
-        {
-            let mut worker = |and : usize, v_a : Vector2<f32>, v_b : Vector2<f32>, p_a : f32, p_b : f32|{
-                if (sit & and) > 0{
-                    let ip = sample_intersection_brute(Line2{start : v_a, end : v_b}, accuracy, f);
-                    let full = if p_a <= 0.0 {v_a} else {v_b};
-                    let dir = sample_tangent(Square2{center : ip, extent : ext_for_normal}, accuracy, f);
-                    let line = Line2{start : ip - dir * (1.0 / ext_for_normal), end : ip + dir * (1.0 / ext_for_normal)};
-                    tangents.push(line);
-
-                    out_intersections.push(ip);
-                    out_extra.push(full);
-
-                }else{
-                    let negative = p_a < 0.0;
-                    if negative{
-                        out_intersections.push(v_a);
-                        out_extra.push(v_b);
-                    }
-                }
-            };
-
-            worker(1, v0, v1, p0, p1);
-            worker(2, v1, v3, p1, p3);
-            worker(4, v3, v2, p3, p2);
-            worker(8, v2, v0, p2, p0);
-        }
-
-        let interpolated_vertex = sample_qef_brute(vg.square2(x,y), accuracy, &tangents);
-
-        for i in 0..out_intersections.len(){
-            tr.push(Triangle2{p1 : interpolated_vertex, p2 : out_intersections[i], p3 : out_extra[i]});
-        }
-
-        features[y * vg.size_x + x] = Some(interpolated_vertex);
-
-        Some(interpolated_vertex)
-    }else{
-        None
-    }
-}
-
-struct ContourData{
-    pub lines : Vec<Line2<f32>>,
-    pub triangles : Vec<Triangle2<f32>>,
-    pub features : Vec<Option<Vector2<f32>>>,
-    pub intersections : Vec<Option<Vec<Vector2<f32>>>>,
-    pub extras : Vec<Option<Vec<Vector2<f32>>>>,
-}
-
-fn make_contour(vg : &VoxelGrid2<f32>, f : &DenFn2<f32>, accuracy : usize) -> ContourData{
-    let mut res1 = Vec::<Line2<f32>>::new();
-    let mut res2 = Vec::<Triangle2<f32>>::new();
-
-    let mut features : Vec<Option<Vector2<f32>>> = vec![None;vg.size_x * vg.size_y];
-    let mut intersections : Vec<Option<Vec<Vector2<f32>>>> = vec![None;vg.size_x * vg.size_y];
-    let mut extras : Vec<Option<Vec<Vector2<f32>>>> = vec![None;vg.size_x * vg.size_y];
-
-    {
-        let mut cached_make = |x: usize, y: usize, res2: &mut Vec<Triangle2<f32>>| -> Option<Vector2<f32>>{
-            let t = y * vg.size_x + x;
-            let possible = features[t];
-            if possible.is_none() {
-                intersections[t] = Some(Vec::with_capacity(4));//TODO extra mem usage
-                extras[t] = Some(Vec::with_capacity(4));
-
-                let ret = make_vertex(vg, res2, x, y, f, accuracy, &mut features, &mut intersections[t].as_mut().unwrap(), &mut extras[t].as_mut().unwrap());
-                if ret.is_none() {
-                    intersections[t] = None;
-                    extras[t] = None;
-                }
-
-                ret
-            } else {
-                possible
-            }
-        };
-
-        for y in 0..vg.size_y {
-            for x in 0..vg.size_x {
-                let p0 = vg.get(x, y);
-                let p1 = vg.get(x + 1, y);
-                let p2 = vg.get(x, y + 1);
-                let p3 = vg.get(x + 1, y + 1);
-
-                let v0 = vg.get_point(x, y);
-                let v1 = vg.get_point(x + 1, y);
-                let v2 = vg.get_point(x, y + 1);
-                let v3 = vg.get_point(x + 1, y + 1);
-
-                let mut sit = 0;
-
-                if !const_sign(p0, p1) { sit |= 1; }
-                if !const_sign(p1, p3) { sit |= 2; }
-                if !const_sign(p3, p2) { sit |= 4; }
-                if !const_sign(p2, p0) { sit |= 8; }
-
-                if sit > 0 {
-                    let interpolated_vertex = cached_make(x, y, &mut res2).unwrap(); //it is 'some' here
-
-                    let mut vert1: Option<Vector2<f32>> = None;
-                    let mut vert2: Option<Vector2<f32>> = None;
-
-                    if (sit & 2) > 0 {
-                        if x + 1 < vg.size_x {
-                            vert1 = cached_make(x + 1, y, &mut res2);
-                        }
-                    }
-                    if (sit & 4) > 0 {
-                        if y + 1 < vg.size_y {
-                            vert2 = cached_make(x, y + 1, &mut res2);
-                        }
-                    }
-
-                    if vert1.is_some() {
-                        res1.push(Line2 { start: interpolated_vertex, end: vert1.unwrap() });
-                    }
-                    if vert2.is_some() {
-                        res1.push(Line2 { start: interpolated_vertex, end: vert2.unwrap() });
-                    }
-                } else {
-                    let negative = p0 < 0.0;
-
-                    if negative {
-                        let tr1 = Triangle2 { p1: v0, p2: v1, p3: v3 };
-                        let tr2 = Triangle2 { p1: v0, p2: v3, p3: v2 };
-
-                        res2.push(tr1);
-                        res2.push(tr2);
-                    }
-                }
-            }
-        }
-    }
-
-    ContourData{lines : res1, triangles : res2, features, intersections, extras}
-
-}
-
-fn fill_in_grid(vg : &mut VoxelGrid2<f32>, f : &DenFn2<f32>, point : Vector2<f32>){
-    for y in 0..vg.vertices_y(){
-        for x in 0..vg.vertices_x(){
-            let vx = vg.vertices_x();
-            vg.grid[y * vx + x] = f(point + Vector2::new(vg.a * (x as f32), vg.a * (y as f32)));
-        }
-    }
-}*/
-
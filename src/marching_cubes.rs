@@ -0,0 +1,236 @@
+use std;
+use na::*;
+use math::*;
+use mesh::*;
+use dc;
+use dc::VoxelGrid3;
+use density::DenNode;
+use uniform_manifold_dc::{corner_points, edge_pairs};
+use std::collections::HashMap;
+
+//Lorensen-Cline marching cubes with linear interpolation along the edges, the reference for the dual methods
+//the iso-polygons of each cell configuration are traced around the cube faces and fanned into triangles,
+//on ambiguous faces the inside corners are kept apart, so neighbouring cells always agree and the surface has no cracks
+//a vertex is created once per grid edge and shared by the cells around it, normals are interpolated central differences of the grid
+
+//gradient of the grid at one of its vertices, one sided at the border
+fn grid_gradient(vg : &VoxelGrid3<f32>, x : usize, y : usize, z : usize) -> Vector3<f32>{
+    let diff = |lo : f32, hi : f32, steps : usize| (hi - lo) / (steps as f32 * vg.a);
+    let (x0, x1) = (if x > 0 {x - 1} else {x}, std::cmp::min(x + 1, vg.size_x));
+    let (y0, y1) = (if y > 0 {y - 1} else {y}, std::cmp::min(y + 1, vg.size_y));
+    let (z0, z1) = (if z > 0 {z - 1} else {z}, std::cmp::min(z + 1, vg.size_z));
+    Vector3::new(diff(vg.get(x0, y, z), vg.get(x1, y, z), x1 - x0),
+                 diff(vg.get(x, y0, z), vg.get(x, y1, z), y1 - y0),
+                 diff(vg.get(x, y, z0), vg.get(x, y, z1), z1 - z0))
+}
+
+//for each cell edge: the corner with smaller coordinates, the other corner, the axis along the edge
+pub fn edge_axes() -> Vec<(usize, usize, usize)>{
+    let corners = corner_points();
+    edge_pairs().iter().map(|pair|{
+        let d = corners[pair.y] - corners[pair.x];
+        let axis = if d.x != 0.0 {0} else if d.y != 0.0 {1} else {2};
+        if d[axis] > 0.0 {(pair.x, pair.y, axis)} else {(pair.y, pair.x, axis)}
+    }).collect()
+}
+
+//corners of each cube face in counter clockwise order seen from the outside (see `corner_points`)
+pub fn faces() -> [[usize;4];6]{
+    [[0, 3, 7, 4], [1, 5, 6, 2], [0, 4, 5, 1], [3, 2, 6, 7], [0, 1, 2, 3], [4, 7, 6, 5]]
+}
+
+//for each configuration (bit `i` set - corner `i` is inside) the polygons as cycles of edge ids
+pub fn polygon_table() -> Vec<Vec<Vec<usize>>>{
+    let edges = edge_pairs();
+    let edge_id = |a : usize, b : usize| edges.iter().position(|e| (e.x == a && e.y == b) || (e.x == b && e.y == a)).unwrap();
+
+    (0..256).map(|config : usize|{
+        let inside = |corner : usize| config & (1 << corner) != 0;
+
+        //on every face a segment goes from the edge where a run of inside corners starts to the edge where it ends
+        //each edge starts a segment on one of its faces and ends one on the other
+        let mut next = [None;12];
+        for face in faces().iter(){
+            for k in 0..4{
+                if inside(face[k]) || !inside(face[(k + 1) % 4]) {continue;}
+                let mut j = (k + 1) % 4;
+                while inside(face[(j + 1) % 4]) {j = (j + 1) % 4;}
+                next[edge_id(face[k], face[(k + 1) % 4])] = Some(edge_id(face[j], face[(j + 1) % 4]));
+            }
+        }
+
+        let mut polygons = Vec::new();
+        let mut used = [false;12];
+        for start in 0..12{
+            if used[start] || next[start].is_none() {continue;}
+            let mut polygon = Vec::new();
+            let mut edge = start;
+            while !used[edge]{
+                used[edge] = true;
+                polygon.push(edge);
+                edge = next[edge].unwrap();
+            }
+            polygons.push(polygon);
+        }
+
+        polygons
+    }).collect()
+}
+
+//the surface of the grid where the density changes its sign (negative inside), in grid coordinates
+pub fn make_mesh(vg : &VoxelGrid3<f32>) -> Mesh{
+    let table = polygon_table();
+    let corners : Vec<[usize;3]> = corner_points().iter().map(|c| [c.x as usize, c.y as usize, c.z as usize]).collect();
+    let edges = edge_axes();
+    let (vx, vy) = (vg.vertices_x(), vg.vertices_y());
+
+    let mut mesh = Mesh::new();
+    let mut vertex_of_edge = vec![std::u32::MAX;3 * vx * vy * vg.vertices_z()];
+
+    for z in 0..vg.size_z{
+        for y in 0..vg.size_y{
+            for x in 0..vg.size_x{
+                let mut densities = [0.0;8];
+                let mut config = 0;
+                for i in 0..8{
+                    densities[i] = vg.get(x + corners[i][0], y + corners[i][1], z + corners[i][2]);
+                    if densities[i] < 0.0 {config |= 1 << i;}
+                }
+                if config == 0 || config == 255 {continue;}
+
+                for polygon in &table[config]{
+                    let mut indices = Vec::with_capacity(polygon.len());
+                    let mut outward = Vector3::new(0.0, 0.0, 0.0); //sum of the edge directions from the inside corner to the outside one
+                    for &edge_id in polygon{
+                        let (lo, hi, axis) = edges[edge_id];
+                        let (px, py, pz) = (x + corners[lo][0], y + corners[lo][1], z + corners[lo][2]);
+                        let key = 3 * (pz * vy * vx + py * vx + px) + axis;
+                        if vertex_of_edge[key] == std::u32::MAX{
+                            let (d0, d1) = (densities[lo], densities[hi]);
+                            let t = d0 / (d0 - d1);
+                            let mut step = Vector3::new(0.0, 0.0, 0.0);
+                            step[axis] = 1.0;
+                            let (qx, qy, qz) = (x + corners[hi][0], y + corners[hi][1], z + corners[hi][2]);
+                            let normal = grid_gradient(vg, px, py, pz) * (1.0 - t) + grid_gradient(vg, qx, qy, qz) * t;
+                            let len = normal.norm();
+                            let normal = if len > 0.0 {normal / len} else if d0 < 0.0 {step} else {-step};
+                            vertex_of_edge[key] = mesh.push_vertex(vg.get_point(px, py, pz) + step * (t * vg.a), normal, 0);
+                        }
+                        indices.push(vertex_of_edge[key]);
+
+                        let dir = if densities[lo] < 0.0 {1.0} else {-1.0};
+                        outward[axis] += dir;
+                    }
+
+                    //the winding is taken from the inside -> outside direction
+                    let flip = {
+                        let v = |i : usize| mesh.vertices[indices[i] as usize];
+                        let mut normal = Vector3::new(0.0, 0.0, 0.0);
+                        for i in 1..indices.len() - 1{
+                            normal += (v(i) - v(0)).cross(&(v(i + 1) - v(0)));
+                        }
+                        normal.dot(&outward) < 0.0
+                    };
+                    for i in 1..indices.len() - 1{
+                        if flip{
+                            mesh.push_triangle(indices[0], indices[i + 1], indices[i]);
+                        }else{
+                            mesh.push_triangle(indices[0], indices[i], indices[i + 1]);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    mesh
+}
+
+//closed surfaces must come out watertight: each directed edge is used once and its reverse once
+pub fn assert_closed(mesh : &Mesh){
+    let mut directed = HashMap::new();
+    for i in 0..mesh.triangle_count(){
+        for k in 0..3{
+            let edge = (mesh.indices[3 * i + k], mesh.indices[3 * i + (k + 1) % 3]);
+            *directed.entry(edge).or_insert(0) += 1;
+        }
+    }
+    assert!(directed.iter().all(|(&(a, b), &count)| count == 1 && directed.get(&(b, a)) == Some(&1)), "mesh is not closed");
+}
+
+//closed, facing away from `center` and every vertex within `tolerance` of the sphere
+pub fn assert_sphere_mesh(mesh : &Mesh, center : Vector3<f32>, rad : f32, tolerance : f32){
+    assert_closed(mesh);
+    assert!((0..mesh.triangle_count()).all(|i| mesh.face_normal(i).dot(&(mesh.triangle(i).p1 - center)) > 0.0), "triangles facing into the sphere");
+    let max_err = mesh.vertices.iter().map(|v| ((v - center).norm() - rad).abs()).fold(0.0, f32::max);
+    assert!(max_err < tolerance, "vertex {} away from the sphere", max_err);
+}
+
+//closed and each corner of the box `center` +- `extent` has a vertex within `tolerance`
+pub fn assert_box_corners(mesh : &Mesh, center : Vector3<f32>, extent : Vector3<f32>, tolerance : f32){
+    assert_closed(mesh);
+    for i in 0..8{
+        let c = corner_points()[i] * 2.0 - Vector3::new(1.0, 1.0, 1.0);
+        let corner = center + c.component_mul(&extent);
+        let nearest = mesh.vertices.iter().map(|v| (v - corner).norm()).fold(std::f32::MAX, f32::min);
+        assert!(nearest < tolerance, "corner {} of the box is lost, nearest vertex {} away", i, nearest);
+    }
+}
+
+//what the mesher tests contour: a grid of `size`^3 cells with edge `a` from `offset` and shapes around its middle
+pub struct TestShapes{
+    pub a : f32,
+    pub size : usize,
+    pub offset : Vector3<f32>,
+    pub center : Vector3<f32>,
+    pub rad : f32, //of the sphere
+    pub box_center : Vector3<f32>, //off the grid vertices, so its faces are not sampled exactly
+    pub box_extent : Vector3<f32>,
+}
+
+impl TestShapes{
+    pub fn new() -> TestShapes{
+        TestShapes{a : 0.125, size : 32, offset : Vector3::new(0.0, 0.0, 0.0), center : Vector3::new(2.0, 2.0, 2.0), rad : 1.3,
+            box_center : Vector3::new(2.03, 1.96, 2.02), box_extent : Vector3::new(1.1, 0.8, 0.9)}
+    }
+
+    pub fn sphere(&self) -> DenFn3<f32>{
+        mk_sphere_sdf(Sphere{center : self.center, rad : self.rad})
+    }
+
+    pub fn aabb(&self) -> DenFn3<f32>{
+        mk_aabb_sdf(self.box_center, self.box_extent)
+    }
+
+    pub fn torus(&self) -> DenFn3<f32>{
+        mk_torus_y_sdf(1.0, 0.3, self.center)
+    }
+
+    //a field full of saddles (ambiguous faces) cut by a sphere
+    pub fn saddles(&self) -> DenFn3<f32>{
+        let center = self.center;
+        box move |p| {
+            let waves = (p.x * 7.0).sin() * (p.y * 7.0).sin() * (p.z * 7.0).sin();
+            Real::max(waves, (p - center).norm() - 1.7)
+        }
+    }
+}
+
+//a sphere as a closure and as a node with bounds (its grid is culled away from the surface),
+//then a field full of saddles (ambiguous faces) cut by a sphere
+pub fn test_marching_cubes(){
+    let shapes = TestShapes::new();
+    let node = DenNode::sdf_sphere(Sphere{center : shapes.center, rad : shapes.rad});
+    let mut vg = VoxelGrid3::new(shapes.a, shapes.size, shapes.size, shapes.size);
+    for &bounded in [false, true].iter(){
+        if bounded {dc::fill_in_grid(&mut vg, &node, shapes.offset);} else {dc::fill_in_grid(&mut vg, &shapes.sphere(), shapes.offset);}
+        let mesh = make_mesh(&vg);
+        println!("marching cubes, sphere (bounded {}): {} vertices, {} triangles", bounded, mesh.vertex_count(), mesh.triangle_count());
+        assert_sphere_mesh(&mesh, shapes.center, shapes.rad, shapes.a / 4.0);
+    }
+
+    dc::fill_in_grid(&mut vg, &shapes.saddles(), shapes.offset);
+    let mesh = make_mesh(&vg);
+    println!("marching cubes, saddles: {} vertices, {} triangles", mesh.vertex_count(), mesh.triangle_count());
+    assert_closed(&mesh);
+}
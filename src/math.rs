@@ -10,7 +10,8 @@ use std;
 use typenum;
 use generic_array;
 use rand::Rng;
-use noise::{NoiseModule, Perlin};
+use noise::Perlin;
+use terrain::octave_perlin2;
 
 #[derive(Clone, Copy, Debug)]
 pub struct Triangle2<T : Scalar + Copy>{
@@ -80,7 +81,18 @@ pub struct Sphere<T : Scalar>{
 
 
 pub type DenFn2<'a, T> = Box<Fn(Vector2<T>) -> T + 'a>;
-pub type DenFn3<T> = Box<Fn(Vector3<T>) -> T>;
+pub type DenFn3<T> = Box<Fn(Vector3<T>) -> T + Send + Sync>; //`Send + Sync` so grids can be sampled from several threads
+
+//what the magnitude of a density value means
+//Exact - euclidean signed distance to the surface
+//Bound - never greater than the real distance (by absolute value), safe for sphere tracing and culling
+//Implicit - only the sign can be relied on (`mk_sphere`, `mk_torus_*`, noise)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DistanceKind{
+    Exact,
+    Bound,
+    Implicit,
+}
 
 
 pub fn intersection2<'a, 'b, T : Real>(a : &'a DenFn2<'a, T>, b : &'a DenFn2<'a, T>) -> DenFn2<'b, T> where 'a : 'b{
@@ -112,36 +124,184 @@ pub fn difference3<T : Real>(a : DenFn3<T>, b : DenFn3<T>) -> DenFn3<T>{
     Box::new(move |x| {Real::max(a(x), -b(x))})
 }
 
-//0 to 1.0
-pub fn octave_perlin2(perlin : &Perlin, x : f32, z : f32, octaves : usize, persistence : f32) -> f32{
-    let mut total = 0.0;
-    let mut frequency = 1.0;
-    let mut amplitude = 1.0;
-    let mut max_value = 0.0;
+//shape of the transition used by the smooth CSG operators
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendKind{
+    Polynomial, //blends only within `radius` of the seam, hard min/max elsewhere
+    Exponential, //softer, affects the whole field (by at most radius * ln 2)
+}
+
+//smooth minimum of `a` and `b`, returns (value, weight of `a` in the blend, 0 to 1)
+//the result is a convex combination of the gradients, so distance bounds stay bounds
+pub fn smooth_min<T : Real>(a : T, b : T, radius : T, kind : BlendKind) -> (T, T){
+    if radius <= T::zero(){
+        return if a <= b {(a, T::one())} else {(b, T::zero())}
+    }
+
+    match kind{
+        BlendKind::Polynomial => {
+            let h = Real::max(T::zero(), Real::min(T::one(), convert::<f64, T>(0.5) + convert::<f64, T>(0.5) * (b - a) / radius));
+            (b + (a - b) * h - radius * h * (T::one() - h), h)
+        },
+        BlendKind::Exponential => {
+            //-r * ln(e^(-a/r) + e^(-b/r)), rearranged to not overflow
+            let value = Real::min(a, b) - radius * (T::one() + (-(a - b).abs() / radius).exp()).ln();
+            (value, T::one() / (T::one() + ((a - b) / radius).exp()))
+        },
+    }
+}
+
+pub fn smooth_max<T : Real>(a : T, b : T, radius : T, kind : BlendKind) -> (T, T){
+    let (value, weight) = smooth_min(-a, -b, radius, kind);
+    (-value, weight)
+}
+
+pub fn smooth_union3<T : Real>(a : DenFn3<T>, b : DenFn3<T>, radius : T, kind : BlendKind) -> DenFn3<T>{
+    Box::new(move |x| {smooth_min(a(x), b(x), radius, kind).0})
+}
+
+pub fn smooth_intersection3<T : Real>(a : DenFn3<T>, b : DenFn3<T>, radius : T, kind : BlendKind) -> DenFn3<T>{
+    Box::new(move |x| {smooth_max(a(x), b(x), radius, kind).0})
+}
+
+pub fn smooth_difference3<T : Real>(a : DenFn3<T>, b : DenFn3<T>, radius : T, kind : BlendKind) -> DenFn3<T>{
+    Box::new(move |x| {smooth_max(a(x), -b(x), radius, kind).0})
+}
+
+//affine transforms, each maps the sample point into the local frame of `f`
+//rotations, mirroring and translation keep distances, scaling corrects them
+
+pub fn translate3<T : Real>(f : DenFn3<T>, offset : Vector3<T>) -> DenFn3<T>{
+    Box::new(move |x| {f(x - offset)})
+}
+
+pub fn rotate3<T : Real>(f : DenFn3<T>, rotation : UnitQuaternion<T>) -> DenFn3<T>{
+    let inv = rotation.inverse();
+    Box::new(move |x| {f(inv * x)})
+}
+
+//`angle` in radians, counter clockwise around `axis`
+pub fn rotate3_axis_angle<T : Real>(f : DenFn3<T>, axis : Vector3<T>, angle : T) -> DenFn3<T>{
+    rotate3(f, UnitQuaternion::from_axis_angle(&Unit::new_normalize(axis), angle))
+}
+
+//`factor` > 0
+pub fn scale3<T : Real>(f : DenFn3<T>, factor : T) -> DenFn3<T>{
+    Box::new(move |x| {f(x / factor) * factor})
+}
 
-    let k = 2.0.powi((octaves - 1) as i32);
+//exact distances become bounds (scaled by the smallest factor), all factors > 0
+pub fn scale3_non_uniform<T : Real>(f : DenFn3<T>, factor : Vector3<T>) -> DenFn3<T>{
+    let min_factor = Real::min(factor.x, Real::min(factor.y, factor.z));
+    Box::new(move |x| {f(Vector3::new(x.x / factor.x, x.y / factor.y, x.z / factor.z)) * min_factor})
+}
 
-    for i in 0..octaves{
-        total += (perlin.get([x * frequency / k, z * frequency / k]) + 1.0)/2.0 * amplitude;
-        max_value += amplitude;
-        amplitude *= persistence;
-        frequency *= 2.0;
+pub fn reflect_point3<T : Real>(point : Vector3<T>, plane : &Plane<T>) -> Vector3<T>{
+    let n = plane.normal.normalize();
+    point - n * ((point - plane.point).dot(&n) * convert(2.0))
+}
+
+//reflects the shape about the plane
+pub fn mirror3<T : Real>(f : DenFn3<T>, plane : Plane<T>) -> DenFn3<T>{
+    Box::new(move |x| {f(reflect_point3(x, &plane))})
+}
+
+//domain deformations, they operate around the origin of `f`'s frame (wrap the result in `translate3` to place it)
+//meshers assume |gradient| <= 1 (Lipschitz constant 1), each operator notes what happens to it:
+//repeat, elongate, symmetry - constant stays 1, but exact distances become bounds
+//twist, bend - constant grows with the distance from the axis, use `lipschitz_correct3` to get a bound back
+
+fn repeat_coord<T : Real>(x : T, period : T, limit : Option<T>) -> T{
+    if period <= T::zero() {return x}
+    let cell = (x / period).round();
+    match limit{
+        Some(limit) => x - period * Real::max(-limit, Real::min(limit, cell)),
+        None => x - period * cell,
     }
+}
+
+//`limit` - number of copies on each side of the original (per axis), `None` for infinite repetition
+//period of 0 disables repetition along that axis
+pub fn repeat_point3<T : Real>(p : Vector3<T>, period : Vector3<T>, limit : Option<Vector3<T>>) -> Vector3<T>{
+    Vector3::new(repeat_coord(p.x, period.x, limit.map(|l| l.x)),
+                 repeat_coord(p.y, period.y, limit.map(|l| l.y)),
+                 repeat_coord(p.z, period.z, limit.map(|l| l.z)))
+}
+
+//rotation around the Y axis by `rate` radians per unit of height
+pub fn twist_point3<T : Real>(p : Vector3<T>, rate : T) -> Vector3<T>{
+    let (s, c) = (rate * p.y).sin_cos();
+    Vector3::new(c * p.x - s * p.z, p.y, s * p.x + c * p.z)
+}
+
+//bends the X axis towards Y, `rate` - radians per unit along X
+pub fn bend_point3<T : Real>(p : Vector3<T>, rate : T) -> Vector3<T>{
+    let (s, c) = (rate * p.x).sin_cos();
+    Vector3::new(c * p.x - s * p.y, s * p.x + c * p.y, p.z)
+}
 
-    total / max_value
+pub fn elongate_point3<T : Real>(p : Vector3<T>, extent : Vector3<T>) -> Vector3<T>{
+    let clamp = |x : T, e : T| x - Real::max(-e, Real::min(e, x));
+    Vector3::new(clamp(p.x, extent.x), clamp(p.y, extent.y), clamp(p.z, extent.z))
+}
+
+//points behind the plane are reflected to the front
+pub fn symmetry_point3<T : Real>(p : Vector3<T>, plane : &Plane<T>) -> Vector3<T>{
+    if (p - plane.point).dot(&plane.normal) < T::zero() {reflect_point3(p, plane)} else {p}
+}
+
+//the shape should fit in one cell (|extent| < period / 2 along repeated axes),
+//otherwise the neighbouring copies are cut and the field jumps at the cell borders
+pub fn repeat3<T : Real>(f : DenFn3<T>, period : Vector3<T>) -> DenFn3<T>{
+    Box::new(move |x| {f(repeat_point3(x, period, None))})
+}
+
+pub fn repeat3_bounded<T : Real>(f : DenFn3<T>, period : Vector3<T>, limit : Vector3<T>) -> DenFn3<T>{
+    Box::new(move |x| {f(repeat_point3(x, period, Some(limit)))})
+}
+
+//Lipschitz constant becomes sqrt(1 + (rate * r)^2) for points at distance r from the Y axis
+pub fn twist3<T : Real>(f : DenFn3<T>, rate : T) -> DenFn3<T>{
+    Box::new(move |x| {f(twist_point3(x, rate))})
+}
+
+//Lipschitz constant becomes about sqrt(1 + (rate * r)^2), r - distance from the origin in the XY plane
+pub fn bend3<T : Real>(f : DenFn3<T>, rate : T) -> DenFn3<T>{
+    Box::new(move |x| {f(bend_point3(x, rate))})
+}
+
+//stretches the shape by `extent` in both directions of each axis, the middle section is the shape's cross section
+pub fn elongate3<T : Real>(f : DenFn3<T>, extent : Vector3<T>) -> DenFn3<T>{
+    Box::new(move |x| {f(elongate_point3(x, extent))})
+}
+
+//keeps the part of the shape in front of the plane and mirrors it to the back
+pub fn symmetry3<T : Real>(f : DenFn3<T>, plane : Plane<T>) -> DenFn3<T>{
+    let plane = Plane{point : plane.point, normal : plane.normal.normalize()};
+    Box::new(move |x| {f(symmetry_point3(x, &plane))})
+}
+
+//divides the density by the (estimated) Lipschitz constant of `f`, turning it into a distance bound
+//e.g. for `twist3(f, rate)` covering shapes within radius r of the axis: sqrt(1 + (rate * r)^2)
+pub fn lipschitz_correct3<T : Real>(f : DenFn3<T>, lipschitz : T) -> DenFn3<T>{
+    Box::new(move |x| {f(x) / lipschitz})
+}
+
+//heightfield like density inside `cube`, constant positive value outside of it
+pub fn height_noise(perlin : &Perlin, cube : &Square3<f32>, octaves : usize, persistence : f32, x : Vector3<f32>) -> f32{
+    if point3_inside_square3_inclusive(&x, cube){
+        let den = -octave_perlin2(perlin, x.x - (cube.center.x - cube.extent), x.z - (cube.center.z - cube.extent), octaves, persistence) * 2.0 * cube.extent;
+        let dy = (x.y - (cube.center.y - cube.extent) ); //cube.extent / 2.0 ; // 0 - 1
+        //println!("{} {} {}", den, dy, x.y);
+        den + dy
+    }else{
+        0.01
+    }
 }
 
 pub fn noise_f32(perlin : Perlin, cube : Square3<f32>) -> DenFn3<f32>{
     box move |x| {
-        if point3_inside_square3_inclusive(&x, &cube){
-            let den = -octave_perlin2(&perlin, x.x - (cube.center.x - cube.extent), x.z - (cube.center.z - cube.extent), 4, 0.56) * 2.0 * cube.extent;
-            let dy = (x.y - (cube.center.y - cube.extent) ); //cube.extent / 2.0 ; // 0 - 1
-            //println!("{} {} {}", den, dy, x.y);
-            den + dy
-        }else{
-            0.01
-        }
-        
+        height_noise(&perlin, &cube, 4, 0.56, x)
     }
 }
 
@@ -299,7 +459,210 @@ pub fn mk_torus_y<T : Real>(R : T, r : T, offset : Vector3<T>) -> DenFn3<T>{
 }
 
 
-pub fn mk_sphere_displacement<'f, T : Real + Copy>(sphere : Sphere<T>, f : Box<Fn(Vector3<T>) -> T>) -> DenFn3<T>{
+//exact signed distance versions of the primitives above
+//axis aligned half spaces (`mk_half_space_x_neg`, ...) are exact already
+
+pub fn mk_sphere_sdf<T : Real + Copy>(sphere : Sphere<T>) -> DenFn3<T>{
+    Box::new(move |x|{
+        (x - sphere.center).norm() - sphere.rad
+    })
+}
+
+//`d` - point relative to the center of the box, in the box's local frame
+pub fn sdf_box<T : Real>(d : Vector3<T>, extent : Vector3<T>) -> T{
+    let q = Vector3::new(d.x.abs() - extent.x, d.y.abs() - extent.y, d.z.abs() - extent.z);
+    let outside = Vector3::new(q.x.max(T::zero()), q.y.max(T::zero()), q.z.max(T::zero())).norm();
+    let inside = q.x.max(q.y).max(q.z).min(T::zero());
+    outside + inside
+}
+
+pub fn mk_aabb_sdf<T : Real + Copy>(center : Vector3<T>, extent : Vector3<T>) -> DenFn3<T>{
+    Box::new(move |p|{
+        sdf_box(p - center, extent)
+    })
+}
+
+pub fn mk_obb_sdf<T : Real + Copy>(center : Vector3<T>, right : Vector3<T>, up : Vector3<T>, extent : Vector3<T>) -> DenFn3<T>{
+    let right = right.normalize();
+    let up = up.normalize();
+    let look = right.cross(&up);
+    Box::new(move |p|{
+        let d = p - center;
+        sdf_box(Vector3::new(d.dot(&right), d.dot(&up), d.dot(&look)), extent)
+    })
+}
+
+pub fn mk_torus_z_sdf<T : Real>(R : T, r : T, offset : Vector3<T>) -> DenFn3<T>{
+    Box::new(move |p|{
+        let x = p - offset;
+        let a = (x.x * x.x + x.y * x.y).sqrt() - R;
+        (a * a + x.z * x.z).sqrt() - r
+    })
+}
+
+pub fn mk_torus_y_sdf<T : Real>(R : T, r : T, offset : Vector3<T>) -> DenFn3<T>{
+    Box::new(move |p|{
+        let x = p - offset;
+        let a = (x.x * x.x + x.z * x.z).sqrt() - R;
+        (a * a + x.y * x.y).sqrt() - r
+    })
+}
+
+//`mk_half_space_*` are exact only for unit normals, these normalize it
+pub fn mk_half_space_pos_sdf<T : Real + Copy>(plane : Plane<T>) -> DenFn3<T>{
+    mk_half_space_pos(Plane{point : plane.point, normal : plane.normal.normalize()})
+}
+
+pub fn mk_half_space_neg_sdf<T : Real + Copy>(plane : Plane<T>) -> DenFn3<T>{
+    mk_half_space_neg(Plane{point : plane.point, normal : plane.normal.normalize()})
+}
+
+
+//more primitives, all exact signed distances except the ellipsoid (a bound)
+//`sdf_*` take the sample point directly so they can be shared with `DenNode`
+
+fn clamp01<T : Real>(x : T) -> T{
+    Real::max(T::zero(), Real::min(T::one(), x))
+}
+
+//2d distance `d` extruded along the axis, `h` - distance along the axis from the center
+fn sdf_extrude<T : Real>(d : T, h : T, half_height : T) -> T{
+    let w = Vector2::new(d, h.abs() - half_height);
+    Real::min(Real::max(w.x, w.y), T::zero()) + Vector2::new(Real::max(w.x, T::zero()), Real::max(w.y, T::zero())).norm()
+}
+
+pub fn sdf_capsule<T : Real>(p : Vector3<T>, a : Vector3<T>, b : Vector3<T>, rad : T) -> T{
+    let pa = p - a;
+    let ba = b - a;
+    let h = clamp01(pa.dot(&ba) / ba.dot(&ba));
+    (pa - ba * h).norm() - rad
+}
+
+//cylinder around the segment from `a` to `b`, flat caps
+pub fn sdf_capped_cylinder<T : Real>(p : Vector3<T>, a : Vector3<T>, b : Vector3<T>, rad : T) -> T{
+    let ba = b - a;
+    let pa = p - a;
+    let baba = ba.dot(&ba);
+    let paba = pa.dot(&ba);
+    let half : T = convert(0.5);
+
+    let x = (pa * baba - ba * paba).norm() - rad * baba;
+    let y = (paba - baba * half).abs() - baba * half;
+    let x2 = x * x;
+    let y2 = y * y * baba;
+    let d = if Real::max(x, y) < T::zero() {
+        -Real::min(x2, y2)
+    }else{
+        (if x > T::zero() {x2} else {T::zero()}) + (if y > T::zero() {y2} else {T::zero()})
+    };
+
+    d.signum() * d.abs().sqrt() / baba
+}
+
+//cone frustum from `a` (radius `rad_a`) to `b` (radius `rad_b`)
+pub fn sdf_capped_cone<T : Real>(p : Vector3<T>, a : Vector3<T>, b : Vector3<T>, rad_a : T, rad_b : T) -> T{
+    let half : T = convert(0.5);
+    let rba = rad_b - rad_a;
+    let baba = (b - a).dot(&(b - a));
+    let papa = (p - a).dot(&(p - a));
+    let paba = (p - a).dot(&(b - a)) / baba;
+    let x = Real::max(T::zero(), papa - paba * paba * baba).sqrt();
+
+    let cax = Real::max(T::zero(), x - if paba < half {rad_a} else {rad_b});
+    let cay = (paba - half).abs() - half;
+    let k = rba * rba + baba;
+    let f = clamp01((rba * (x - rad_a) + paba * baba) / k);
+    let cbx = x - rad_a - f * rba;
+    let cby = paba - f;
+    let s = if cbx < T::zero() && cay < T::zero() {-T::one()} else {T::one()};
+
+    s * Real::min(cax * cax + cay * cay * baba, cbx * cbx + cby * cby * baba).sqrt()
+}
+
+//`extent` - half size including the rounding
+pub fn sdf_rounded_box<T : Real>(d : Vector3<T>, extent : Vector3<T>, radius : T) -> T{
+    sdf_box(d, extent - Vector3::new(radius, radius, radius)) - radius
+}
+
+//scaled distance in the normalized space of the ellipsoid, never overestimates
+pub fn sdf_ellipsoid<T : Real>(d : Vector3<T>, radii : Vector3<T>) -> T{
+    let min_rad = Real::min(radii.x, Real::min(radii.y, radii.z));
+    (Vector3::new(d.x / radii.x, d.y / radii.y, d.z / radii.z).norm() - T::one()) * min_rad
+}
+
+//prism along the Y axis, `rad` - distance from the axis to the sides
+pub fn sdf_hex_prism<T : Real>(d : Vector3<T>, rad : T, half_height : T) -> T{
+    let kx : T = convert(-0.866025404);
+    let ky : T = convert(0.5);
+    let kz : T = convert(0.577350269);
+
+    let mut q = Vector2::new(d.x.abs(), d.z.abs());
+    let dot = Real::min(kx * q.x + ky * q.y, T::zero());
+    q = q - Vector2::new(kx, ky) * (dot * convert(2.0));
+    let q = q - Vector2::new(Real::max(-kz * rad, Real::min(kz * rad, q.x)), rad);
+
+    sdf_extrude(q.norm() * q.y.signum(), d.y, half_height)
+}
+
+//prism along the Y axis with an equilateral triangle cross section pointing to +Z, `rad` - half the side length
+pub fn sdf_tri_prism<T : Real>(d : Vector3<T>, rad : T, half_height : T) -> T{
+    let k : T = convert(1.732050808);
+    let half : T = convert(0.5);
+
+    let mut q = Vector2::new(d.x.abs() - rad, d.z + rad / k);
+    if q.x + k * q.y > T::zero(){
+        q = Vector2::new(q.x - k * q.y, -k * q.x - q.y) * half;
+    }
+    q.x = q.x - Real::max(-rad * convert(2.0), Real::min(T::zero(), q.x));
+
+    sdf_extrude(-q.norm() * q.y.signum(), d.y, half_height)
+}
+
+//torus in the plane perpendicular to `axis`
+pub fn sdf_torus<T : Real>(d : Vector3<T>, axis : Vector3<T>, R : T, r : T) -> T{
+    let n = axis.normalize();
+    let h = d.dot(&n);
+    let a = (d - n * h).norm() - R;
+    (a * a + h * h).sqrt() - r
+}
+
+pub fn mk_capsule<T : Real>(a : Vector3<T>, b : Vector3<T>, rad : T) -> DenFn3<T>{
+    Box::new(move |p| {sdf_capsule(p, a, b, rad)})
+}
+
+pub fn mk_capped_cylinder<T : Real>(a : Vector3<T>, b : Vector3<T>, rad : T) -> DenFn3<T>{
+    Box::new(move |p| {sdf_capped_cylinder(p, a, b, rad)})
+}
+
+pub fn mk_capped_cone<T : Real>(a : Vector3<T>, b : Vector3<T>, rad_a : T, rad_b : T) -> DenFn3<T>{
+    Box::new(move |p| {sdf_capped_cone(p, a, b, rad_a, rad_b)})
+}
+
+pub fn mk_cone<T : Real>(base : Vector3<T>, apex : Vector3<T>, rad : T) -> DenFn3<T>{
+    mk_capped_cone(base, apex, rad, T::zero())
+}
+
+pub fn mk_rounded_box<T : Real>(center : Vector3<T>, extent : Vector3<T>, radius : T) -> DenFn3<T>{
+    Box::new(move |p| {sdf_rounded_box(p - center, extent, radius)})
+}
+
+pub fn mk_ellipsoid<T : Real>(center : Vector3<T>, radii : Vector3<T>) -> DenFn3<T>{
+    Box::new(move |p| {sdf_ellipsoid(p - center, radii)})
+}
+
+pub fn mk_hex_prism<T : Real>(center : Vector3<T>, rad : T, half_height : T) -> DenFn3<T>{
+    Box::new(move |p| {sdf_hex_prism(p - center, rad, half_height)})
+}
+
+pub fn mk_tri_prism<T : Real>(center : Vector3<T>, rad : T, half_height : T) -> DenFn3<T>{
+    Box::new(move |p| {sdf_tri_prism(p - center, rad, half_height)})
+}
+
+pub fn mk_torus<T : Real>(center : Vector3<T>, axis : Vector3<T>, R : T, r : T) -> DenFn3<T>{
+    Box::new(move |p| {sdf_torus(p - center, axis, R, r)})
+}
+
+pub fn mk_sphere_displacement<'f, T : Real + Copy>(sphere : Sphere<T>, f : Box<Fn(Vector3<T>) -> T + Send + Sync>) -> DenFn3<T>{
     Box::new(move |x|{
         let dist = x - sphere.center;
         dist.dot(&dist) - sphere.rad * sphere.rad * f(dist.normalize())
@@ -319,6 +682,47 @@ pub fn distance_point3_plane<T : Real>(point3 : &Vector3<T>, plane : &Plane<T>)
     Real::abs(plane.normal.dot(&vec))
 }
 
+//closest point on a (possibly degenerate) triangle, by the voronoi region of `point3` (Ericson, Real-Time Collision Detection 5.1.5)
+pub fn closest_point3_triangle3<T : Real>(point3 : &Vector3<T>, tr : &Triangle3<T>) -> Vector3<T>{
+    let (a, b, c) = (tr.p1, tr.p2, tr.p3);
+    let ab = b - a;
+    let ac = c - a;
+
+    let ap = point3 - a;
+    let d1 = ab.dot(&ap);
+    let d2 = ac.dot(&ap);
+    if d1 <= T::zero() && d2 <= T::zero() {return a;}
+
+    let bp = point3 - b;
+    let d3 = ab.dot(&bp);
+    let d4 = ac.dot(&bp);
+    if d3 >= T::zero() && d4 <= d3 {return b;}
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= T::zero() && d1 >= T::zero() && d3 <= T::zero() && d1 - d3 > T::zero(){
+        return a + ab * (d1 / (d1 - d3));
+    }
+
+    let cp = point3 - c;
+    let d5 = ab.dot(&cp);
+    let d6 = ac.dot(&cp);
+    if d6 >= T::zero() && d5 <= d6 {return c;}
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= T::zero() && d2 >= T::zero() && d6 <= T::zero() && d2 - d6 > T::zero(){
+        return a + ac * (d2 / (d2 - d6));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= T::zero() && d4 - d3 >= T::zero() && d5 - d6 >= T::zero() && (d4 - d3) + (d5 - d6) > T::zero(){
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    let sum = va + vb + vc;
+    if sum <= T::zero() {return a;} //degenerate, every edge test failed
+    a + ab * (vb / sum) + ac * (vc / sum)
+}
+
 pub fn point3_inside_square3_inclusive<T : Real>(point3 : &Vector3<T>, square3 : &Square3<T>) -> bool{
     point3.x <= square3.center.x + square3.extent &&
     point3.x >= square3.center.x - square3.extent &&
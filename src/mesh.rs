@@ -0,0 +1,163 @@
+use std;
+use na::*;
+use math::*;
+
+//indexed triangle mesh shared by the meshers: one vertex per feature point, three indices per triangle
+//normals and material ids are per vertex, meshers without materials leave them at 0
+#[derive(Clone, Debug)]
+pub struct Mesh{
+    pub vertices : Vec<Vector3<f32>>,
+    pub normals : Vec<Vector3<f32>>,
+    pub materials : Vec<u32>,
+    pub indices : Vec<u32>,
+}
+
+impl Mesh{
+    pub fn new() -> Mesh{
+        Mesh{vertices : Vec::new(), normals : Vec::new(), materials : Vec::new(), indices : Vec::new()}
+    }
+
+    pub fn vertex_count(&self) -> usize{
+        self.vertices.len()
+    }
+
+    pub fn triangle_count(&self) -> usize{
+        self.indices.len() / 3
+    }
+
+    //returns the index of the new vertex
+    pub fn push_vertex(&mut self, position : Vector3<f32>, normal : Vector3<f32>, material : u32) -> u32{
+        self.vertices.push(position);
+        self.normals.push(normal);
+        self.materials.push(material);
+        (self.vertices.len() - 1) as u32
+    }
+
+    pub fn push_triangle(&mut self, a : u32, b : u32, c : u32){
+        self.indices.push(a);
+        self.indices.push(b);
+        self.indices.push(c);
+    }
+
+    //`a`, `b`, `c`, `d` in counter clockwise order seen from the front
+    pub fn push_quad(&mut self, a : u32, b : u32, c : u32, d : u32){
+        self.push_triangle(a, b, c);
+        self.push_triangle(a, c, d);
+    }
+
+    //quad that may not be flat, split along the diagonal that keeps both halves facing like the vertex normals
+    pub fn push_bent_quad(&mut self, a : u32, b : u32, c : u32, d : u32){
+        let quad = [a, b, c, d];
+        let normal = quad.iter().fold(Vector3::new(0.0, 0.0, 0.0), |sum, &i| sum + self.normals[i as usize]);
+        let facing = |i : usize|{
+            let v = |k : usize| self.vertices[quad[(i + k) % 4] as usize];
+            f32::min((v(1) - v(0)).cross(&(v(2) - v(0))).dot(&normal), (v(2) - v(0)).cross(&(v(3) - v(0))).dot(&normal))
+        };
+        if facing(0) >= facing(1){
+            self.push_quad(a, b, c, d);
+        }else{
+            self.push_quad(b, c, d, a);
+        }
+    }
+
+    pub fn triangle(&self, i : usize) -> Triangle3<f32>{
+        Triangle3{p1 : self.vertices[self.indices[3 * i] as usize], p2 : self.vertices[self.indices[3 * i + 1] as usize], p3 : self.vertices[self.indices[3 * i + 2] as usize]}
+    }
+
+    //unit normal of the triangle from its winding, zero for degenerate triangles
+    pub fn face_normal(&self, i : usize) -> Vector3<f32>{
+        let tr = self.triangle(i);
+        let n = (tr.p2 - tr.p1).cross(&(tr.p3 - tr.p1));
+        let len = n.norm();
+        if len > 0.0 {n / len} else {n}
+    }
+
+    //triangle soup, for code that still works with separate triangles
+    pub fn triangles(&self) -> Vec<Triangle3<f32>>{
+        (0..self.triangle_count()).map(|i| self.triangle(i)).collect()
+    }
+
+    //moves every vertex by `offset`
+    pub fn translate(&mut self, offset : Vector3<f32>){
+        for v in self.vertices.iter_mut(){
+            *v += offset;
+        }
+    }
+
+    //appends `other`, its indices are shifted past the vertices already present
+    pub fn append(&mut self, other : &Mesh){
+        let base = self.vertices.len() as u32;
+        self.vertices.extend_from_slice(&other.vertices);
+        self.normals.extend_from_slice(&other.normals);
+        self.materials.extend_from_slice(&other.materials);
+        self.indices.extend(other.indices.iter().map(|i| i + base));
+    }
+
+    //replaces the normals with the area weighted average of the adjacent face normals
+    pub fn smooth_normals(&mut self){
+        let mut normals = vec![Vector3::new(0.0, 0.0, 0.0);self.vertices.len()];
+        for i in 0..self.triangle_count(){
+            let tr = self.triangle(i);
+            let n = (tr.p2 - tr.p1).cross(&(tr.p3 - tr.p1)); //length is twice the area
+            for k in 0..3{
+                normals[self.indices[3 * i + k] as usize] += n;
+            }
+        }
+
+        for (normal, sum) in self.normals.iter_mut().zip(normals.into_iter()){
+            let len = sum.norm();
+            if len > 0.0 {*normal = sum / len;}
+        }
+    }
+
+    //flat shaded cube, four vertices per face so every face keeps its own normal
+    pub fn push_cube(&mut self, cube : Square3<f32>, material : u32){
+        let corners = [Vector3::new(-1.0, -1.0, -1.0), Vector3::new(1.0, -1.0, -1.0), Vector3::new(1.0, -1.0, 1.0), Vector3::new(-1.0, -1.0, 1.0),
+                       Vector3::new(-1.0, 1.0, -1.0), Vector3::new(1.0, 1.0, -1.0), Vector3::new(1.0, 1.0, 1.0), Vector3::new(-1.0, 1.0, 1.0)];
+        //corners of each face in counter clockwise order seen from the outside
+        let faces : [([usize;4], Vector3<f32>);6] = [([0, 3, 7, 4], Vector3::new(-1.0, 0.0, 0.0)), ([1, 5, 6, 2], Vector3::new(1.0, 0.0, 0.0)),
+                                                      ([0, 4, 5, 1], Vector3::new(0.0, 0.0, -1.0)), ([3, 2, 6, 7], Vector3::new(0.0, 0.0, 1.0)),
+                                                      ([0, 1, 2, 3], Vector3::new(0.0, -1.0, 0.0)), ([4, 7, 6, 5], Vector3::new(0.0, 1.0, 0.0))];
+        for &(face, normal) in faces.iter(){
+            let mut indices = [0;4];
+            for i in 0..4{
+                indices[i] = self.push_vertex(cube.center + corners[face[i]] * cube.extent, normal, material);
+            }
+            self.push_quad(indices[0], indices[1], indices[2], indices[3]);
+        }
+    }
+
+    //bytes held by the buffers, for comparing against a triangle soup
+    pub fn memory_size(&self) -> usize{
+        self.vertices.len() * (2 * std::mem::size_of::<Vector3<f32>>() + std::mem::size_of::<u32>()) + self.indices.len() * std::mem::size_of::<u32>()
+    }
+}
+
+//colored lines meshers can emit next to the mesh to show what they did, e.g. the cells they refined
+#[derive(Clone, Debug)]
+pub struct DebugGeometry{
+    pub lines : Vec<Line3<f32>>,
+    pub colors : Vec<Vector3<f32>>, //one per line
+}
+
+impl DebugGeometry{
+    pub fn new() -> DebugGeometry{
+        DebugGeometry{lines : Vec::new(), colors : Vec::new()}
+    }
+
+    pub fn add_line(&mut self, line : Line3<f32>, color : Vector3<f32>){
+        self.lines.push(line);
+        self.colors.push(color);
+    }
+
+    //the 12 edges of the cube
+    pub fn add_square3_bounds(&mut self, cube : Square3<f32>, color : Vector3<f32>){
+        let e = cube.extent;
+        let corners = [Vector3::new(-e, -e, -e), Vector3::new(e, -e, -e), Vector3::new(e, e, -e), Vector3::new(-e, e, -e),
+                       Vector3::new(-e, -e, e), Vector3::new(e, -e, e), Vector3::new(e, e, e), Vector3::new(-e, e, e)];
+        let edges = [(0, 1), (1, 2), (2, 3), (3, 0), (4, 5), (5, 6), (6, 7), (7, 4), (0, 4), (1, 5), (2, 6), (3, 7)];
+        for &(a, b) in edges.iter(){
+            self.add_line(Line3{start : cube.center + corners[a], end : cube.center + corners[b]}, color);
+        }
+    }
+}
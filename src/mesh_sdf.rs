@@ -0,0 +1,457 @@
+use std;
+use std::fs::File;
+use std::io::Read;
+use na::*;
+use math::*;
+use dc::VoxelGrid3;
+use dcm::*;
+use sampled_grid::*;
+use rayon::prelude::*;
+
+//conversion of triangle meshes (OBJ, STL) into signed distance grids, so existing assets can be combined with
+//other density functions and re-contoured
+//distances are exact at the grid vertices and interpolated in between (see sampled_grid.rs), the sign comes from `InsideTest`
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InsideTest{
+    WindingNumber, //generalized winding number, robust against small holes and self intersections
+    RayParity,     //number of crossings of a ray, exact for closed meshes and cheaper
+}
+
+//`v` and `f` records, polygons are fanned into triangles, texture and normal indices are ignored
+pub fn parse_obj(src : &str) -> Result<Vec<Triangle3<f32>>, String>{
+    let mut vertices : Vec<Vector3<f32>> = Vec::new();
+    let mut triangles = Vec::new();
+
+    for (i, line) in src.lines().enumerate(){
+        let mut words = line.split_whitespace();
+        match words.next(){
+            Some("v") => {
+                let coords : Vec<f32> = words.take(3).map(|w| w.parse()).collect::<Result<_, _>>().map_err(|_| format!("line {}: invalid vertex", i + 1))?;
+                if coords.len() != 3 {return Err(format!("line {}: vertex needs 3 coordinates", i + 1));}
+                vertices.push(Vector3::new(coords[0], coords[1], coords[2]));
+            },
+            Some("f") => {
+                let mut face = Vec::new();
+                for word in words{
+                    let index : i64 = word.split('/').next().unwrap().parse().map_err(|_| format!("line {}: invalid face index `{}`", i + 1, word))?;
+                    //1 based, negative indices are relative to the end of the vertex list
+                    let resolved = if index < 0 {vertices.len() as i64 + index} else {index - 1};
+                    if resolved < 0 || resolved >= vertices.len() as i64 {return Err(format!("line {}: face index {} out of range", i + 1, index));}
+                    face.push(vertices[resolved as usize]);
+                }
+                if face.len() < 3 {return Err(format!("line {}: face needs at least 3 vertices", i + 1));}
+                for j in 1..face.len() - 1{
+                    triangles.push(Triangle3{p1 : face[0], p2 : face[j], p3 : face[j + 1]});
+                }
+            },
+            _ => (),
+        }
+    }
+
+    Ok(triangles)
+}
+
+fn read_f32_le(bytes : &[u8]) -> f32{
+    f32::from_bits(bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24)
+}
+
+//binary or ASCII STL, binary files may also start with `solid` so the size decides
+pub fn parse_stl(bytes : &[u8]) -> Result<Vec<Triangle3<f32>>, String>{
+    if bytes.len() >= 84{
+        let count = (bytes[80] as usize) | (bytes[81] as usize) << 8 | (bytes[82] as usize) << 16 | (bytes[83] as usize) << 24;
+        if bytes.len() == 84 + count * 50{
+            let mut triangles = Vec::with_capacity(count);
+            for i in 0..count{
+                let record = &bytes[84 + i * 50..];
+                let v = |k : usize| Vector3::new(read_f32_le(&record[12 + k * 12..]), read_f32_le(&record[16 + k * 12..]), read_f32_le(&record[20 + k * 12..]));
+                triangles.push(Triangle3{p1 : v(0), p2 : v(1), p3 : v(2)});
+            }
+            return Ok(triangles);
+        }
+    }
+
+    let src = std::str::from_utf8(bytes).map_err(|_| String::from("neither a binary nor an ASCII STL"))?;
+    if !src.trim_left().starts_with("solid") {return Err(String::from("neither a binary nor an ASCII STL"));}
+
+    let mut triangles = Vec::new();
+    let mut face = Vec::with_capacity(3);
+    for (i, line) in src.lines().enumerate(){
+        let mut words = line.split_whitespace();
+        match words.next(){
+            Some("vertex") => {
+                let coords : Vec<f32> = words.take(3).map(|w| w.parse()).collect::<Result<_, _>>().map_err(|_| format!("line {}: invalid vertex", i + 1))?;
+                if coords.len() != 3 {return Err(format!("line {}: vertex needs 3 coordinates", i + 1));}
+                face.push(Vector3::new(coords[0], coords[1], coords[2]));
+            },
+            Some("endloop") => {
+                if face.len() != 3 {return Err(format!("line {}: facet must have 3 vertices", i + 1));}
+                triangles.push(Triangle3{p1 : face[0], p2 : face[1], p3 : face[2]});
+                face.clear();
+            },
+            _ => (),
+        }
+    }
+
+    Ok(triangles)
+}
+
+//picks the parser by extension: `.obj` or `.stl`
+pub fn load_mesh(path : &str) -> Result<Vec<Triangle3<f32>>, String>{
+    let mut bytes = Vec::new();
+    File::open(path).and_then(|mut file| file.read_to_end(&mut bytes)).map_err(|e| format!("{}: {}", path, e))?;
+
+    let lower = path.to_lowercase();
+    let result = if lower.ends_with(".obj") {
+        std::str::from_utf8(&bytes).map_err(|_| String::from("not a text file")).and_then(parse_obj)
+    }else if lower.ends_with(".stl") {
+        parse_stl(&bytes)
+    }else{
+        Err(String::from("unknown mesh format, expected .obj or .stl"))
+    };
+
+    result.map_err(|e| format!("{}: {}", path, e))
+}
+
+//solid angle of the triangle seen from `p` divided by 4pi (Van Oosterom and Strackee)
+fn winding_number_triangle(p : &Vector3<f32>, tr : &Triangle3<f32>) -> f32{
+    let a = tr.p1 - p;
+    let b = tr.p2 - p;
+    let c = tr.p3 - p;
+    let (la, lb, lc) = (a.norm(), b.norm(), c.norm());
+
+    let numerator = a.dot(&b.cross(&c));
+    let denominator = la * lb * lc + a.dot(&b) * lc + b.dot(&c) * la + c.dot(&a) * lb;
+
+    2.0 * numerator.atan2(denominator) / (4.0 * std::f32::consts::PI)
+}
+
+//Moller-Trumbore, counts hits with t > 0 only
+fn ray_hits_triangle(origin : &Vector3<f32>, dir : &Vector3<f32>, tr : &Triangle3<f32>) -> bool{
+    let e1 = tr.p2 - tr.p1;
+    let e2 = tr.p3 - tr.p1;
+    let h = dir.cross(&e2);
+    let det = e1.dot(&h);
+    if det.abs() < 1e-12 {return false;}
+
+    let s = origin - tr.p1;
+    let u = s.dot(&h) / det;
+    if u < 0.0 || u > 1.0 {return false;}
+
+    let q = s.cross(&e1);
+    let v = dir.dot(&q) / det;
+    if v < 0.0 || u + v > 1.0 {return false;}
+
+    e2.dot(&q) / det > 0.0
+}
+
+//irrational looking direction so that the ray practically never grazes an edge or a vertex
+fn ray_direction() -> Vector3<f32>{
+    Vector3::new(0.3122, 0.8117, 0.4939).normalize()
+}
+
+pub fn point3_inside_mesh(p : &Vector3<f32>, triangles : &Vec<Triangle3<f32>>, test : InsideTest) -> bool{
+    match test{
+        InsideTest::WindingNumber => triangles.iter().map(|tr| winding_number_triangle(p, tr)).sum::<f32>().abs() > 0.5,
+        InsideTest::RayParity => {
+            let dir = ray_direction();
+            triangles.iter().filter(|tr| ray_hits_triangle(p, &dir, tr)).count() % 2 == 1
+        },
+    }
+}
+
+pub fn distance_point3_mesh(p : &Vector3<f32>, triangles : &Vec<Triangle3<f32>>) -> f32{
+    let mut best = std::f32::MAX;
+    for tr in triangles{
+        let d = (closest_point3_triangle3(p, tr) - p).norm_squared();
+        if d < best {best = d;}
+    }
+
+    best.sqrt()
+}
+
+fn triangle_bounds(tr : &Triangle3<f32>) -> (Vector3<f32>, Vector3<f32>){
+    let mut min = tr.p1;
+    let mut max = tr.p1;
+    for p in [tr.p2, tr.p3].iter(){
+        for i in 0..3{
+            min[i] = Real::min(min[i], p[i]);
+            max[i] = Real::max(max[i], p[i]);
+        }
+    }
+
+    (min, max)
+}
+
+//bounding box of the vertices
+fn mesh_bounds(triangles : &Vec<Triangle3<f32>>) -> (Vector3<f32>, Vector3<f32>){
+    let mut min = Vector3::new(std::f32::MAX, std::f32::MAX, std::f32::MAX);
+    let mut max = -min;
+    for tr in triangles{
+        let (lo, hi) = triangle_bounds(tr);
+        for i in 0..3{
+            min[i] = Real::min(min[i], lo[i]);
+            max[i] = Real::max(max[i], hi[i]);
+        }
+    }
+
+    (min, max)
+}
+
+//triangles bucketed into a uniform grid of cubic cells by their bounding boxes, a triangle is stored in every cell its box touches
+//speeds up the closest point and ray parity queries of `MeshSdf::new`, the winding number still sums over every triangle
+struct TriangleGrid<'a>{
+    triangles : &'a Vec<Triangle3<f32>>,
+    min : Vector3<f32>,
+    cell : f32,
+    dims : [usize;3],
+    cells : Vec<Vec<usize>>,
+}
+
+impl<'a> TriangleGrid<'a>{
+    //about as many cells as triangles for a mesh with a cubic bounding box
+    fn new(triangles : &'a Vec<Triangle3<f32>>) -> TriangleGrid<'a>{
+        let (min, max) = mesh_bounds(triangles);
+        let extent = max - min;
+        let largest = Real::max(Real::max(extent.x, extent.y), extent.z);
+        let cell = if largest > 0.0 {largest / Real::max((triangles.len() as f32).cbrt().floor(), 1.0)} else {1.0};
+        let dim = |i : usize| Real::max((extent[i] / cell).ceil(), 1.0) as usize;
+        let dims = [dim(0), dim(1), dim(2)];
+
+        let mut grid = TriangleGrid{triangles, min, cell, dims, cells : vec![Vec::new();dims[0] * dims[1] * dims[2]]};
+        //boxes are padded a little so that a ray stepping through the corner of a cell does not miss a triangle due to rounding
+        let pad = Vector3::new(cell, cell, cell) * 1e-3;
+        for (t, tr) in triangles.iter().enumerate(){
+            let (lo, hi) = triangle_bounds(tr);
+            let (lo, hi) = (grid.coords(&(lo - pad)), grid.coords(&(hi + pad)));
+            for z in lo[2]..hi[2] + 1{
+                for y in lo[1]..hi[1] + 1{
+                    for x in lo[0]..hi[0] + 1{
+                        let i = grid.index(x, y, z);
+                        grid.cells[i].push(t);
+                    }
+                }
+            }
+        }
+
+        grid
+    }
+
+    fn index(&self, x : usize, y : usize, z : usize) -> usize{
+        (z * self.dims[1] + y) * self.dims[0] + x
+    }
+
+    //cell containing `p`, points outside of the grid are clamped to its border cells
+    fn coords(&self, p : &Vector3<f32>) -> [usize;3]{
+        let coord = |i : usize| {
+            let c = ((p[i] - self.min[i]) / self.cell).floor();
+            if c > 0.0 {std::cmp::min(c as usize, self.dims[i] - 1)} else {0}
+        };
+
+        [coord(0), coord(1), coord(2)]
+    }
+
+    //same as `distance_point3_mesh`: visits shells of cells around the cell of `p` until the unvisited ones are farther than the closest triangle
+    fn distance(&self, p : &Vector3<f32>) -> f32{
+        let c = self.coords(p);
+        let mut best = std::f32::MAX;
+        let shells = *self.dims.iter().max().unwrap();
+
+        for r in 0..shells{
+            let range = |i : usize| (c[i].saturating_sub(r), std::cmp::min(c[i] + r + 1, self.dims[i]));
+            let ((x0, x1), (y0, y1), (z0, z1)) = (range(0), range(1), range(2));
+            for z in z0..z1{
+                for y in y0..y1{
+                    for x in x0..x1{
+                        let shell = std::cmp::max(std::cmp::max(abs_diff(x, c[0]), abs_diff(y, c[1])), abs_diff(z, c[2]));
+                        if shell != r {continue;}
+
+                        for &t in &self.cells[self.index(x, y, z)]{
+                            let d = (closest_point3_triangle3(p, &self.triangles[t]) - p).norm_squared();
+                            if d < best {best = d;}
+                        }
+                    }
+                }
+            }
+
+            //the cells left are more than `r` cells away from the cell of `p` along some axis, so at least `r` cells away from `p`
+            let reach = self.cell * r as f32;
+            if best <= reach * reach {break;}
+        }
+
+        best.sqrt()
+    }
+
+    //same as `point3_inside_mesh` with `InsideTest::RayParity`: walks the cells along the ray (Amanatides and Woo),
+    //triangles stored in several of them are tested once
+    fn ray_parity(&self, origin : &Vector3<f32>, dir : &Vector3<f32>) -> bool{
+        let max = self.min + Vector3::new(self.dims[0] as f32, self.dims[1] as f32, self.dims[2] as f32) * self.cell;
+        let (mut t0, mut t1) = (0.0f32, std::f32::MAX);
+        for i in 0..3{
+            if dir[i] == 0.0{
+                if origin[i] < self.min[i] || origin[i] > max[i] {return false;}
+            }else{
+                let (a, b) = ((self.min[i] - origin[i]) / dir[i], (max[i] - origin[i]) / dir[i]);
+                t0 = Real::max(t0, Real::min(a, b));
+                t1 = Real::min(t1, Real::max(a, b));
+            }
+        }
+        if t0 > t1 {return false;}
+
+        let entry = origin + dir * t0;
+        let start = self.coords(&entry);
+        let mut c = [start[0] as isize, start[1] as isize, start[2] as isize];
+        let mut step = [0isize;3];
+        let mut t_max = [std::f32::MAX;3];
+        let mut t_delta = [std::f32::MAX;3];
+        for i in 0..3{
+            if dir[i] != 0.0{
+                step[i] = if dir[i] > 0.0 {1} else {-1};
+                let boundary = self.min[i] + (c[i] + if dir[i] > 0.0 {1} else {0}) as f32 * self.cell;
+                t_max[i] = t0 + (boundary - entry[i]) / dir[i];
+                t_delta[i] = self.cell / dir[i].abs();
+            }
+        }
+
+        let mut candidates = Vec::new();
+        loop{
+            candidates.extend_from_slice(&self.cells[self.index(c[0] as usize, c[1] as usize, c[2] as usize)]);
+
+            let i = if t_max[0] < t_max[1] {if t_max[0] < t_max[2] {0} else {2}} else {if t_max[1] < t_max[2] {1} else {2}};
+            if t_max[i] > t1 {break;}
+            c[i] += step[i];
+            if c[i] < 0 || c[i] >= self.dims[i] as isize {break;}
+            t_max[i] += t_delta[i];
+        }
+
+        candidates.sort();
+        candidates.dedup();
+        candidates.iter().filter(|&&t| ray_hits_triangle(origin, dir, &self.triangles[t])).count() % 2 == 1
+    }
+}
+
+fn abs_diff(a : usize, b : usize) -> usize{
+    if a > b {a - b} else {b - a}
+}
+
+//signed distance samples of a mesh at the vertices of a grid with its min corner at `offset`
+pub struct MeshSdf{
+    pub sampled : SampledGrid,
+}
+
+impl MeshSdf{
+    //`size` cells along each axis, each `a` wide; negative inside the mesh
+    //z layers of the grid are sampled in parallel, the triangles are looked up through a `TriangleGrid`
+    pub fn new(triangles : &Vec<Triangle3<f32>>, offset : Vector3<f32>, a : f32, size : [usize;3], test : InsideTest) -> MeshSdf{
+        let mut grid = VoxelGrid3::new(a, size[0], size[1], size[2]);
+        let accel = TriangleGrid::new(triangles);
+        let dir = ray_direction();
+
+        let (vx, vy) = (grid.vertices_x(), grid.vertices_y());
+        grid.grid.par_chunks_mut(vx * vy).enumerate().for_each(|(z, layer)|{
+            for y in 0..vy{
+                for x in 0..vx{
+                    let p = offset + Vector3::new(a * (x as f32), a * (y as f32), a * (z as f32));
+                    let dist = accel.distance(&p);
+                    let inside = match test{
+                        InsideTest::WindingNumber => point3_inside_mesh(&p, triangles, test),
+                        InsideTest::RayParity => accel.ray_parity(&p, &dir),
+                    };
+                    layer[y * vx + x] = if inside {-dist} else {dist};
+                }
+            }
+        });
+
+        MeshSdf{sampled : SampledGrid::new(grid, offset, Interpolation::Trilinear)}
+    }
+
+    //grid that encloses the bounding box of the mesh with `padding` cells of empty space on each side
+    pub fn fit(triangles : &Vec<Triangle3<f32>>, a : f32, padding : usize, test : InsideTest) -> MeshSdf{
+        let (min, max) = mesh_bounds(triangles);
+
+        let pad = a * padding as f32;
+        let offset = min - Vector3::new(pad, pad, pad);
+        let size = |i : usize| ((max[i] - min[i] + 2.0 * pad) / a).ceil() as usize + 1;
+        MeshSdf::new(triangles, offset, a, [size(0), size(1), size(2)], test)
+    }
+
+    pub fn eval(&self, p : Vector3<f32>) -> f32{
+        self.sampled.eval(p)
+    }
+
+    pub fn to_den_fn(self) -> DenFn3<f32>{
+        self.sampled.to_den_fn()
+    }
+
+    //whole mesh filled with `material`, for `dcm::make_contour`
+    pub fn to_den_mat_fn(self, material : u32) -> DenMatFn3<f32>{
+        box move |p| {
+            let density = self.eval(p);
+            MaterialPoint{density, material : if density <= 0.0 {material} else {0}}
+        }
+    }
+}
+
+//mesh as a density function: loads the file and samples a grid around it with `padding` cells of space on each side
+pub fn mk_mesh_sdf(path : &str, a : f32, padding : usize, test : InsideTest) -> Result<DenFn3<f32>, String>{
+    let triangles = load_mesh(path)?;
+    if triangles.is_empty() {return Err(format!("{}: mesh has no triangles", path));}
+    Ok(MeshSdf::fit(&triangles, a, padding, test).to_den_fn())
+}
+
+//voxelizes an octahedron given as an OBJ string and compares the grid against its analytic distance
+pub fn test_mesh_sdf(){
+    let obj = "v 1 0 0\nv -1 0 0\nv 0 1 0\nv 0 -1 0\nv 0 0 1\nv 0 0 -1\n\
+               f 1 3 5\nf 3 2 5\nf 2 4 5\nf 4 1 5\nf 3 1 6\nf 2 3 6\nf 4 2 6\nf 1 4 6\n";
+    let triangles = parse_obj(obj).unwrap();
+    assert_eq!(triangles.len(), 8);
+
+    let exact = |p : Vector3<f32>| (p.x.abs() + p.y.abs() + p.z.abs() - 1.0) / 3.0f32.sqrt();
+
+    let a = 0.1;
+    for &test in [InsideTest::WindingNumber, InsideTest::RayParity].iter(){
+        let sdf = MeshSdf::fit(&triangles, a, 2, test);
+        let mut max_err : f32 = 0.0;
+        let mut sign_errors = 0;
+        for i in 0..1000{
+            let p = Vector3::new((i % 10) as f32, ((i / 10) % 10) as f32, (i / 100) as f32) * 0.3 - Vector3::new(1.37, 1.31, 1.33);
+            let (d, e) = (sdf.eval(p), exact(p));
+            //outside of the octahedron the plane distance is exact only where the closest point lies on a face, not an edge or a corner
+            let q = p - Vector3::new(p.x.signum(), p.y.signum(), p.z.signum()) * (e / 3.0f32.sqrt());
+            let on_face = q.x * p.x >= 0.0 && q.y * p.y >= 0.0 && q.z * p.z >= 0.0;
+            if e.abs() < 0.75 && (e <= 0.0 || on_face) {max_err = Real::max(max_err, (d - e).abs());}
+            if e.abs() > 0.1 && (d < 0.0) != (e < 0.0) {sign_errors += 1;}
+        }
+
+        println!("{:?}: max error near the surface {}, sign errors {}", test, max_err, sign_errors);
+        assert_eq!(sign_errors, 0);
+        //grid values are exact distances, interpolating them is off by at most half a cell diagonal
+        assert!(max_err < a * 3.0f32.sqrt() / 2.0, "{:?}: max error {} is above half a cell diagonal", test, max_err);
+    }
+
+    //the cell lookups of `MeshSdf::new` must give the same answers as the queries over every triangle, also outside of the mesh bounds
+    let (rings, segments) = (12, 24);
+    let point = |i : usize, j : usize| {
+        let (theta, phi) = (std::f32::consts::PI * i as f32 / rings as f32, 2.0 * std::f32::consts::PI * j as f32 / segments as f32);
+        Vector3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin())
+    };
+    let mut sphere = Vec::new();
+    for i in 0..rings{
+        for j in 0..segments{
+            let (p00, p10, p11, p01) = (point(i, j), point(i + 1, j), point(i + 1, j + 1), point(i, j + 1));
+            if i != rings - 1 {sphere.push(Triangle3{p1 : p00, p2 : p10, p3 : p11});}
+            if i != 0 {sphere.push(Triangle3{p1 : p00, p2 : p11, p3 : p01});}
+        }
+    }
+
+    let accel = TriangleGrid::new(&sphere);
+    let dir = ray_direction();
+    for i in 0..1000{
+        let p = Vector3::new((i % 10) as f32, ((i / 10) % 10) as f32, (i / 100) as f32) * 0.43 - Vector3::new(1.97, 1.91, 2.03);
+        assert_eq!(accel.distance(&p), distance_point3_mesh(&p, &sphere), "distance at {:?}", p);
+        let inside = accel.ray_parity(&p, &dir);
+        assert_eq!(inside, point3_inside_mesh(&p, &sphere, InsideTest::RayParity), "ray parity at {:?}", p);
+        if (p.norm() - 1.0).abs() > 0.05 {assert_eq!(inside, p.norm() < 1.0, "ray parity at {:?}", p);}
+    }
+    println!("{} triangles in {:?} cells agree with the brute force queries", sphere.len(), accel.dims);
+}
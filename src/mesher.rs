@@ -0,0 +1,313 @@
+use na::*;
+use math::*;
+use density::*;
+use dcm::*;
+use mesh::*;
+use dc;
+use dcm;
+use uniform_manifold_dc;
+use cubic;
+use marching_cubes;
+use cms;
+use adaptive_manifold_dc;
+use adaptive_dc;
+use qef::CollapseSettings;
+use time::precise_time_ns;
+
+//common entry point of the meshing algorithms, so they can be switched with one setting and compared on the same input
+//every mesher takes a material density (plain densities can be wrapped with `MatNode` or `dcm::with_material`)
+//and returns the mesh in world coordinates
+
+//cube of `size`^3 cells with edge `a`, `offset` - min corner
+#[derive(Clone, Copy, Debug)]
+pub struct MeshRegion{
+    pub offset : Vector3<f32>,
+    pub a : f32,
+    pub size : usize,
+}
+
+impl MeshRegion{
+    pub fn extent(&self) -> f32{
+        self.a * self.size as f32 / 2.0
+    }
+
+    pub fn center(&self) -> Vector3<f32>{
+        let e = self.extent();
+        self.offset + Vector3::new(e, e, e)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MesherSettings{
+    pub accuracy : usize, //iterations spent on each edge intersection and feature point
+    pub debug : bool, //return debug geometry if the mesher has any
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MeshStats{
+    pub vertices : usize,
+    pub triangles : usize,
+    pub memory : usize, //bytes, see `Mesh::memory_size`
+    pub time_ns : u64,
+}
+
+pub struct MeshOutput{
+    pub mesh : Mesh,
+    pub debug : Option<DebugGeometry>,
+    pub stats : MeshStats,
+}
+
+pub trait Mesher : Send + Sync{
+    fn name(&self) -> &'static str;
+    fn mesh(&self, f : &DensityMat3, region : MeshRegion, settings : &MesherSettings) -> MeshOutput;
+}
+
+fn output(mesh : Mesh, debug : Option<DebugGeometry>, start : u64) -> MeshOutput{
+    let stats = MeshStats{vertices : mesh.vertex_count(), triangles : mesh.triangle_count(), memory : mesh.memory_size(), time_ns : precise_time_ns() - start};
+    MeshOutput{mesh, debug, stats}
+}
+
+//`f` moved by -`offset`, both as a density and as a material density
+//`dc` and `dcm` grids start at the origin, so the region is moved there through this
+pub struct Shifted<'f>{
+    pub f : &'f DensityMat3,
+    pub offset : Vector3<f32>,
+}
+
+impl<'f> Density3 for Shifted<'f>{
+    fn density(&self, p : Vector3<f32>) -> f32{
+        self.f.material_point(p + self.offset).density
+    }
+
+    fn density_batch(&self, points : &[Vector3<f32>], out : &mut [f32]){
+        let mut material_points = vec![MaterialPoint{density : 0.0, material : 0};points.len()];
+        self.material_point_batch(points, &mut material_points);
+        for (d, m) in out.iter_mut().zip(material_points.iter()){
+            *d = m.density;
+        }
+    }
+
+    fn gradient(&self, p : Vector3<f32>) -> Option<Vector3<f32>>{
+        self.f.gradient(p + self.offset)
+    }
+
+    fn bounds(&self, min : Vector3<f32>, max : Vector3<f32>) -> Option<(f32, f32)>{
+        self.f.bounds(min + self.offset, max + self.offset)
+    }
+}
+
+impl<'f> DensityMat3 for Shifted<'f>{
+    fn material_point(&self, p : Vector3<f32>) -> MaterialPoint<f32>{
+        self.f.material_point(p + self.offset)
+    }
+
+    fn material_point_batch(&self, points : &[Vector3<f32>], out : &mut [MaterialPoint<f32>]){
+        if self.offset == Vector3::new(0.0, 0.0, 0.0){
+            return self.f.material_point_batch(points, out);
+        }
+        let moved : Vec<Vector3<f32>> = points.iter().map(|p| p + self.offset).collect();
+        self.f.material_point_batch(&moved, out);
+    }
+
+    fn gradient(&self, p : Vector3<f32>) -> Option<Vector3<f32>>{
+        self.f.gradient(p + self.offset)
+    }
+
+    fn bounds(&self, min : Vector3<f32>, max : Vector3<f32>) -> Option<(f32, f32)>{
+        self.f.bounds(min + self.offset, max + self.offset)
+    }
+}
+
+//uniform dual contouring, `dc::make_contour`
+pub struct DcMesher;
+
+impl Mesher for DcMesher{
+    fn name(&self) -> &'static str {"dc"}
+
+    fn mesh(&self, f : &DensityMat3, region : MeshRegion, settings : &MesherSettings) -> MeshOutput{
+        let start = precise_time_ns();
+        let shifted = Shifted{f, offset : region.offset};
+        let mut grid = dc::VoxelGrid3::new(region.a, region.size, region.size, region.size);
+        dc::fill_in_grid(&mut grid, &shifted, Vector3::new(0.0, 0.0, 0.0));
+        let mut mesh = dc::make_contour(&grid, &shifted, settings.accuracy).mesh;
+        mesh.translate(region.offset);
+        output(mesh, None, start)
+    }
+}
+
+//uniform dual contouring over material changes, `dcm::make_contour`
+pub struct DcmMesher;
+
+impl Mesher for DcmMesher{
+    fn name(&self) -> &'static str {"dcm"}
+
+    fn mesh(&self, f : &DensityMat3, region : MeshRegion, settings : &MesherSettings) -> MeshOutput{
+        let start = precise_time_ns();
+        let shifted = Shifted{f, offset : region.offset};
+        let mut grid = VoxelMaterialGrid3::new(region.a, region.size, region.size, region.size);
+        dcm::fill_in_grid(&mut grid, &shifted, Vector3::new(0.0, 0.0, 0.0));
+        let mut mesh = dcm::make_contour(&grid, &shifted, settings.accuracy).mesh;
+        mesh.translate(region.offset);
+        output(mesh, None, start)
+    }
+}
+
+//uniform manifold dual contouring, `uniform_manifold_dc::construct_grid`
+pub struct UmdcMesher;
+
+impl Mesher for UmdcMesher{
+    fn name(&self) -> &'static str {"umdc"}
+
+    fn mesh(&self, f : &DensityMat3, region : MeshRegion, settings : &MesherSettings) -> MeshOutput{
+        let start = precise_time_ns();
+        let density = Shifted{f, offset : Vector3::new(0.0, 0.0, 0.0)}; //just the density of `f`
+        let (_, mesh, debug) = uniform_manifold_dc::construct_grid(&density, region.offset, region.a, region.size, settings.accuracy, settings.debug);
+        output(mesh, debug, start)
+    }
+}
+
+//marching cubes over the sampled grid, `marching_cubes::make_mesh`
+pub struct MarchingCubesMesher;
+
+impl Mesher for MarchingCubesMesher{
+    fn name(&self) -> &'static str {"mc"}
+
+    fn mesh(&self, f : &DensityMat3, region : MeshRegion, _settings : &MesherSettings) -> MeshOutput{
+        let start = precise_time_ns();
+        let shifted = Shifted{f, offset : region.offset};
+        let mut grid = dc::VoxelGrid3::new(region.a, region.size, region.size, region.size);
+        dc::fill_in_grid(&mut grid, &shifted, Vector3::new(0.0, 0.0, 0.0));
+        let mut mesh = marching_cubes::make_mesh(&grid);
+        mesh.translate(region.offset);
+        output(mesh, None, start)
+    }
+}
+
+//cubical marching squares on an adaptive octree, `cms::make_mesh`
+//`region.size` must be a power of two
+pub struct CmsMesher;
+
+impl Mesher for CmsMesher{
+    fn name(&self) -> &'static str {"cms"}
+
+    fn mesh(&self, f : &DensityMat3, region : MeshRegion, settings : &MesherSettings) -> MeshOutput{
+        let start = precise_time_ns();
+        let density = Shifted{f, offset : Vector3::new(0.0, 0.0, 0.0)}; //just the density of `f`
+        let (mesh, debug) = cms::make_mesh(&density, region.offset, region.a, region.size, &cms::CmsSettings::new(settings.accuracy), settings.debug);
+        output(mesh, debug, start)
+    }
+}
+
+//adaptive manifold dual contouring, `adaptive_manifold_dc::make_mesh`
+//`region.size` must be a power of two
+pub struct AmdcMesher;
+
+impl Mesher for AmdcMesher{
+    fn name(&self) -> &'static str {"amdc"}
+
+    fn mesh(&self, f : &DensityMat3, region : MeshRegion, settings : &MesherSettings) -> MeshOutput{
+        let start = precise_time_ns();
+        let density = Shifted{f, offset : Vector3::new(0.0, 0.0, 0.0)}; //just the density of `f`
+        let (mesh, debug) = adaptive_manifold_dc::make_mesh(&density, region.offset, region.a, region.size, &CollapseSettings::new(settings.accuracy), settings.debug);
+        output(mesh, debug, start)
+    }
+}
+
+//octree dual contouring with qef simplification, `adaptive_dc::make_mesh`
+//`region.size` must be a power of two
+pub struct AdcMesher;
+
+impl Mesher for AdcMesher{
+    fn name(&self) -> &'static str {"adc"}
+
+    fn mesh(&self, f : &DensityMat3, region : MeshRegion, settings : &MesherSettings) -> MeshOutput{
+        let start = precise_time_ns();
+        let density = Shifted{f, offset : Vector3::new(0.0, 0.0, 0.0)}; //just the density of `f`
+        let (mesh, debug) = adaptive_dc::make_mesh(&density, region.offset, region.a, region.size, &CollapseSettings::new(settings.accuracy), settings.debug);
+        output(mesh, debug, start)
+    }
+}
+
+//blocky leaves of the octree built by the C library, `cubic::mesh_cubic_octree`
+//`region.size` must be a power of two
+pub struct CubicMesher;
+
+impl Mesher for CubicMesher{
+    fn name(&self) -> &'static str {"cubic"}
+
+    fn mesh(&self, f : &DensityMat3, region : MeshRegion, _settings : &MesherSettings) -> MeshOutput{
+        let start = precise_time_ns();
+        //cells the surface may pass through: the density of a distance field changes by at most the half diagonal
+        let threshold = region.a * 3.0f32.sqrt() / 2.0;
+        let mesh = unsafe{cubic::mesh_cubic_octree(f, region.center(), region.extent(), region.size, threshold)};
+        output(mesh, None, start)
+    }
+}
+
+//the setting that picks a mesher
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MesherKind{
+    Dc,
+    Dcm,
+    Umdc,
+    Cubic,
+    MarchingCubes,
+    Cms,
+    Amdc,
+    Adc,
+}
+
+impl MesherKind{
+    pub fn all() -> [MesherKind;8]{
+        [MesherKind::Dc, MesherKind::Dcm, MesherKind::Umdc, MesherKind::Cubic, MesherKind::MarchingCubes, MesherKind::Cms, MesherKind::Amdc, MesherKind::Adc]
+    }
+
+    pub fn from_name(name : &str) -> Option<MesherKind>{
+        MesherKind::all().iter().cloned().find(|kind| kind.mesher().name() == name)
+    }
+
+    //the octree meshers split the grid in halves down to single cells
+    pub fn needs_power_of_two(self) -> bool{
+        match self{
+            MesherKind::Cubic | MesherKind::Cms | MesherKind::Amdc | MesherKind::Adc => true,
+            _ => false,
+        }
+    }
+
+    pub fn mesher(self) -> Box<Mesher>{
+        match self{
+            MesherKind::Dc => Box::new(DcMesher),
+            MesherKind::Dcm => Box::new(DcmMesher),
+            MesherKind::Umdc => Box::new(UmdcMesher),
+            MesherKind::Cubic => Box::new(CubicMesher),
+            MesherKind::MarchingCubes => Box::new(MarchingCubesMesher),
+            MesherKind::Cms => Box::new(CmsMesher),
+            MesherKind::Amdc => Box::new(AmdcMesher),
+            MesherKind::Adc => Box::new(AdcMesher),
+        }
+    }
+}
+
+//meshes the same shape with every mesher and prints their statistics side by side
+pub fn test_meshers(){
+    let shape = MatNode{node : DenNode::sdf_sphere(Sphere{center : Vector3::new(2.0, 2.0, 2.0), rad : 1.3}).smooth_union(DenNode::sdf_aabb(Vector3::new(2.5, 1.5, 2.0), Vector3::new(1.0, 0.4, 1.2)), 0.3, BlendKind::Polynomial), material : 1};
+    let region = MeshRegion{offset : Vector3::new(0.0, 0.0, 0.0), a : 0.125, size : 32};
+    let settings = MesherSettings{accuracy : 8, debug : false};
+
+    println!("{:>6} {:>9} {:>9} {:>9} {:>9}", "mesher", "vertices", "triangles", "KiB", "ms");
+    for kind in MesherKind::all().iter(){
+        let mesher = kind.mesher();
+        assert_eq!(MesherKind::from_name(mesher.name()), Some(*kind));
+
+        let out = mesher.mesh(&shape, region, &settings);
+        let stats = out.stats;
+        println!("{:>6} {:>9} {:>9} {:>9} {:>9.2}", mesher.name(), stats.vertices, stats.triangles, stats.memory / 1024, stats.time_ns as f64 / 1e6);
+
+        assert!(stats.triangles > 0, "{} produced no triangles", mesher.name());
+        //marching cubes vertices are interpolated on grid edges, close to the surface even at the cut off box edges,
+        //dual vertices stay within a cell of it
+        let tolerance = if *kind == MesherKind::MarchingCubes {region.a / 4.0} else {2.0 * region.a};
+        let max_err = out.mesh.vertices.iter().map(|v| shape.material_point(*v).density.abs()).fold(0.0, f32::max);
+        assert!(max_err <= tolerance, "{}: vertex {} away from the surface", mesher.name(), max_err);
+    }
+}
@@ -0,0 +1,137 @@
+use na::*;
+use math::*;
+
+//qef of hermite planes in accumulated form, so the qefs of neighbouring cells can be summed up an octree without keeping the planes
+//the sums are kept in f64: the error is a difference of large terms
+#[derive(Clone, Copy, Debug)]
+pub struct Qef{
+    pub ata : Matrix3<f64>,
+    pub atb : Vector3<f64>,
+    pub btb : f64,
+    pub point_accum : Vector4<f64>, //sum of the points, their count in `w`
+}
+
+//eigenvalues of `ata` below this part of the largest one are treated as zero, the minimizer stays at the mass point along their eigenvectors
+const TRUNCATION : f64 = 0.02;
+
+impl Qef{
+    pub fn new() -> Qef{
+        Qef{ata : Matrix3::zeros(), atb : Vector3::zeros(), btb : 0.0, point_accum : Vector4::zeros()}
+    }
+
+    pub fn add(&mut self, plane : &Plane<f32>){
+        let n = Vector3::new(plane.normal.x as f64, plane.normal.y as f64, plane.normal.z as f64);
+        let p = Vector3::new(plane.point.x as f64, plane.point.y as f64, plane.point.z as f64);
+        let b = n.dot(&p);
+        self.ata += n * n.transpose();
+        self.atb += n * b;
+        self.btb += b * b;
+        self.point_accum += Vector4::new(p.x, p.y, p.z, 1.0);
+    }
+
+    pub fn merge(&mut self, other : &Qef){
+        self.ata += other.ata;
+        self.atb += other.atb;
+        self.btb += other.btb;
+        self.point_accum += other.point_accum;
+    }
+
+    pub fn mass_point(&self) -> Vector3<f32>{
+        let w = self.point_accum.w;
+        Vector3::new((self.point_accum.x / w) as f32, (self.point_accum.y / w) as f32, (self.point_accum.z / w) as f32)
+    }
+
+    //sum of the squared distances from `point` to the planes
+    pub fn error(&self, point : &Vector3<f32>) -> f32{
+        let x = Vector3::new(point.x as f64, point.y as f64, point.z as f64);
+        (x.dot(&(self.ata * x)) - 2.0 * x.dot(&self.atb) + self.btb).max(0.0) as f32
+    }
+
+    //minimizer closest to the mass point (truncated pseudo inverse) and its error
+    pub fn solve(&self) -> (Vector3<f32>, f32){
+        let w = self.point_accum.w;
+        let mass = Vector3::new(self.point_accum.x / w, self.point_accum.y / w, self.point_accum.z / w);
+        let (values, vectors) = symmetric_eigen(self.ata);
+        let max = values.iter().fold(0.0, |max : f64, v| max.max(v.abs()));
+
+        let rhs = self.atb - self.ata * mass;
+        let mut x = mass;
+        for i in 0..3{
+            if values[i].abs() > max * TRUNCATION{
+                let v = Vector3::new(vectors[(0, i)], vectors[(1, i)], vectors[(2, i)]);
+                x += v * (v.dot(&rhs) / values[i]);
+            }
+        }
+
+        let point = Vector3::new(x.x as f32, x.y as f32, x.z as f32);
+        (point, self.error(&point))
+    }
+}
+
+//eigenvalues and eigenvectors (columns) of a symmetric matrix by jacobi rotations
+fn symmetric_eigen(m : Matrix3<f64>) -> (Vector3<f64>, Matrix3<f64>){
+    let mut a = m;
+    let mut vectors = Matrix3::identity();
+    for _ in 0..8{
+        for &(p, q) in [(0, 1), (0, 2), (1, 2)].iter(){
+            if a[(p, q)].abs() < 1e-30 {continue;}
+            let theta = (a[(q, q)] - a[(p, p)]) / (2.0 * a[(p, q)]);
+            let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+            let c = 1.0 / (t * t + 1.0).sqrt();
+            let mut rotation = Matrix3::identity();
+            rotation[(p, p)] = c;
+            rotation[(q, q)] = c;
+            rotation[(p, q)] = t * c;
+            rotation[(q, p)] = -t * c;
+            a = rotation.transpose() * a * rotation;
+            vectors = vectors * rotation;
+        }
+    }
+    (Vector3::new(a[(0, 0)], a[(1, 1)], a[(2, 2)]), vectors)
+}
+
+//settings of the octree meshers that collapse nodes while their summed qef stays small (`adaptive_dc`, `adaptive_manifold_dc`)
+#[derive(Clone, Copy, Debug)]
+pub struct CollapseSettings{
+    pub accuracy : usize, //iterations spent on each edge intersection
+    pub error : f32, //nodes collapse while their qef error stays below `error` * a^2
+}
+
+impl CollapseSettings{
+    pub fn new(accuracy : usize) -> CollapseSettings{
+        CollapseSettings{accuracy, error : 0.05}
+    }
+}
+
+//planes through a box corner, an edge and a single plane: the corner, the point of the edge and the point of the plane nearest the mass point
+pub fn test_qef(){
+    let corner = Vector3::new(1.0, 2.0, 3.0);
+    let axes = [Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, 0.0, 1.0)];
+    for count in 1..4{
+        let mut qef = Qef::new();
+        for i in 0..count{
+            //two points on each plane, around the corner
+            qef.add(&Plane{point : corner + axes[(i + 1) % 3] * 0.3, normal : axes[i]});
+            qef.add(&Plane{point : corner + axes[(i + 2) % 3] * 0.5, normal : axes[i]});
+        }
+        let (point, error) = qef.solve();
+        let mass = qef.mass_point();
+        let mut expected = mass;
+        for i in 0..count{
+            expected[i] = corner[i];
+        }
+        println!("{} planes: {:?}, error {}", count, point, error);
+        assert!((point - expected).norm() < 1e-4 && error < 1e-6);
+    }
+
+    //merging is the same as adding the planes to one qef
+    let planes = [Plane{point : Vector3::new(0.2, 0.1, 0.0), normal : Vector3::new(0.6, 0.8, 0.0)}, Plane{point : Vector3::new(0.0, 0.4, 0.3), normal : Vector3::new(0.0, 0.0, 1.0)}];
+    let (mut a, mut b, mut both) = (Qef::new(), Qef::new(), Qef::new());
+    a.add(&planes[0]);
+    b.add(&planes[1]);
+    both.add(&planes[0]);
+    both.add(&planes[1]);
+    a.merge(&b);
+    let p = Vector3::new(0.7, -0.2, 1.1);
+    assert!((a.error(&p) - both.error(&p)).abs() < 1e-5);
+}
@@ -2,6 +2,7 @@
 use std::vec::*;
 use graphics::*;
 use math::*;
+use mesh::*;
 
 use na::{Vector3};
 
@@ -235,6 +236,18 @@ pub fn add_triangle_color_normal(dat: &mut RendererVertFragDef, tr: &Triangle3<f
     dat.vertex_count += 3;
 }
 
+//shared vertices of the mesh with their own normals, `color` maps a material id to the vertex color
+pub fn add_mesh_color_normal(dat : &mut RendererVertFragDef, mesh : &Mesh, color : &Fn(u32) -> Vector3<f32>){
+    for i in 0..mesh.vertex_count(){
+        add_vector_to_pool(dat, mesh.vertices[i]);
+        add_vector_to_pool(dat, color(mesh.materials[i]));
+        add_vector_to_pool(dat, mesh.normals[i]);
+    }
+
+    for i in mesh.indices.iter() {dat.index_pool.push(i.clone() + dat.vertex_count);}
+    dat.vertex_count += mesh.vertex_count() as u32;
+}
+
 fn add_vector_to_pool(dat : &mut RendererVertFragDef, vec : Vector3<f32>){
     for i in vec.iter(){dat.vertex_pool.push(i.clone());}
 }
@@ -251,6 +264,12 @@ pub fn add_line3_color(dat : &mut RendererVertFragDef, line : Line3<f32>, color
     dat.vertex_count += 2;
 }
 
+pub fn add_debug_geometry_color(dat : &mut RendererVertFragDef, debug : &DebugGeometry){
+    for (line, color) in debug.lines.iter().zip(debug.colors.iter()){
+        add_line3_color(dat, *line, *color);
+    }
+}
+
 pub fn add_square3_bounds_color(dat : &mut RendererVertFragDef, cube : Square3<f32>, color : Vector3<f32>){
     add_vector_to_pool(dat, Vector3::new(cube.center.x - cube.extent, cube.center.y - cube.extent, cube.center.z - cube.extent));
     add_vector_to_pool(dat, color);
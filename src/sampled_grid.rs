@@ -0,0 +1,242 @@
+use std;
+use na::*;
+use math::*;
+use density::*;
+use dc::VoxelGrid3;
+use dcm::*;
+
+//stored grids as density functions, so a grid (loaded from disk, edited, voxelized from a mesh) can be meshed
+//without the function it was originally sampled from
+//the grid vertex (x, y, z) lies at `offset + (x, y, z) * a`, outside of the grid the value at the closest point of the
+//grid plus the distance to it is returned, which keeps the outside positive and 1-Lipschitz for distance grids
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation{
+    Trilinear, //C0, reproduces the samples, planar faces between them
+    Tricubic,  //Catmull-Rom over the 4x4x4 neighbourhood, C1 and smoother normals, may overshoot near sharp features
+}
+
+//interpolation weights and their derivatives along one axis: up to 4 (index, weight, d weight / d t) triples
+fn axis_weights(local : f32, cells : usize, interpolation : Interpolation) -> ([(usize, f32, f32);4], usize){
+    let max = cells as f32;
+    let clamped = Real::min(Real::max(local, 0.0), max);
+    let i = Real::min(clamped.floor(), max - 1.0) as usize;
+    let t = clamped - i as f32;
+
+    match interpolation{
+        Interpolation::Trilinear => ([(i, 1.0 - t, -1.0), (i + 1, t, 1.0), (0, 0.0, 0.0), (0, 0.0, 0.0)], 2),
+        Interpolation::Tricubic => {
+            let (t2, t3) = (t * t, t * t * t);
+            let w = [(-t3 + 2.0 * t2 - t) / 2.0, (3.0 * t3 - 5.0 * t2 + 2.0) / 2.0, (-3.0 * t3 + 4.0 * t2 + t) / 2.0, (t3 - t2) / 2.0];
+            let dw = [(-3.0 * t2 + 4.0 * t - 1.0) / 2.0, (9.0 * t2 - 10.0 * t) / 2.0, (-9.0 * t2 + 8.0 * t + 1.0) / 2.0, (3.0 * t2 - 2.0 * t) / 2.0];
+            let index = |k : usize| std::cmp::min(std::cmp::max(i as isize + k as isize - 1, 0), cells as isize) as usize; //border samples are repeated
+            ([(index(0), w[0], dw[0]), (index(1), w[1], dw[1]), (index(2), w[2], dw[2]), (index(3), w[3], dw[3])], 4)
+        },
+    }
+}
+
+//value and gradient of the interpolated samples, `get` reads the vertex (x, y, z)
+fn interpolate<F : Fn(usize, usize, usize) -> f32>(get : F, local : Vector3<f32>, a : f32, cells : [usize;3], interpolation : Interpolation) -> (f32, Vector3<f32>){
+    let (wx, nx) = axis_weights(local.x, cells[0], interpolation);
+    let (wy, ny) = axis_weights(local.y, cells[1], interpolation);
+    let (wz, nz) = axis_weights(local.z, cells[2], interpolation);
+
+    let mut value = 0.0;
+    let mut grad = Vector3::new(0.0, 0.0, 0.0);
+    for &(z, w_z, dw_z) in &wz[..nz]{
+        for &(y, w_y, dw_y) in &wy[..ny]{
+            for &(x, w_x, dw_x) in &wx[..nx]{
+                let v = get(x, y, z);
+                value += w_x * w_y * w_z * v;
+                grad += Vector3::new(dw_x * w_y * w_z, w_x * dw_y * w_z, w_x * w_y * dw_z) * v;
+            }
+        }
+    }
+
+    (value, grad / a)
+}
+
+//distance from `local` (in cells) to the grid box, in world units
+fn outside_distance(local : Vector3<f32>, a : f32, cells : [usize;3]) -> Vector3<f32>{
+    let mut outside = Vector3::new(0.0, 0.0, 0.0);
+    for k in 0..3{
+        outside[k] = (local[k] - Real::min(Real::max(local[k], 0.0), cells[k] as f32)) * a;
+    }
+
+    outside
+}
+
+pub struct SampledGrid{
+    pub grid : VoxelGrid3<f32>,
+    pub offset : Vector3<f32>,
+    pub interpolation : Interpolation,
+}
+
+impl SampledGrid{
+    //`grid` must hold densities at every vertex, fill it with `dc::fill_in_grid_exact` (culled vertices of `dc::fill_in_grid` hold bounds)
+    pub fn new(grid : VoxelGrid3<f32>, offset : Vector3<f32>, interpolation : Interpolation) -> SampledGrid{
+        SampledGrid{grid, offset, interpolation}
+    }
+
+    fn cells(&self) -> [usize;3]{
+        [self.grid.size_x, self.grid.size_y, self.grid.size_z]
+    }
+
+    pub fn eval_grad(&self, p : Vector3<f32>) -> (f32, Vector3<f32>){
+        let g = &self.grid;
+        let local = (p - self.offset) / g.a;
+        let (value, grad) = interpolate(|x, y, z| g.get(x, y, z), local, g.a, self.cells(), self.interpolation);
+
+        let outside = outside_distance(local, g.a, self.cells());
+        let dist = outside.norm();
+        if dist > 0.0 {(value + dist, grad + outside / dist)} else {(value, grad)}
+    }
+
+    pub fn eval(&self, p : Vector3<f32>) -> f32{
+        self.eval_grad(p).0
+    }
+
+    pub fn to_den_fn(self) -> DenFn3<f32>{
+        box move |p| {self.eval(p)}
+    }
+}
+
+impl Density3 for SampledGrid{
+    fn density(&self, p : Vector3<f32>) -> f32{
+        self.eval(p)
+    }
+
+    fn gradient(&self, p : Vector3<f32>) -> Option<Vector3<f32>>{
+        Some(self.eval_grad(p).1)
+    }
+}
+
+//material grid with interpolated densities, the material of a solid point is the one of the nearest solid vertex of its cell
+pub struct SampledMatGrid{
+    pub grid : VoxelMaterialGrid3<f32>,
+    pub offset : Vector3<f32>,
+    pub interpolation : Interpolation,
+}
+
+impl SampledMatGrid{
+    pub fn new(grid : VoxelMaterialGrid3<f32>, offset : Vector3<f32>, interpolation : Interpolation) -> SampledMatGrid{
+        SampledMatGrid{grid, offset, interpolation}
+    }
+
+    fn cells(&self) -> [usize;3]{
+        [self.grid.size_x, self.grid.size_y, self.grid.size_z]
+    }
+
+    pub fn eval_grad(&self, p : Vector3<f32>) -> (f32, Vector3<f32>){
+        let g = &self.grid;
+        let local = (p - self.offset) / g.a;
+        let (value, grad) = interpolate(|x, y, z| g.get(x, y, z), local, g.a, self.cells(), self.interpolation);
+
+        let outside = outside_distance(local, g.a, self.cells());
+        let dist = outside.norm();
+        if dist > 0.0 {(value + dist, grad + outside / dist)} else {(value, grad)}
+    }
+
+    //material of the closest vertex of the cell containing `p` that is not air, 0 if all of them are
+    pub fn nearest_material(&self, p : Vector3<f32>) -> u32{
+        let g = &self.grid;
+        let local = (p - self.offset) / g.a;
+        let cells = self.cells();
+
+        let mut best = (std::f32::MAX, 0);
+        for corner in 0..8{
+            let mut index = [0;3];
+            let mut d2 = 0.0;
+            for k in 0..3{
+                let clamped = Real::min(Real::max(local[k], 0.0), cells[k] as f32);
+                let i = Real::min(clamped.floor(), cells[k] as f32 - 1.0) as usize + ((corner >> k) & 1);
+                d2 += (clamped - i as f32) * (clamped - i as f32);
+                index[k] = i;
+            }
+
+            let material = g.get_material(index[0], index[1], index[2]);
+            if material != 0 && d2 < best.0 {best = (d2, material);}
+        }
+
+        best.1
+    }
+
+    pub fn to_den_mat_fn(self) -> DenMatFn3<f32>{
+        box move |p| {self.material_point(p)}
+    }
+}
+
+impl DensityMat3 for SampledMatGrid{
+    fn material_point(&self, p : Vector3<f32>) -> MaterialPoint<f32>{
+        let density = self.eval_grad(p).0;
+        MaterialPoint{density, material : if density <= 0.0 {self.nearest_material(p)} else {0}}
+    }
+
+    fn gradient(&self, p : Vector3<f32>) -> Option<Vector3<f32>>{
+        Some(self.eval_grad(p).1)
+    }
+}
+
+//samples a sphere, rebuilds it from the grid alone and compares values and normals with the analytic function,
+//then checks the materials of a two material copy of it
+pub fn test_sampled_grid(){
+    let sphere = DenNode::sdf_sphere(Sphere{center : Vector3::new(1.0, 1.1, 0.9), rad : 0.7});
+    let a = 0.125;
+    let size = 16;
+    let mut grid = VoxelGrid3::new(a, size, size, size);
+    for z in 0..size + 1{
+        for y in 0..size + 1{
+            for x in 0..size + 1{
+                let p = grid.get_point(x, y, z);
+                grid.set(x, y, z, sphere.eval(p));
+            }
+        }
+    }
+
+    for &interpolation in [Interpolation::Trilinear, Interpolation::Tricubic].iter(){
+        let sampled = SampledGrid::new(VoxelGrid3{a, size_x : size, size_y : size, size_z : size, grid : grid.grid.clone()}, Vector3::new(0.0, 0.0, 0.0), interpolation);
+        let mut max_err : f32 = 0.0;
+        let mut max_angle : f32 = 0.0;
+        for i in 0..1000{
+            let p = Vector3::new((i % 10) as f32, ((i / 10) % 10) as f32, (i / 100) as f32) * 0.19 + Vector3::new(0.1, 0.13, 0.11);
+            let (d, g) = sampled.eval_grad(p);
+            let (e, eg) = sphere.eval_grad(p);
+            let eg = eg.unwrap(); //sdf spheres have an exact gradient
+            if e.abs() < 0.2{ //the distance has a kink at the center of the sphere that no interpolation reproduces
+                max_err = Real::max(max_err, (d - e).abs());
+                max_angle = Real::max(max_angle, Real::min(g.normalize().dot(&eg), 1.0).acos());
+            }
+        }
+
+        println!("{:?}: max value error {}, max normal error {} rad near the surface", interpolation, max_err, max_angle);
+        assert!(max_err < a / 4.0);
+        //tricubic normals must be clearly smoother than the piecewise planar trilinear ones
+        let angle_bound = match interpolation {Interpolation::Trilinear => 0.2, Interpolation::Tricubic => 0.06};
+        assert!(max_angle < angle_bound, "{:?}: normals off by {} rad", interpolation, max_angle);
+    }
+
+    //the same sphere with material 1 at x < 1 and 2 from x = 1 on (a vertex plane)
+    let mut mat_grid = VoxelMaterialGrid3::new(a, size, size, size);
+    for z in 0..size + 1{
+        for y in 0..size + 1{
+            for x in 0..size + 1{
+                let p = mat_grid.get_point(x, y, z);
+                let density = sphere.eval(p);
+                mat_grid.set(x, y, z, MaterialPoint{density, material : if density > 0.0 {0} else if p.x < 1.0 {1} else {2}});
+            }
+        }
+    }
+    let plain = SampledGrid::new(VoxelGrid3{a, size_x : size, size_y : size, size_z : size, grid : grid.grid.clone()}, Vector3::new(0.0, 0.0, 0.0), Interpolation::Trilinear);
+    let sampled = SampledMatGrid::new(mat_grid, Vector3::new(0.0, 0.0, 0.0), Interpolation::Trilinear);
+    for i in 0..1000{
+        let p = Vector3::new((i % 10) as f32, ((i / 10) % 10) as f32, (i / 100) as f32) * 0.19 + Vector3::new(0.1, 0.13, 0.11);
+        let m = sampled.material_point(p);
+        assert_eq!(m.density, plain.eval(p));
+        //solid points take the material of their side, except in the cells next to the x = 1 plane where either side may be nearest
+        let expected = if m.density > 0.0 {0} else if p.x < 1.0 - a {1} else if p.x > 1.0 + a {2} else {m.material};
+        assert!(m.material == expected && (m.density > 0.0 || m.material != 0), "material {} at {:?}", m.material, p);
+    }
+    //inside the cell just below the plane the nearest vertex decides
+    assert_eq!(sampled.nearest_material(Vector3::new(1.0 - 0.8 * a, 1.1, 0.9)), 1);
+    assert_eq!(sampled.nearest_material(Vector3::new(1.0 - 0.2 * a, 1.1, 0.9)), 2);
+}
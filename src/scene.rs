@@ -0,0 +1,687 @@
+use std;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::collections::HashMap;
+use na::*;
+use math::*;
+use density::*;
+use dcm::*;
+use dcm;
+use mesher::*;
+use terrain::*;
+use noise::{Perlin, Seedable};
+
+//text description of a CSG scene and of how to mesh it, so scenes can be changed without recompiling
+//one statement per line (a call may span several lines), `#` starts a comment:
+//
+//  block_size = 0.125         #grid settings: block_size, chunk_size, accuracy, seed, offset = (x, y, z)
+//  chunk_size = 64            #a power of two for cubic, cms, amdc and adc
+//  mesher = umdc              #dc, dcm, umdc, cubic, mc, cms, amdc or adc, see `mesher::MesherKind`
+//  let ring = torus_z(major_rad = 2, minor_rad = 0.8, offset = (0, 0, -4))
+//  object difference(ring, aabb(center = (0, 3, -4), extent = (1.5, 1.5, 1.5))) material 2
+//
+//expressions use the syntax `DenNode` is printed with, so a printed graph can be pasted into a scene file as is
+//all objects are unioned into the scene density, `material` (1 by default) is used by the material aware mesher
+//`height_noise` without a `seed` parameter takes its seed from the world seed set above it
+
+pub struct Scene{
+    pub block_size : f32,
+    pub chunk_size : usize,
+    pub accuracy : usize,
+    pub seed : WorldSeed,
+    pub offset : Vector3<f32>, //min corner of the meshed grid
+    pub mesher : MesherKind,
+    pub objects : Vec<MatNode>,
+}
+
+impl Scene{
+    pub fn new() -> Scene{
+        Scene{
+            block_size : 0.125,
+            chunk_size : 128,
+            accuracy : 8,
+            seed : WorldSeed(DEFAULT_WORLD_SEED),
+            offset : Vector3::new(0.0, 0.0, 0.0),
+            mesher : MesherKind::Umdc,
+            objects : Vec::new(),
+        }
+    }
+
+    //union of all objects, `None` for an empty scene
+    pub fn density(&self) -> Option<DenNode>{
+        let mut objects = self.objects.iter().map(|o| o.node.clone());
+        let first = objects.next()?;
+        Some(objects.fold(first, |acc, node| acc.union(node)))
+    }
+
+    pub fn to_den_fn(&self) -> DenFn3<f32>{
+        match self.density(){
+            Some(node) => node.to_den_fn(),
+            None => box |_| {1.0},
+        }
+    }
+
+    pub fn to_den_mat_fn(&self) -> DenMatFn3<f32>{
+        let mut result : DenMatFn3<f32> = box |_| {MaterialPoint{density : 1.0, material : 0}};
+        for object in &self.objects{
+            let object = object.clone();
+            result = dcm::union3_mat(result, box move |p| {object.material_point(p)});
+        }
+
+        result
+    }
+}
+
+//the union of the objects like `to_den_mat_fn`, but the graphs are evaluated in batches and bounded
+impl DensityMat3 for Scene{
+    fn material_point(&self, p : Vector3<f32>) -> MaterialPoint<f32>{
+        let mut result = MaterialPoint{density : 1.0, material : 0};
+        for object in &self.objects{
+            let m = object.material_point(p);
+            if m.density < result.density {result = m;}
+        }
+
+        result
+    }
+
+    fn material_point_batch(&self, points : &[Vector3<f32>], out : &mut [MaterialPoint<f32>]){
+        let mut object_out = vec![MaterialPoint{density : 0.0, material : 0};points.len()];
+        for m in out.iter_mut(){
+            *m = MaterialPoint{density : 1.0, material : 0};
+        }
+        for object in &self.objects{
+            object.material_point_batch(points, &mut object_out);
+            for (m, o) in out.iter_mut().zip(object_out.iter()){
+                if o.density < m.density {*m = *o;}
+            }
+        }
+    }
+
+    fn gradient(&self, p : Vector3<f32>) -> Option<Vector3<f32>>{
+        let closest = self.objects.iter().fold(None, |closest : Option<(f32, &MatNode)>, object|{
+            let density = object.node.eval(p);
+            match closest{
+                Some((d, _)) if d <= density => closest,
+                _ => Some((density, object)),
+            }
+        });
+        closest.and_then(|(_, object)| object.gradient(p))
+    }
+
+    fn bounds(&self, min : Vector3<f32>, max : Vector3<f32>) -> Option<(f32, f32)>{
+        Some(self.objects.iter().fold((1.0, 1.0), |(lo, hi), object|{
+            let (object_lo, object_hi) = object.node.bounds(min, max);
+            (lo.min(object_lo), hi.min(object_hi))
+        }))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SceneError{
+    pub line : usize,   //starting from 1
+    pub column : usize, //starting from 1
+    pub message : String,
+}
+
+impl fmt::Display for SceneError{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result{
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+fn error<T>(line : usize, column : usize, message : String) -> Result<T, SceneError>{
+    Err(SceneError{line, column, message})
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token{
+    Ident(String),
+    Number(f32),
+    LParen,
+    RParen,
+    Comma,
+    Assign,
+    Minus,
+    Newline, //only emitted outside of parentheses, terminates a statement
+    Eof,
+}
+
+#[derive(Clone, Debug)]
+struct Lexeme{
+    token : Token,
+    line : usize,
+    column : usize,
+}
+
+fn tokenize(src : &str) -> Result<Vec<Lexeme>, SceneError>{
+    let chars : Vec<char> = src.chars().collect();
+    let mut tokens = Vec::new();
+    let mut depth = 0;
+    let mut i = 0;
+    let mut line = 1;
+    let mut column = 1;
+
+    while i < chars.len(){
+        let c = chars[i];
+        let start = (line, column);
+        let push = |tokens : &mut Vec<Lexeme>, token| tokens.push(Lexeme{token, line : start.0, column : start.1});
+
+        if c == '#'{
+            while i < chars.len() && chars[i] != '\n' {i += 1; column += 1;}
+            continue;
+        }
+
+        if c == '\n'{
+            if depth == 0 {push(&mut tokens, Token::Newline);}
+            i += 1;
+            line += 1;
+            column = 1;
+            continue;
+        }
+
+        if c.is_whitespace(){
+            i += 1;
+            column += 1;
+            continue;
+        }
+
+        if c.is_alphabetic() || c == '_'{
+            let begin = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {i += 1;}
+            column += i - begin;
+            push(&mut tokens, Token::Ident(chars[begin..i].iter().collect()));
+            continue;
+        }
+
+        if c.is_digit(10) || c == '.'{
+            let begin = i;
+            while i < chars.len() && (chars[i].is_digit(10) || chars[i] == '.') {i += 1;}
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E'){
+                i += 1;
+                if i < chars.len() && (chars[i] == '-' || chars[i] == '+') {i += 1;}
+                while i < chars.len() && chars[i].is_digit(10) {i += 1;}
+            }
+            column += i - begin;
+            let text : String = chars[begin..i].iter().collect();
+            match text.parse::<f32>(){
+                Ok(value) => push(&mut tokens, Token::Number(value)),
+                Err(_) => return error(start.0, start.1, format!("invalid number `{}`", text)),
+            }
+            continue;
+        }
+
+        let token = match c{
+            '(' => {depth += 1; Token::LParen},
+            ')' => {
+                if depth == 0 {return error(line, column, String::from("unmatched `)`"));}
+                depth -= 1;
+                Token::RParen
+            },
+            ',' => Token::Comma,
+            '=' => Token::Assign,
+            '-' => Token::Minus,
+            _ => return error(line, column, format!("unexpected character `{}`", c)),
+        };
+        push(&mut tokens, token);
+        i += 1;
+        column += 1;
+    }
+
+    tokens.push(Lexeme{token : Token::Newline, line, column});
+    tokens.push(Lexeme{token : Token::Eof, line, column});
+    Ok(tokens)
+}
+
+enum Value{
+    Number(f32),
+    Vector(Vector3<f32>),
+    Node(DenNode),
+    Name(String),
+}
+
+struct Arg{
+    name : Option<String>,
+    value : Value,
+    line : usize,
+    column : usize,
+    used : bool,
+}
+
+//arguments of a single call, checked and consumed by the builder of the called function
+struct Args<'a>{
+    function : String,
+    line : usize,
+    column : usize,
+    args : Vec<Arg>,
+    bindings : &'a HashMap<String, DenNode>,
+}
+
+impl<'a> Args<'a>{
+    fn named(&mut self, name : &str) -> Option<&mut Arg>{
+        let arg = self.args.iter_mut().find(|a| a.name.as_ref().map_or(false, |n| n == name))?;
+        arg.used = true;
+        Some(arg)
+    }
+
+    fn required(&mut self, name : &str) -> Result<&mut Arg, SceneError>{
+        let (line, column, function) = (self.line, self.column, self.function.clone());
+        match self.named(name){
+            Some(arg) => Ok(arg),
+            None => error(line, column, format!("`{}` requires parameter `{}`", function, name)),
+        }
+    }
+
+    fn number(&mut self, name : &str) -> Result<f32, SceneError>{
+        let arg = self.required(name)?;
+        match arg.value{
+            Value::Number(x) => Ok(x),
+            _ => error(arg.line, arg.column, format!("`{}` must be a number", name)),
+        }
+    }
+
+    fn opt_number(&mut self, name : &str) -> Result<Option<f32>, SceneError>{
+        if self.args.iter().any(|a| a.name.as_ref().map_or(false, |n| n == name)) {self.number(name).map(Some)} else {Ok(None)}
+    }
+
+    fn integer(&mut self, name : &str) -> Result<Option<usize>, SceneError>{
+        let (line, column) = match self.args.iter().find(|a| a.name.as_ref().map_or(false, |n| n == name)){
+            Some(arg) => (arg.line, arg.column),
+            None => return Ok(None),
+        };
+        let x = self.number(name)?;
+        if x < 0.0 || x.fract() != 0.0 {return error(line, column, format!("`{}` must be a non negative integer", name));}
+        Ok(Some(x as usize))
+    }
+
+    fn vector(&mut self, name : &str) -> Result<Vector3<f32>, SceneError>{
+        let arg = self.required(name)?;
+        match arg.value{
+            Value::Vector(v) => Ok(v),
+            _ => error(arg.line, arg.column, format!("`{}` must be a vector `(x, y, z)`", name)),
+        }
+    }
+
+    fn unit_vector(&mut self, name : &str) -> Result<Vector3<f32>, SceneError>{
+        let (line, column) = {let arg = self.required(name)?; (arg.line, arg.column)};
+        let v = self.vector(name)?;
+        if v.norm() == 0.0 {return error(line, column, format!("`{}` must not be zero", name));}
+        Ok(v.normalize())
+    }
+
+    fn plane(&mut self) -> Result<Plane<f32>, SceneError>{
+        Ok(Plane{point : self.vector("point")?, normal : self.unit_vector("normal")?})
+    }
+
+    fn blend_kind(&mut self) -> Result<BlendKind, SceneError>{
+        let arg = match self.named("kind"){
+            Some(arg) => arg,
+            None => return Ok(BlendKind::Polynomial),
+        };
+        match arg.value{
+            Value::Name(ref name) if name == "poly" => Ok(BlendKind::Polynomial),
+            Value::Name(ref name) if name == "exp" => Ok(BlendKind::Exponential),
+            _ => error(arg.line, arg.column, String::from("`kind` must be `poly` or `exp`")),
+        }
+    }
+
+    //`i`-th positional argument, either a call or a name bound with `let`
+    fn node(&mut self, i : usize) -> Result<DenNode, SceneError>{
+        let (line, column, function) = (self.line, self.column, self.function.clone());
+        let bindings = self.bindings;
+        let arg = match self.args.iter_mut().filter(|a| a.name.is_none()).nth(i){
+            Some(arg) => arg,
+            None => return error(line, column, format!("`{}` requires {} node argument{}", function, i + 1, if i > 0 {"s"} else {""})),
+        };
+        arg.used = true;
+        match arg.value{
+            Value::Node(ref node) => Ok(node.clone()),
+            Value::Name(ref name) => match bindings.get(name){
+                Some(node) => Ok(node.clone()),
+                None => error(arg.line, arg.column, format!("unknown name `{}`", name)),
+            },
+            _ => error(arg.line, arg.column, String::from("expected a node")),
+        }
+    }
+
+    //every argument must have been consumed by the builder
+    fn finish(self) -> Result<(), SceneError>{
+        match self.args.iter().find(|a| !a.used){
+            Some(&Arg{name : Some(ref name), line, column, ..}) => error(line, column, format!("`{}` has no parameter `{}`", self.function, name)),
+            Some(&Arg{name : None, line, column, ..}) => error(line, column, format!("too many arguments for `{}`", self.function)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn build_node(args : &mut Args, seed : WorldSeed) -> Result<DenNode, SceneError>{
+    let function = args.function.clone();
+    let node = match function.as_str(){
+        "sphere" => DenNode::sphere(Sphere{center : args.vector("center")?, rad : args.number("rad")?}),
+        "aabb" => DenNode::aabb(args.vector("center")?, args.vector("extent")?),
+        "obb" => DenNode::obb(args.vector("center")?, args.unit_vector("right")?, args.unit_vector("up")?, args.vector("extent")?),
+        "torus_z" => DenNode::torus_z(args.number("major_rad")?, args.number("minor_rad")?, args.vector("offset")?),
+        "torus_y" => DenNode::torus_y(args.number("major_rad")?, args.number("minor_rad")?, args.vector("offset")?),
+        "half_space_pos" => DenNode::half_space_pos(args.plane()?),
+        "half_space_neg" => DenNode::half_space_neg(args.plane()?),
+        "height_noise" => {
+            let perlin = match args.integer("seed")?{
+                Some(s) => Perlin::new().set_seed(s as u32),
+                None => seed.perlin(NoiseSource::Terrain),
+            };
+            let cube = Square3{center : args.vector("center")?, extent : args.number("extent")?};
+            let octaves = args.integer("octaves")?.unwrap_or(4);
+            let persistence = args.opt_number("persistence")?.unwrap_or(0.56);
+            DenNode::HeightNoise{perlin, cube, octaves, persistence}
+        },
+
+        "sdf_sphere" => DenNode::sdf_sphere(Sphere{center : args.vector("center")?, rad : args.number("rad")?}),
+        "sdf_aabb" => DenNode::sdf_aabb(args.vector("center")?, args.vector("extent")?),
+        "sdf_obb" => DenNode::sdf_obb(args.vector("center")?, args.unit_vector("right")?, args.unit_vector("up")?, args.vector("extent")?),
+        "sdf_torus_z" => DenNode::sdf_torus_z(args.number("major_rad")?, args.number("minor_rad")?, args.vector("offset")?),
+        "sdf_torus_y" => DenNode::sdf_torus_y(args.number("major_rad")?, args.number("minor_rad")?, args.vector("offset")?),
+        "capsule" => DenNode::capsule(args.vector("a")?, args.vector("b")?, args.number("rad")?),
+        "capped_cylinder" => DenNode::capped_cylinder(args.vector("a")?, args.vector("b")?, args.number("rad")?),
+        "capped_cone" => DenNode::capped_cone(args.vector("a")?, args.vector("b")?, args.number("rad_a")?, args.number("rad_b")?),
+        "cone" => DenNode::cone(args.vector("base")?, args.vector("apex")?, args.number("rad")?),
+        "rounded_box" => DenNode::rounded_box(args.vector("center")?, args.vector("extent")?, args.number("radius")?),
+        "ellipsoid" => DenNode::ellipsoid(args.vector("center")?, args.vector("radii")?),
+        "hex_prism" => DenNode::hex_prism(args.vector("center")?, args.number("rad")?, args.number("half_height")?),
+        "tri_prism" => DenNode::tri_prism(args.vector("center")?, args.number("rad")?, args.number("half_height")?),
+        "torus" => DenNode::torus(args.vector("center")?, args.unit_vector("axis")?, args.number("major_rad")?, args.number("minor_rad")?),
+
+        "union" => args.node(0)?.union(args.node(1)?),
+        "intersection" => args.node(0)?.intersection(args.node(1)?),
+        "difference" => args.node(0)?.difference(args.node(1)?),
+        "smooth_union" => args.node(0)?.smooth_union(args.node(1)?, args.number("radius")?, args.blend_kind()?),
+        "smooth_intersection" => args.node(0)?.smooth_intersection(args.node(1)?, args.number("radius")?, args.blend_kind()?),
+        "smooth_difference" => args.node(0)?.smooth_difference(args.node(1)?, args.number("radius")?, args.blend_kind()?),
+
+        "translate" => args.node(0)?.translate(args.vector("offset")?),
+        "rotate" => args.node(0)?.rotate_axis_angle(args.unit_vector("axis")?, args.number("angle")?),
+        "scale" => args.node(0)?.scale(args.number("factor")?),
+        "scale_non_uniform" => args.node(0)?.scale_non_uniform(args.vector("factor")?),
+        "mirror" => args.node(0)?.mirror(args.plane()?),
+        "repeat" => args.node(0)?.repeat(args.vector("period")?),
+        "repeat_bounded" => args.node(0)?.repeat_bounded(args.vector("period")?, args.vector("limit")?),
+        "twist" => args.node(0)?.twist(args.number("rate")?),
+        "bend" => args.node(0)?.bend(args.number("rate")?),
+        "elongate" => args.node(0)?.elongate(args.vector("extent")?),
+        "symmetry" => args.node(0)?.symmetry(args.plane()?),
+        "lipschitz_correct" => args.node(0)?.lipschitz_correct(args.number("lipschitz")?),
+
+        _ => return error(args.line, args.column, format!("unknown function `{}`", function)),
+    };
+
+    Ok(node)
+}
+
+struct Parser<'a>{
+    tokens : &'a [Lexeme],
+    pos : usize,
+    bindings : HashMap<String, DenNode>,
+    scene : Scene,
+    chunk_size_at : (usize, usize), //line and column of the last `chunk_size` value, checked against the mesher once all settings are read
+}
+
+impl<'a> Parser<'a>{
+    fn peek(&self) -> &Lexeme{
+        &self.tokens[self.pos]
+    }
+
+    fn next(&mut self) -> Lexeme{
+        let lexeme = self.tokens[self.pos].clone();
+        if lexeme.token != Token::Eof {self.pos += 1;}
+        lexeme
+    }
+
+    fn expect(&mut self, token : Token, what : &str) -> Result<Lexeme, SceneError>{
+        let lexeme = self.next();
+        if lexeme.token == token {Ok(lexeme)} else {error(lexeme.line, lexeme.column, format!("expected {}", what))}
+    }
+
+    fn ident(&mut self, what : &str) -> Result<(String, Lexeme), SceneError>{
+        let lexeme = self.next();
+        match lexeme.token.clone(){
+            Token::Ident(name) => Ok((name, lexeme)),
+            _ => error(lexeme.line, lexeme.column, format!("expected {}", what)),
+        }
+    }
+
+    fn number(&mut self) -> Result<f32, SceneError>{
+        let lexeme = self.next();
+        match lexeme.token{
+            Token::Number(x) => Ok(x),
+            Token::Minus => self.number().map(|x| -x),
+            _ => error(lexeme.line, lexeme.column, String::from("expected a number")),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, SceneError>{
+        let lexeme = self.peek().clone();
+        match lexeme.token{
+            Token::Number(_) | Token::Minus => Ok(Value::Number(self.number()?)),
+            Token::LParen => {
+                self.next();
+                let x = self.number()?;
+                self.expect(Token::Comma, "`,`")?;
+                let y = self.number()?;
+                self.expect(Token::Comma, "`,`")?;
+                let z = self.number()?;
+                self.expect(Token::RParen, "`)` after the third component of a vector")?;
+                Ok(Value::Vector(Vector3::new(x, y, z)))
+            },
+            Token::Ident(name) => {
+                self.next();
+                if self.peek().token == Token::LParen {
+                    Ok(Value::Node(self.call(name, lexeme.line, lexeme.column)?))
+                }else{
+                    Ok(Value::Name(name))
+                }
+            },
+            _ => error(lexeme.line, lexeme.column, String::from("expected a value")),
+        }
+    }
+
+    //`name(arg, ..., param = value, ...)`, the name has already been consumed
+    fn call(&mut self, function : String, line : usize, column : usize) -> Result<DenNode, SceneError>{
+        self.expect(Token::LParen, "`(`")?;
+        let mut args = Vec::new();
+
+        if self.peek().token != Token::RParen{
+            loop{
+                let start = self.peek().clone();
+                let named = match (&start.token, &self.tokens[self.pos + 1].token){
+                    (&Token::Ident(ref name), &Token::Assign) => Some(name.clone()),
+                    _ => None,
+                };
+                if named.is_some() {self.pos += 2;}
+                if let Some(ref name) = named{
+                    if args.iter().any(|a : &Arg| a.name.as_ref() == Some(name)) {
+                        return error(start.line, start.column, format!("parameter `{}` is given twice", name));
+                    }
+                }
+                let value = self.value()?;
+                args.push(Arg{name : named, value, line : start.line, column : start.column, used : false});
+
+                let separator = self.next();
+                match separator.token{
+                    Token::Comma => continue,
+                    Token::RParen => break,
+                    _ => return error(separator.line, separator.column, format!("expected `,` or `)` in the arguments of `{}`", function)),
+                }
+            }
+        }else{
+            self.next();
+        }
+
+        let mut args = Args{function, line, column, args, bindings : &self.bindings};
+        let node = build_node(&mut args, self.scene.seed)?;
+        args.finish()?;
+        Ok(node)
+    }
+
+    fn node(&mut self) -> Result<DenNode, SceneError>{
+        let lexeme = self.peek().clone();
+        match self.value()?{
+            Value::Node(node) => Ok(node),
+            Value::Name(name) => match self.bindings.get(&name){
+                Some(node) => Ok(node.clone()),
+                None => error(lexeme.line, lexeme.column, format!("unknown name `{}`", name)),
+            },
+            _ => error(lexeme.line, lexeme.column, String::from("expected a node")),
+        }
+    }
+
+    fn setting(&mut self, name : String, lexeme : Lexeme) -> Result<(), SceneError>{
+        self.expect(Token::Assign, "`=`")?;
+        let value_at = self.peek().clone();
+        let value = self.value()?;
+        let invalid = |what : &str| error(value_at.line, value_at.column, format!("`{}` must be {}", name, what));
+        let integer = |x : f32| if x > 0.0 && x.fract() == 0.0 {Some(x as usize)} else {None};
+
+        match (name.as_str(), value){
+            ("block_size", Value::Number(x)) => if x > 0.0 {self.scene.block_size = x} else {return invalid("positive")},
+            ("chunk_size", Value::Number(x)) => match integer(x){
+                Some(n) if n >= 2 => {
+                    self.scene.chunk_size = n;
+                    self.chunk_size_at = (value_at.line, value_at.column);
+                },
+                _ => return invalid("an integer of at least 2"),
+            },
+            ("accuracy", Value::Number(x)) => match integer(x) {Some(n) => self.scene.accuracy = n, None => return invalid("a positive integer")},
+            ("seed", Value::Number(x)) => if x >= 0.0 && x.fract() == 0.0 {self.scene.seed = WorldSeed(x as u32)} else {return invalid("a non negative integer")},
+            ("offset", Value::Vector(v)) => self.scene.offset = v,
+            ("mesher", Value::Name(mesher)) => self.scene.mesher = match MesherKind::from_name(&mesher){
+                Some(kind) => kind,
+                None => return invalid(&mesher_names()),
+            },
+            ("block_size", _) | ("chunk_size", _) | ("accuracy", _) | ("seed", _) => return invalid("a number"),
+            ("offset", _) => return invalid("a vector `(x, y, z)`"),
+            ("mesher", _) => return invalid(&mesher_names()),
+            _ => return error(lexeme.line, lexeme.column, format!("unknown setting `{}`", name)),
+        }
+
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), SceneError>{
+        let (keyword, lexeme) = self.ident("a statement")?;
+        match keyword.as_str(){
+            "let" => {
+                let (name, _) = self.ident("a name after `let`")?;
+                self.expect(Token::Assign, "`=`")?;
+                let node = self.node()?;
+                self.bindings.insert(name, node);
+            },
+            "object" => {
+                let node = self.node()?;
+                let mut material = 1;
+                let next = self.peek().token.clone();
+                if let Token::Ident(word) = next{
+                    if word != "material" {return error(self.peek().line, self.peek().column, String::from("expected `material` or the end of the line"));}
+                    self.next();
+                    let at = self.peek().clone();
+                    let x = self.number()?;
+                    if x < 0.0 || x.fract() != 0.0 {return error(at.line, at.column, String::from("material must be a non negative integer"));}
+                    material = x as u32;
+                }
+                self.scene.objects.push(MatNode{node, material});
+            },
+            _ => self.setting(keyword, lexeme)?,
+        }
+
+        let end = self.next();
+        match end.token{
+            Token::Newline | Token::Eof => Ok(()),
+            _ => error(end.line, end.column, String::from("expected the end of the line")),
+        }
+    }
+}
+
+//"one of `dc`, `dcm`, ..." for an unknown mesher
+fn mesher_names() -> String{
+    let names : Vec<String> = MesherKind::all().iter().map(|kind| format!("`{}`", kind.mesher().name())).collect();
+    format!("one of {}", names.join(", "))
+}
+
+pub fn parse_scene(src : &str) -> Result<Scene, SceneError>{
+    let tokens = tokenize(src)?;
+    let mut parser = Parser{tokens : &tokens, pos : 0, bindings : HashMap::new(), scene : Scene::new(), chunk_size_at : (0, 0)};
+
+    loop{
+        let token = parser.peek().token.clone();
+        match token{
+            Token::Eof => break,
+            Token::Newline => {parser.next();},
+            _ => parser.statement()?,
+        }
+    }
+
+    let scene = parser.scene;
+    if scene.mesher.needs_power_of_two() && !scene.chunk_size.is_power_of_two(){
+        let (line, column) = parser.chunk_size_at;
+        return error(line, column, format!("`chunk_size` must be a power of two for the `{}` mesher", scene.mesher.mesher().name()));
+    }
+
+    Ok(scene)
+}
+
+//errors are formatted as `path:line:column: message`
+pub fn load_scene(path : &str) -> Result<Scene, String>{
+    let mut src = String::new();
+    File::open(path).and_then(|mut file| file.read_to_string(&mut src)).map_err(|e| format!("{}: {}", path, e))?;
+    parse_scene(&src).map_err(|e| format!("{}:{}", path, e))
+}
+
+//meshes the scene with its mesher, the mesh is in world coordinates
+pub fn mesh_scene(scene : &Scene) -> MeshOutput{
+    let region = MeshRegion{offset : scene.offset, a : scene.block_size, size : scene.chunk_size};
+    scene.mesher.mesher().mesh(scene, region, &MesherSettings{accuracy : scene.accuracy, debug : false})
+}
+
+//parses the printed form of a graph back and checks that it evaluates to the same values, then checks a few error positions
+pub fn test_scene_parser(){
+    let node = DenNode::torus_z(2.0, 0.8, Vector3::new(0.0, 0.0, -4.0))
+        .smooth_union(DenNode::capsule(Vector3::new(-1.0, 0.0, 0.0), Vector3::new(1.0, 0.5, 0.0), 0.3), 0.2, BlendKind::Exponential)
+        .difference(DenNode::aabb(Vector3::new(0.0, 3.0, -4.0), Vector3::new(1.5, 1.5, 1.5)))
+        .rotate_axis_angle(Vector3::new(0.0, 1.0, 0.0), 0.5)
+        .repeat_bounded(Vector3::new(6.0, 6.0, 6.0), Vector3::new(1.0, 0.0, 1.0));
+
+    let src = format!("# round trip\nblock_size = 0.25\nchunk_size = 32\nmesher = dcm\nlet shape = {}\nobject shape material 3\n", node);
+    let scene = parse_scene(&src).unwrap();
+    assert_eq!(scene.block_size, 0.25);
+    assert_eq!(scene.chunk_size, 32);
+    assert_eq!(scene.mesher, MesherKind::Dcm);
+    assert_eq!(scene.objects[0].material, 3);
+
+    let parsed = scene.density().unwrap();
+    let mut max_diff : f32 = 0.0;
+    for i in 0..1000{
+        let p = Vector3::new((i % 10) as f32 - 5.0, ((i / 10) % 10) as f32 - 5.0, (i / 100) as f32 - 8.0) * 0.97;
+        max_diff = Real::max(max_diff, (parsed.eval(p) - node.eval(p)).abs());
+    }
+    println!("scene round trip: max difference {}", max_diff);
+    assert!(max_diff < 1e-4);
+
+    let errors = [
+        ("object sphere(center = (0, 0, 0))", 1, 8),
+        ("let a = sphere(center = (0, 0, 0), rad = 1)\nobject union(a, b)", 2, 17),
+        ("chunk_size = 1.5", 1, 14),
+        ("object sphere(center = (0, 0), rad = 1)", 1, 29),
+        ("object sphere(center = (0, 0, 0), rad = 1, radius = 2)", 1, 44),
+        ("object box(center = (0, 0, 0))", 1, 8),
+        ("chunk_size = 48\nmesher = cms", 1, 14),
+        ("mesher = adc\nchunk_size = 48", 2, 14),
+    ];
+    for &(src, line, column) in errors.iter(){
+        match parse_scene(src){
+            Err(e) => {
+                println!("{}", e);
+                assert!(e.line == line && e.column == column, "expected an error at {}:{}", line, column);
+            },
+            Ok(_) => panic!("`{}` should not parse", src),
+        }
+    }
+    assert_eq!(parse_scene("chunk_size = 48\nmesher = mc").unwrap().chunk_size, 48);
+}
@@ -0,0 +1,274 @@
+use na::*;
+use noise::{NoiseModule, Perlin, Seedable};
+use math::*;
+use dcm::*;
+use uniform_manifold_dc;
+use std;
+
+//seeded fractal noise for terrain generation
+//every `get*` returns roughly [-1;1], density constructors follow the usual convention: negative - solid, positive - air
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FractalKind{
+    Fbm,    //plain sum of octaves
+    Ridged, //ridged multifractal: sharp crests along the zero set of each octave, later octaves are weighted by earlier ones
+    Billow, //abs of each octave: rounded, puffy hills
+}
+
+//displaces the sample point by a low frequency noise vector before evaluating the fractal
+#[derive(Clone, Copy, Debug)]
+pub struct DomainWarp{
+    pub amplitude : f32, //maximum displacement in world units
+    pub frequency : f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FractalNoise{
+    pub perlin : Perlin,
+    pub kind : FractalKind,
+    pub octaves : usize,
+    pub frequency : f32,  //frequency of the first octave
+    pub lacunarity : f32, //frequency multiplier between octaves
+    pub gain : f32,       //amplitude multiplier between octaves (persistence)
+    pub warp : Option<DomainWarp>,
+}
+
+//arbitrary offsets that decorrelate the warp components from each other and from the fractal itself
+const WARP_OFFSETS : [[f32;3];3] = [[0.0, 0.0, 0.0], [5.2, 1.3, 7.1], [1.7, 9.2, 3.4]];
+
+impl FractalNoise{
+    //4 octaves of fBm with lacunarity 2 and gain 0.5, use struct update syntax to change the rest:
+    //`FractalNoise{kind : FractalKind::Ridged, octaves : 6, ..FractalNoise::new(seed)}`
+    pub fn new(seed : u32) -> FractalNoise{
+        FractalNoise{
+            perlin : Perlin::new().set_seed(seed),
+            kind : FractalKind::Fbm,
+            octaves : 4,
+            frequency : 1.0,
+            lacunarity : 2.0,
+            gain : 0.5,
+            warp : None,
+        }
+    }
+
+    pub fn seed(&self) -> u32{
+        self.perlin.seed()
+    }
+
+    pub fn get2(&self, x : f32, z : f32) -> f32{
+        let (x, z) = match self.warp{
+            Some(warp) => {
+                let o1 = WARP_OFFSETS[1];
+                let o2 = WARP_OFFSETS[2];
+                (x + self.perlin.get([x * warp.frequency + o1[0], z * warp.frequency + o1[2]]) * warp.amplitude,
+                 z + self.perlin.get([x * warp.frequency + o2[0], z * warp.frequency + o2[2]]) * warp.amplitude)
+            },
+            None => (x, z),
+        };
+
+        self.fractal(|frequency| self.perlin.get([x * frequency, z * frequency]))
+    }
+
+    pub fn get3(&self, p : Vector3<f32>) -> f32{
+        let p = match self.warp{
+            Some(warp) => {
+                let q = p * warp.frequency;
+                let mut d = Vector3::new(0.0, 0.0, 0.0);
+                for i in 0..3{
+                    let o = WARP_OFFSETS[i];
+                    d[i] = self.perlin.get([q.x + o[0], q.y + o[1], q.z + o[2]]);
+                }
+                p + d * warp.amplitude
+            },
+            None => p,
+        };
+
+        self.fractal(|frequency| self.perlin.get([p.x * frequency, p.y * frequency, p.z * frequency]))
+    }
+
+    //sums `octave(frequency)` according to `kind`, normalized by the total amplitude
+    fn fractal<F : Fn(f32) -> f32>(&self, octave : F) -> f32{
+        let mut total = 0.0;
+        let mut frequency = self.frequency;
+        let mut amplitude = 1.0;
+        let mut max_value = 0.0;
+        let mut weight = 1.0;
+
+        for _ in 0..self.octaves{
+            let n = octave(frequency);
+            total += match self.kind{
+                FractalKind::Fbm => n,
+                FractalKind::Billow => n.abs() * 2.0 - 1.0,
+                FractalKind::Ridged => {
+                    let signal = (1.0 - n.abs()) * (1.0 - n.abs()) * weight; //0 to 1
+                    weight = Real::min(Real::max(signal * 2.0, 0.0), 1.0);
+                    signal * 2.0 - 1.0
+                },
+            } * amplitude;
+            max_value += amplitude;
+            amplitude *= self.gain;
+            frequency *= self.lacunarity;
+        }
+
+        if max_value > 0.0 {total / max_value} else {0.0}
+    }
+}
+
+
+pub const DEFAULT_WORLD_SEED : u32 = 1337;
+
+//every noise source of a world, each one gets its own seed derived from the world seed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoiseSource{
+    Terrain,
+    Caves,
+    CubicOctree,
+    FastNoise, //C++ side noise used by `cubic::gen_dense_grid`
+}
+
+//one number that fully determines the generated world, print it with bug reports
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WorldSeed(pub u32);
+
+impl WorldSeed{
+    //`V3D_SEED` environment variable if it is set and valid, `DEFAULT_WORLD_SEED` otherwise
+    pub fn from_env() -> WorldSeed{
+        WorldSeed(std::env::var("V3D_SEED").ok().and_then(|s| s.trim().parse().ok()).unwrap_or(DEFAULT_WORLD_SEED))
+    }
+
+    //splitmix32 style mixing so that neighbouring world seeds and sources give unrelated seeds
+    pub fn derive(&self, source : NoiseSource) -> u32{
+        let mut h = self.0.wrapping_add((source as u32 + 1).wrapping_mul(0x9E3779B9));
+        h = (h ^ (h >> 16)).wrapping_mul(0x85EBCA6B);
+        h = (h ^ (h >> 13)).wrapping_mul(0xC2B2AE35);
+        h ^ (h >> 16)
+    }
+
+    pub fn perlin(&self, source : NoiseSource) -> Perlin{
+        Perlin::new().set_seed(self.derive(source))
+    }
+
+    pub fn noise(&self, source : NoiseSource) -> FractalNoise{
+        FractalNoise::new(self.derive(source))
+    }
+}
+
+//64 bit FNV-1a over the bit patterns, unlike `std::hash` it is stable across rust versions and platforms
+pub fn fnv1a_hash(floats : &[f32], ints : &[u32]) -> u64{
+    let mut hash : u64 = 0xcbf29ce484222325;
+    for word in floats.iter().map(|x| x.to_bits()).chain(ints.iter().cloned()){
+        for i in 0..4{
+            hash ^= ((word >> (i * 8)) & 0xFF) as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    hash
+}
+
+//small test world: ridged volumetric terrain with caves carved out of it
+pub fn mk_test_world(seed : WorldSeed, cube : Square3<f32>) -> DenFn3<f32>{
+    let terrain = FractalNoise{kind : FractalKind::Ridged, frequency : 0.5, ..seed.noise(NoiseSource::Terrain)};
+    let caves = FractalNoise{octaves : 2, frequency : 0.7, ..seed.noise(NoiseSource::Caves)};
+    difference3(mk_terrain_volume(terrain, cube, cube.extent / 2.0), mk_caves(caves, 0.08))
+}
+
+//meshes the test world with uniform manifold DC and hashes the resulting triangles
+pub fn world_mesh_hash(seed : WorldSeed) -> (u64, usize){
+    let size = 32;
+    let a = 0.25;
+    let offset = Vector3::new(-4.0, -4.0, -4.0);
+    let world = mk_test_world(seed, Square3{center : offset + Vector3::new(1.0, 1.0, 1.0) * (size as f32 * a / 2.0), extent : size as f32 * a / 2.0 - a});
+
+    let (_, mesh, _) = uniform_manifold_dc::construct_grid(&world, offset, a, size, 8, false);
+    let positions : Vec<f32> = mesh.vertices.iter().flat_map(|v| v.as_slice().to_owned()).collect();
+
+    (fnv1a_hash(&positions, &mesh.indices), mesh.triangle_count())
+}
+
+//the same seed must produce a bit identical mesh, different seeds must not
+//the hash covers the raw bits of noise and libm output, equality between platforms has not been checked
+pub fn test_world_reproducibility(){
+    let seed = WorldSeed(42);
+    let (hash1, triangles) = world_mesh_hash(seed);
+    let (hash2, _) = world_mesh_hash(seed);
+    let (other, _) = world_mesh_hash(WorldSeed(43));
+
+    println!("world seed {} meshed into {} triangles, hash {:016x}", seed.0, triangles, hash1);
+    assert!(triangles > 0, "test world is empty");
+    assert_eq!(hash1, hash2, "world generation is not deterministic");
+    assert!(hash1 != other, "world seed has no effect on generation");
+}
+
+
+//legacy normalisation used by the original heightfield generators: 0 to 1.0, first octave at 1 / 2^(octaves - 1)
+fn legacy_noise(perlin : &Perlin, octaves : usize, persistence : f32) -> FractalNoise{
+    FractalNoise{
+        perlin : *perlin,
+        kind : FractalKind::Fbm,
+        octaves,
+        frequency : 1.0 / 2.0.powi((octaves - 1) as i32),
+        lacunarity : 2.0,
+        gain : persistence,
+        warp : None,
+    }
+}
+
+//0 to 1.0
+pub fn octave_perlin2(perlin : &Perlin, x : f32, z : f32, octaves : usize, persistence : f32) -> f32{
+    (legacy_noise(perlin, octaves, persistence).get2(x, z) + 1.0) / 2.0
+}
+
+//0 to 1.0
+pub fn octave_perlin3(perlin : &Perlin, x : f32, y : f32, z : f32, octaves : usize, persistence : f32) -> f32{
+    (legacy_noise(perlin, octaves, persistence).get3(Vector3::new(x, y, z)) + 1.0) / 2.0
+}
+
+
+//heightfield inside `cube`: the surface rises from the bottom of the cube up to its top as the noise goes from -1 to 1
+//noise is sampled relative to the min corner of the cube, outside of the cube the density is a constant positive value
+pub fn mk_terrain_height(noise : FractalNoise, cube : Square3<f32>) -> DenFn3<f32>{
+    box move |x| {
+        if point3_inside_square3_inclusive(&x, &cube){
+            let h = (noise.get2(x.x - (cube.center.x - cube.extent), x.z - (cube.center.z - cube.extent)) + 1.0) * cube.extent;
+            x.y - (cube.center.y - cube.extent) - h
+        }else{
+            0.01
+        }
+    }
+}
+
+//volumetric terrain inside `cube`: a ground plane at the center of the cube displaced by 3D noise of magnitude `amplitude`
+//unlike a heightfield this produces overhangs, arches and floating islands once `amplitude` is large compared to the first octave's wavelength
+pub fn mk_terrain_volume(noise : FractalNoise, cube : Square3<f32>, amplitude : f32) -> DenFn3<f32>{
+    box move |x| {
+        if point3_inside_square3_inclusive(&x, &cube){
+            x.y - cube.center.y - noise.get3(x - cube.center) * amplitude
+        }else{
+            0.01
+        }
+    }
+}
+
+//negative inside tunnel-like regions around the zero set of the noise, `radius` is in noise units (0 to 1)
+//carve them out of a terrain with `difference3(terrain, caves)`
+pub fn mk_caves(noise : FractalNoise, radius : f32) -> DenFn3<f32>{
+    box move |x| {
+        noise.get3(x).abs() - radius
+    }
+}
+
+//assigns materials to a terrain density by height: `layers` are (top y, material) sorted by top y,
+//points above the last layer get its material, air is material 0
+pub fn mk_terrain_layers_mat(f : DenFn3<f32>, layers : Vec<(f32, u32)>) -> DenMatFn3<f32>{
+    box move |x| {
+        let density = f(x);
+        let material = if density > 0.0 {
+            0
+        }else{
+            layers.iter().find(|&&(top, _)| x.y <= top).or(layers.last()).map_or(0, |&(_, mat)| mat)
+        };
+
+        MaterialPoint{density, material}
+    }
+}
@@ -1,13 +1,15 @@
 use std;
 use na::*;
 use math::*;
-use renderer::*;
+use density::*;
+use mesh::*;
 use alga::general::*;
 use std::rc::Rc;
 use std::cell::RefCell;
 use num::PrimInt;
 use std::collections::HashMap;
 use qef_bindings::*;
+use rayon::prelude::*;
 
 //uniform manifold dual contouring is a modification to dual marching cubes (hermite extension to dual marching cubes)
 
@@ -386,7 +388,7 @@ impl<T : Real + SupersetOf<f32>> HermiteGrid<T>{
 
 //it is assumed that surface is smooth in the area along the line and density at the ends of the line have different signs
 //TODO handle multiple intersections per edge ???
-fn sample_surface_intersection(line : &Line3<f32>, n : usize, f : &DenFn3<f32>) -> Vector3<f32>{
+pub fn sample_surface_intersection<D : Density3>(line : &Line3<f32>, n : usize, f : &D) -> Vector3<f32>{
     let ext = line.end - line.start;
     let norm = ext.norm();
     let dir = ext / norm;
@@ -400,8 +402,8 @@ fn sample_surface_intersection(line : &Line3<f32>, n : usize, f : &DenFn3<f32>)
     for _ in 0..n {
         let point1 = center - dir * cur_ext;
         let point2 = center + dir * cur_ext;
-        let den1 = f(point1).abs();
-        let den2 = f(point2).abs();
+        let den1 = f.density(point1).abs();
+        let den2 = f.density(point2).abs();
 
         if den1 <= den2 {
             center = point1;
@@ -414,10 +416,8 @@ fn sample_surface_intersection(line : &Line3<f32>, n : usize, f : &DenFn3<f32>)
     center
 }
 
-pub fn sample_normal(point : &Vector3<f32>, eps : f32, f : &DenFn3<f32>) -> Vector3<f32>{
-    Vector3::new( f(Vector3::new(point.x + eps, point.y, point.z)) - f(Vector3::new(point.x, point.y, point.z)),
-                  f(Vector3::new(point.x, point.y + eps, point.z)) - f(Vector3::new(point.x, point.y, point.z)),
-                  f(Vector3::new(point.x, point.y, point.z + eps)) - f(Vector3::new(point.x, point.y, point.z)) ).normalize()
+pub fn sample_normal<D : Density3>(point : &Vector3<f32>, eps : f32, f : &D) -> Vector3<f32>{
+    surface_normal(f, point, eps) //exact gradient if `f` provides one, central differences otherwise
 }
 
 fn is_const_sign(a : f32, b : f32) -> bool {
@@ -513,7 +513,7 @@ fn solve_qef_analically_ATA_ATb(planes : &Vec<Plane<f32>>) -> Option<Vector3<f32
     }
 }
 
-fn solve_qef_analically_qr(planes : &Vec<Plane<f32>>, bounds : &Square3<f32>) -> Vector3<f32>{
+pub fn solve_qef_analically_qr(planes : &Vec<Plane<f32>>, bounds : &Square3<f32>) -> Vector3<f32>{
     let mut masspoint = Vector4::zeros();
     let normals : Vec<f32> = planes.iter().flat_map(|x| {
         masspoint += Vector4::new(x.point.x, x.point.y, x.point.z, 1.0);
@@ -626,23 +626,34 @@ fn sample_qef_brute(square : &Square3<f32>, n : usize, planes : &Vec<Plane<f32>>
 
 //constructs grid: calculates hermite data and configuration for each cell
 //TODO generating triangles write in this function would benefit performance (no extra looping through cells)
-pub fn construct_grid<'f>(f : &'f DenFn3<f32>, offset : Vector3<f32>, a : f32, size : usize, accuracy : usize, render_tr_light : &mut RendererVertFragDef, render_debug_lines : &mut RendererVertFragDef) -> HermiteGrid<f32>{
+//with `debug` set, the bounds of cells holding more than one vertex are returned too
+pub fn construct_grid<'f, D : Density3>(f : &'f D, offset : Vector3<f32>, a : f32, size : usize, accuracy : usize, debug : bool) -> (HermiteGrid<f32>, Mesh, Option<DebugGeometry>){
     let corners = corner_points();
     let edge_pairs = edge_pairs();
     let edge_table = edge_table();
 
-    //bindings between edge and vertex for each cell
-    let mut cache : Vec< Option< HashMap<usize, Vector3<f32>  > > > = vec![None;size * size * size];
+    //cells proven to be fully inside or outside, they are not sampled
+    let culled = cull_cells(f, offset, a, [size, size, size]);
+
+    //the cell and its vertices, each with the edges bound to it
+    let load_cell = |x : usize, y : usize, z : usize| -> (Cell<f32>, Vec<(Vec<usize>, Vector3<f32>)>){
+        if let Some(value) = culled.as_ref().and_then(|cells| cells[z * size * size + y * size + x]){
+            let config = if value < 0.0 {255} else {0};
+            return (Cell{densities : [value;8], hermite_data : HashMap::new(), config}, Vec::new());
+        }
 
-    let mut load_cell = |grid : &mut HermiteGrid<f32>, x : usize, y : usize, z : usize, cache : &mut Vec<Option<HashMap<usize,Vector3<f32>>>>|{
         let cell_min = offset + Vector3::new(x as f32 * a, y as f32 * a, z as f32 * a);
-        let bounds = grid.cube(x,y,z,offset);
+        let bounds = Square3{center : cell_min + Vector3::new(a, a, a) * 0.5, extent : a / 2.0}; //same as `HermiteGrid::cube`
+        let mut points = [cell_min;8];
+        for i in 0..8{
+            points[i] = cell_min + corners[i] * a;
+        }
         let mut densities = [0.0;8];
+        f.density_batch(&points, &mut densities);
+
         let mut config = 0;
         let mut corner_vertex_count = 0;
         for i in 0..8{
-            let p = cell_min + corners[i] * a;
-            densities[i] = f(p);
             if densities[i] < 0.0{
                 config |= 1 << i;
                 corner_vertex_count += 1;
@@ -653,11 +664,7 @@ pub fn construct_grid<'f>(f : &'f DenFn3<f32>, offset : Vector3<f32>, a : f32, s
 
         let mut hermite_data = HashMap::new();
 
-        let mut cached_cell = HashMap::new();
-
-        if vertices.len() >= 1 { //render cells that contain more than 1 vertex
-            //add_square3_bounds_color(render_debug_lines, bounds.clone(), Vector3::new(1.0,0.0,0.0));
-        }
+        let mut cell_vertices = Vec::with_capacity(vertices.len());
 
         for vertex in vertices{
 
@@ -674,7 +681,7 @@ pub fn construct_grid<'f>(f : &'f DenFn3<f32>, offset : Vector3<f32>, a : f32, s
 
                 let intersection = sample_surface_intersection(&edge, accuracy, f);
                 
-                let normal = sample_normal(&intersection, 1e-5, f);
+                let normal = sample_normal(&intersection, a / 100.0, f); //forward differences with 1e-5 were too noisy in f32
                 
 
                 let plane = Plane{point : intersection, normal};
@@ -749,85 +756,84 @@ pub fn construct_grid<'f>(f : &'f DenFn3<f32>, offset : Vector3<f32>, a : f32, s
 
             //add_square3_bounds_color(render_debug_lines, Square3{center : minimizer, extent : 0.075/4.0}, Vector3::new(1.0,1.0,0.0));
 
-            for edge_id in &vertex { 
-                cached_cell.insert(edge_id.clone(), minimizer);//duplicates are not possible
-            }
+            cell_vertices.push((vertex, minimizer));
 
         }
 
-        let t = z * size * size + y * size + x;
-        cache[t] = Some(cached_cell);
-
-        let cell = Cell{densities, hermite_data, config};
-
-        grid.set(x, y, z, cell);
-
+        (Cell{densities, hermite_data, config}, cell_vertices)
     };
 
-    let mut load_cell_cached = |grid : &mut HermiteGrid<f32>, x : usize, y : usize, z : usize, cache : &mut Vec<Option<HashMap<usize,Vector3<f32>>>>|{
-        let t = z * size * size + y * size + x;
-
-        let mut load = false;
-        {
-            let cached = cache[t].as_ref();
-            match cached{
-                None => load = true,
-                Some(_) => (),
+    //all cells are loaded up front in parallel z-slabs, each slab writes only its own part of the grid so the result
+    //does not depend on the number of threads
+    let mut grid = HermiteGrid::new(a, size);
+    let mut cell_vertices : Vec<Vec<(Vec<usize>, Vector3<f32>)>> = vec![Vec::new();size * size * size];
+    grid.cells[..size * size * size].par_chunks_mut(size * size).zip(cell_vertices.par_chunks_mut(size * size)).enumerate().for_each(|(z, (cells, cell_vertices))|{
+        for y in 0..size{
+            for x in 0..size{
+                let (cell, vertices) = load_cell(x, y, z);
+                cells[y * size + x] = Some(cell);
+                cell_vertices[y * size + x] = vertices;
             }
         }
+    });
+
+    //one mesh vertex per cell vertex, in cell order, its normal is the average of the normals on its edges
+    let mut mesh = Mesh::new();
+    let mut cache : Vec<HashMap<usize, u32>> = vec![HashMap::new();size * size * size];
+    let mut debug = if debug {Some(DebugGeometry::new())} else {None};
+    for t in 0..size * size * size{
+        if let Some(ref mut debug) = debug{
+            if cell_vertices[t].len() > 1{
+                let cell_min = offset + Vector3::new((t % size) as f32 * a, (t / size % size) as f32 * a, (t / (size * size)) as f32 * a);
+                debug.add_square3_bounds(Square3{center : cell_min + Vector3::new(a, a, a) * 0.5, extent : a / 2.0}, Vector3::new(1.0, 0.0, 0.0));
+            }
+        }
+        for &(ref edges, minimizer) in &cell_vertices[t]{
+            let hermite_data = &grid.cells[t].as_ref().unwrap().hermite_data;
+            let sum = edges.iter().fold(Vector3::new(0.0, 0.0, 0.0), |sum, edge_id| sum + hermite_data.get(edge_id).unwrap().normal);
+            let len = sum.norm();
+            let index = mesh.push_vertex(minimizer, if len > 0.0 {sum / len} else {sum}, 0);
+            for edge_id in edges{
+                cache[t].insert(edge_id.clone(), index);//duplicates are not possible
+            }
+        }
+    }
+    let load_cell_cached = |x : usize, y : usize, z : usize| {&cache[z * size * size + y * size + x]};
 
-        let cached = {
-            if load{
-                load_cell(grid, x, y, z, cache);
-            };
-            cache[t].as_ref().unwrap()
-        };
-
-        cached.clone() //TODO cloning is bad here
-        
-    };
-
-    let mut grid = HermiteGrid::new(a, size);
     for y in 0..size-1{
         for z in 0..size-1{
             for x in 0..size-1{
 
-                let cell = load_cell_cached(&mut grid, x,y,z, &mut cache);
-                for (edge_id, minimizer) in &cell{
-                    let t = minimizer.clone();
-                    match edge_id.clone(){ //TODO add triangle vertex only once, use indexing + culling (decide direction by normal)
+                let cell = load_cell_cached(x,y,z);
+                let mut cell : Vec<(usize, u32)> = cell.iter().map(|(&edge_id, &index)| (edge_id, index)).collect();
+                cell.sort_by_key(|&(edge_id, _)| edge_id); //HashMap iteration order differs between runs, keep the triangle order reproducible
+                for &(edge_id, t) in &cell{
+                    match edge_id.clone(){ //TODO culling (decide direction by normal)
                         5 => {
-                            let r = load_cell_cached(&mut grid, x+1,y,z, &mut cache).get(&7).unwrap().clone();
-                            let ru = load_cell_cached(&mut grid, x+1,y+1,z, &mut cache).get(&3).unwrap().clone();
-                            let u = load_cell_cached(&mut grid, x,y+1,z, &mut cache).get(&1).unwrap().clone();
-                            let normal = &grid.get(x,y,z).hermite_data.get(&5).unwrap().normal;
-                            add_triangle_color_normal(render_tr_light, &Triangle3{p1 : t, p2 : r, p3 : ru}, &Vector3::new(1.0, 1.0, 0.0), normal);
-                            add_triangle_color_normal(render_tr_light, &Triangle3{p1 : t, p2 : ru, p3 : u}, &Vector3::new(1.0, 1.0, 0.0), normal);
+                            let r = load_cell_cached(x+1,y,z).get(&7).unwrap().clone();
+                            let ru = load_cell_cached(x+1,y+1,z).get(&3).unwrap().clone();
+                            let u = load_cell_cached(x,y+1,z).get(&1).unwrap().clone();
+                            mesh.push_quad(t, r, ru, u);
                         },
                         6 => {
-                            let f = load_cell_cached(&mut grid, x,y,z+1, &mut cache).get(&4).unwrap().clone();
-                            let fu_ = load_cell_cached(&mut grid, x,y+1,z+1, &mut cache);
+                            let f = load_cell_cached(x,y,z+1).get(&4).unwrap().clone();
+                            let fu_ = load_cell_cached(x,y+1,z+1);
                             // let config = grid.get(x,y+1,z+1).config;
                             // println!("vertex count {:?}, edges: {:?}, map : {:?}", vertex_num_table()[config], edge_table[config], &fu_);
                             let fu = fu_.get(&0).unwrap().clone();
-                            let u = load_cell_cached(&mut grid, x,y+1,z, &mut cache).get(&2).unwrap().clone();
-                            let normal = &grid.get(x,y,z).hermite_data.get(&6).unwrap().normal;
-                            add_triangle_color_normal(render_tr_light, &Triangle3{p1 : t, p2 : f, p3 : fu}, &Vector3::new(1.0, 1.0, 0.0), normal);
-                            add_triangle_color_normal(render_tr_light, &Triangle3{p1 : t, p2 : fu, p3 : u}, &Vector3::new(1.0, 1.0, 0.0), normal);
+                            let u = load_cell_cached(x,y+1,z).get(&2).unwrap().clone();
+                            mesh.push_quad(t, f, fu, u);
                         },
                         10 => {
-                            let r_ = load_cell_cached(&mut grid, x+1,y,z, &mut cache);
+                            let r_ = load_cell_cached(x+1,y,z);
                             //let config = grid.get(x+1,y,z).config;
                             //let config_this = grid.get(x,y,z).config;
                             //println!("this {:?}, errored {:?}", &edge_table[config_this], &edge_table[config]);
                             let r = r_.get(&11).unwrap().clone();
-                            let rf = load_cell_cached(&mut grid, x+1,y,z+1, &mut cache).get(&8).unwrap().clone();
-                            let f = load_cell_cached(&mut grid, x,y,z+1, &mut cache).get(&9).unwrap().clone();
-
-                            let normal = &grid.get(x,y,z).hermite_data.get(&10).unwrap().normal;
-
-                            add_triangle_color_normal(render_tr_light, &Triangle3{p1 : t, p2 : rf, p3 : r}, &Vector3::new(1.0, 1.0, 0.0), normal);
-                            add_triangle_color_normal(render_tr_light, &Triangle3{p1 : t, p2 : f, p3 : rf}, &Vector3::new(1.0, 1.0, 0.0), normal);
+                            let rf = load_cell_cached(x+1,y,z+1).get(&8).unwrap().clone();
+                            let f = load_cell_cached(x,y,z+1).get(&9).unwrap().clone();
+                            mesh.push_triangle(t, rf, r);
+                            mesh.push_triangle(t, f, rf);
                         },
                         _ => ()
                     }
@@ -837,5 +843,5 @@ pub fn construct_grid<'f>(f : &'f DenFn3<f32>, offset : Vector3<f32>, a : f32, s
         }
     }
 
-    grid
+    (grid, mesh, debug)
 }
\ No newline at end of file
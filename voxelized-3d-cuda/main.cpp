@@ -658,7 +658,8 @@ extern "C" Node **simplify_grid_recursively(uint size, float3 center, float exte
 
 }
 
-extern "C" void init_noise(){
+extern "C" void init_noise(int seed){
+    noise.SetSeed(seed);
     noise.SetNoiseType(FastNoise::Perlin);
     noise.SetFrequency(4);
 }